@@ -0,0 +1,62 @@
+use bril_rs::pipeline::{Pipeline, PASSES};
+use bril_rs::{bril, cfg};
+
+use clap::Parser;
+use std::io::{BufReader, Read};
+
+/// runs a pass pipeline over a bril program, e.g. `bril-opt 'ssa,licm,from-ssa,dce(global)'`
+#[derive(Parser)]
+struct Args {
+    /// comma separated passes applied from left to right, the program is only reformatted if empty
+    #[arg(default_value_t = Pipeline::default())]
+    pipeline: Pipeline,
+    #[arg(short)]
+    f: Option<String>,
+    /// write bril text instead of json, input of either format is accepted
+    #[arg(long)]
+    text: bool,
+    /// list the available passes with their options and exit
+    #[arg(long)]
+    list_passes: bool,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    if args.list_passes {
+        for (name, options, about) in PASSES {
+            let pass = if options.is_empty() {
+                name.to_string()
+            } else {
+                format!("{name}({options})")
+            };
+            println!("{pass:<34}{about}");
+        }
+        return Ok(());
+    }
+    let mut reader: Box<dyn Read> = if let Some(ref f) = args.f {
+        Box::new(BufReader::new(std::fs::File::open(f)?))
+    } else {
+        Box::new(BufReader::new(std::io::stdin()))
+    };
+    let mut buf = String::new();
+    assert!(reader.read_to_string(&mut buf)? > 0);
+
+    let bril_prog = if buf.trim_start().starts_with('{') {
+        bril::Prog::from_json(&buf)?
+    } else {
+        bril::Prog::from_text(&buf).unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            std::process::exit(2)
+        })
+    };
+    let prog = args
+        .pipeline
+        .run(cfg::ProgCfgs::from_bril_prog(&bril_prog))
+        .into_bril_prog();
+    if args.text {
+        print!("{prog}");
+    } else {
+        println!("{}", serde_json::to_string(&prog).unwrap());
+    }
+    Ok(())
+}
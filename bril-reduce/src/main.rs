@@ -0,0 +1,79 @@
+use bril_rs::bril::{self, LabelOrInst, Prog};
+use bril_rs::reduce::Reducer;
+
+use clap::Parser;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// shrinks a bril program in json as long as the interestingness command keeps exiting with 0,
+/// the reduced program is printed to stdout
+#[derive(Parser)]
+struct Args {
+    #[arg(short)]
+    f: Option<String>,
+    /// give up after testing this many candidates
+    #[arg(long)]
+    max_tests: Option<usize>,
+    /// each candidate is passed on stdin, its path is also exported as `BRIL_PROG`
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let mut reader: Box<dyn Read> = if let Some(ref f) = args.f {
+        Box::new(BufReader::new(std::fs::File::open(f)?))
+    } else {
+        Box::new(BufReader::new(std::io::stdin()))
+    };
+    let mut buf = String::new();
+    assert!(reader.read_to_string(&mut buf)? > 0);
+    let bril_prog = bril::Prog::from_json(&buf).unwrap();
+
+    let candidate_path =
+        std::env::temp_dir().join(format!("bril-reduce-{}.json", std::process::id()));
+    let interesting =
+        |prog: &Prog| is_interesting(&args.command, &candidate_path, prog).unwrap_or(false);
+    if !interesting(&bril_prog) {
+        eprintln!("error: the input program is not interesting to begin with");
+        std::process::exit(1);
+    }
+    let mut reducer = Reducer::new(interesting);
+    if let Some(max_tests) = args.max_tests {
+        reducer = reducer.max_tests(max_tests);
+    }
+    let reduced = reducer.reduce(bril_prog.clone());
+    let _ = std::fs::remove_file(&candidate_path);
+    eprintln!(
+        "{} -> {} instructions after {} tests",
+        num_instrs(&bril_prog),
+        num_instrs(&reduced),
+        reducer.num_tests()
+    );
+    println!("{}", serde_json::to_string(&reduced).unwrap());
+    Ok(())
+}
+
+fn is_interesting(command: &[String], candidate_path: &Path, prog: &Prog) -> std::io::Result<bool> {
+    let json = serde_json::to_string(prog)?;
+    std::fs::write(candidate_path, &json)?;
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .env("BRIL_PROG", candidate_path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // the command may exit without reading all of its input
+    let _ = child.stdin.take().unwrap().write_all(json.as_bytes());
+    Ok(child.wait()?.success())
+}
+
+fn num_instrs(prog: &Prog) -> usize {
+    prog.functions
+        .iter()
+        .flat_map(|func| &func.instrs)
+        .filter(|inst| matches!(inst, LabelOrInst::Inst { .. }))
+        .count()
+}
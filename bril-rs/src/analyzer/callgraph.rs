@@ -0,0 +1,113 @@
+use crate::bril::LabelOrInst;
+use crate::cfg::{Cfg, ProgCfgs};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// static call graph of the program, function are identified by name
+#[derive(Default, Debug)]
+pub struct CallGraph {
+    pub funcs: Vec<String>,
+    pub callees: HashMap<String, BTreeSet<String>>,
+    pub callers: HashMap<String, BTreeSet<String>>,
+    /// number of call sites targeting a function across the program
+    pub num_call_sites: HashMap<String, usize>,
+}
+
+impl CallGraph {
+    pub fn from_prog(prog: &ProgCfgs) -> Self {
+        let mut call_graph = Self {
+            funcs: prog.0.iter().map(|cfg| cfg.func_ctx.name.clone()).collect(),
+            ..Default::default()
+        };
+        for cfg in &prog.0 {
+            let caller = &cfg.func_ctx.name;
+            call_graph.callees.entry(caller.clone()).or_default();
+            for callee in call_sites(cfg) {
+                call_graph
+                    .callees
+                    .entry(caller.clone())
+                    .or_default()
+                    .insert(callee.clone());
+                call_graph
+                    .callers
+                    .entry(callee.clone())
+                    .or_default()
+                    .insert(caller.clone());
+                *call_graph.num_call_sites.entry(callee).or_default() += 1;
+            }
+        }
+        call_graph
+    }
+
+    pub fn callees_of(&self, func: &str) -> impl Iterator<Item = &String> {
+        self.callees.get(func).into_iter().flatten()
+    }
+
+    pub fn callers_of(&self, func: &str) -> impl Iterator<Item = &String> {
+        self.callers.get(func).into_iter().flatten()
+    }
+
+    /// all functions transitively called from `func`, including itself
+    pub fn reachable_from(&self, func: &str) -> HashSet<String> {
+        let mut reachable = HashSet::new();
+        let mut stack = vec![func.to_string()];
+        while let Some(cur) = stack.pop() {
+            if reachable.insert(cur.clone()) {
+                stack.extend(self.callees_of(&cur).cloned());
+            }
+        }
+        reachable
+    }
+
+    /// whether `func` lies on a cycle of the call graph, directly or mutually recursive
+    pub fn is_recursive(&self, func: &str) -> bool {
+        self.callees_of(func)
+            .any(|callee| self.reachable_from(callee).contains(func))
+    }
+
+    /// post order of the call graph starting from every function, callees come before callers
+    /// unless they are on the same cycle
+    pub fn bottom_up_order(&self) -> Vec<String> {
+        fn dfs(
+            call_graph: &CallGraph,
+            cur: &str,
+            vis: &mut HashSet<String>,
+            order: &mut Vec<String>,
+        ) {
+            if !vis.insert(cur.to_string()) {
+                return;
+            }
+            for callee in call_graph.callees_of(cur) {
+                dfs(call_graph, callee, vis, order);
+            }
+            order.push(cur.to_string());
+        }
+        let (mut vis, mut order) = (HashSet::new(), vec![]);
+        for func in &self.funcs {
+            dfs(self, func, &mut vis, &mut order);
+        }
+        order
+    }
+}
+
+/// name of the callee at each call site of the function, in layout order
+pub fn call_sites(cfg: &Cfg) -> Vec<String> {
+    cfg.nodes
+        .iter()
+        .flat_map(|node| {
+            node.lock()
+                .unwrap()
+                .blk
+                .instrs
+                .iter()
+                .filter_map(|inst| match inst {
+                    LabelOrInst::Inst {
+                        op,
+                        funcs: Some(funcs),
+                        ..
+                    } if op == "call" => funcs.first().cloned(),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
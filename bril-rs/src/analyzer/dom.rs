@@ -3,7 +3,7 @@ use crate::graphviz_prelude::*;
 use crate::optim::dflow::WorkListAlgo;
 use rand::prelude::*;
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex, Weak};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
 
 pub type DomNodeRef = Arc<Mutex<DomNode>>;
 pub type WeakDomNodeRef = Weak<Mutex<DomNode>>;
@@ -117,12 +117,74 @@ fn draw_cfg_with_dom(cfg: &Cfg, highlight_frontier_for: Option<&NodeRef>) -> Gra
 pub struct DomTree {
     pub root: WeakDomNodeRef,
     pub nodes: Vec<DomNodeRef>,
+    /// preorder and postorder number of every block in the tree,
+    /// `a` dominates `b` iff `b` is numbered within the interval of `a`
+    numbering: HashMap<NodePtr, (usize, usize)>,
+    preorder: Vec<NodeRef>,
+    postorder: Vec<NodeRef>,
+    idoms: HashMap<NodePtr, NodeRef>,
+    children: HashMap<NodePtr, Vec<NodeRef>>,
+    frontiers: OnceLock<HashMap<NodePtr, HashSet<NodePtr>>>,
 }
 
 impl DomTree {
+    /// immediate dominators by the iterative algo of Cooper, Harvey and Kennedy,
+    /// blocks unreachable from the entry are kept in `nodes` but left out of the tree
     pub fn from_cfg(cfg: &Cfg) -> Self {
+        let idx_of: HashMap<NodePtr, usize> = cfg
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (Arc::as_ptr(node), i))
+            .collect();
+        let succs: Vec<Vec<usize>> = cfg
+            .nodes
+            .iter()
+            .map(|node| {
+                node.lock()
+                    .unwrap()
+                    .successors
+                    .iter()
+                    .map(|succ| *idx_of.get(&Weak::as_ptr(succ)).unwrap())
+                    .collect()
+            })
+            .collect();
+        let root = *idx_of.get(&Weak::as_ptr(&cfg.root)).unwrap();
+        let idoms = immediate_dominators(&succs, root);
+
+        let nodes: Vec<DomNodeRef> = cfg
+            .nodes
+            .iter()
+            .map(|node| Arc::new(Mutex::new(DomNode::from_cfg_node(node))))
+            .collect();
+        for (i, idom) in idoms.into_iter().enumerate() {
+            if let Some(idom) = idom.filter(|&idom| idom != i) {
+                nodes[idom]
+                    .lock()
+                    .unwrap()
+                    .successors
+                    .push(Arc::downgrade(&nodes[i]));
+            }
+        }
+        Self::with_links(Arc::downgrade(&nodes[root]), nodes)
+    }
+
+    /// solves dominator sets as a dataflow problem, much slower than `from_cfg`
+    /// but straightforward enough to cross-check it
+    pub fn from_cfg_by_dom_sets(cfg: &Cfg) -> Self {
         let mut build_ctx = DomTreeConstCtx::new(cfg);
-        let ret = build_ctx.execute(cfg);
+        let mut ret = build_ctx.execute(cfg);
+        // unreachable blocks are dominated by every block, they are left out of the tree
+        let mut reachable = HashSet::from([Weak::as_ptr(&cfg.root)]);
+        let mut stack = vec![Weak::upgrade(&cfg.root).unwrap()];
+        while let Some(node) = stack.pop() {
+            for succ in &node.lock().unwrap().successors {
+                if reachable.insert(Weak::as_ptr(succ)) {
+                    stack.push(Weak::upgrade(succ).unwrap());
+                }
+            }
+        }
+        ret.retain(|ptr, _| reachable.contains(ptr));
         let ptr2node: HashMap<_, _> = cfg
             .nodes
             .iter()
@@ -133,10 +195,7 @@ impl DomTree {
                 )
             })
             .collect();
-        let dom_tree = Self {
-            root: Arc::downgrade(ptr2node.get(&Weak::as_ptr(&cfg.root)).unwrap()),
-            nodes: ptr2node.values().cloned().collect(),
-        };
+        let root = Arc::downgrade(ptr2node.get(&Weak::as_ptr(&cfg.root)).unwrap());
         for doms in ret.values() {
             let mut doms: Vec<_> = doms.iter().collect();
             doms.sort_by_key(|ptr| ret.get(ptr).unwrap().len());
@@ -144,11 +203,95 @@ impl DomTree {
                 .iter()
                 .map(|ptr| Arc::downgrade(ptr2node.get(ptr).unwrap()))
                 .collect();
-            dom_tree.construct_path(doms);
+            Self::construct_path(&root, doms);
+        }
+        Self::with_links(root, ptr2node.into_values().collect())
+    }
+
+    /// `nodes` already linked to their children, numbers the tree reachable from `root`
+    pub(crate) fn with_links(root: WeakDomNodeRef, nodes: Vec<DomNodeRef>) -> Self {
+        let mut dom_tree = Self {
+            root,
+            nodes,
+            numbering: HashMap::new(),
+            preorder: vec![],
+            postorder: vec![],
+            idoms: HashMap::new(),
+            children: HashMap::new(),
+            frontiers: OnceLock::new(),
+        };
+        let mut stack = vec![(Weak::upgrade(&dom_tree.root).unwrap(), 0)];
+        while let Some((dom_node, i)) = stack.pop() {
+            let dom_node_lock = dom_node.lock().unwrap();
+            let cfg_node = Arc::clone(&dom_node_lock.cfg_node);
+            let cfg_ptr = Arc::as_ptr(&cfg_node);
+            if i == 0 {
+                dom_tree
+                    .numbering
+                    .insert(cfg_ptr, (dom_tree.preorder.len(), 0));
+                dom_tree.preorder.push(Arc::clone(&cfg_node));
+                let children: Vec<NodeRef> = dom_node_lock
+                    .successors
+                    .iter()
+                    .map(|child| {
+                        Arc::clone(&Weak::upgrade(child).unwrap().lock().unwrap().cfg_node)
+                    })
+                    .collect();
+                for child in &children {
+                    dom_tree
+                        .idoms
+                        .insert(Arc::as_ptr(child), Arc::clone(&cfg_node));
+                }
+                dom_tree.children.insert(cfg_ptr, children);
+            }
+            if let Some(child) = dom_node_lock.successors.get(i) {
+                let child = Weak::upgrade(child).unwrap();
+                drop(dom_node_lock);
+                stack.push((dom_node, i + 1));
+                stack.push((child, 0));
+            } else {
+                dom_tree.numbering.get_mut(&cfg_ptr).unwrap().1 = dom_tree.postorder.len();
+                dom_tree.postorder.push(cfg_node);
+            }
         }
         dom_tree
     }
 
+    /// immediate dominator of every block in the tree, the root maps to itself
+    pub fn immediate_dominators(&self) -> HashMap<NodePtr, NodePtr> {
+        let root_ptr = Arc::as_ptr(&self.preorder[0]);
+        self.preorder
+            .iter()
+            .map(|node| {
+                let node_ptr = Arc::as_ptr(node);
+                (
+                    node_ptr,
+                    self.idom(node_ptr)
+                        .map_or(root_ptr, |idom| Arc::as_ptr(&idom)),
+                )
+            })
+            .collect()
+    }
+
+    /// `None` for the root and blocks left out of the tree
+    pub fn idom(&self, node: NodePtr) -> Option<NodeRef> {
+        self.idoms.get(&node).cloned()
+    }
+
+    pub fn children(&self, node: NodePtr) -> &[NodeRef] {
+        self.children.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    /// parents come before their children
+    pub fn preorder(&self) -> impl Iterator<Item = &NodeRef> {
+        self.preorder.iter()
+    }
+
+    /// children come before their parents
+    pub fn postorder(&self) -> impl Iterator<Item = &NodeRef> {
+        self.postorder.iter()
+    }
+
     pub fn port_as_dot_with_scope<F: Fn(usize) -> String>(&self, scoper: F) -> Graph {
         // no back edge
         struct Visitor {
@@ -197,9 +340,9 @@ impl DomTree {
         g
     }
 
-    fn construct_path(&self, path: Vec<WeakDomNodeRef>) {
-        let mut cur_node = Weak::upgrade(&self.root).unwrap();
-        debug_assert_eq!(Arc::as_ptr(&cur_node), Weak::as_ptr(&self.root));
+    fn construct_path(root: &WeakDomNodeRef, path: Vec<WeakDomNodeRef>) {
+        let mut cur_node = Weak::upgrade(root).unwrap();
+        debug_assert_eq!(Arc::as_ptr(&cur_node), Weak::as_ptr(root));
         for node in path.iter().skip(1) {
             {
                 let mut cur_lock = cur_node.lock().unwrap();
@@ -262,30 +405,212 @@ impl DomTree {
         frontier
     }
 
-    pub fn is_dominator_of(&self, a: NodePtr, b: NodePtr) -> bool {
-        fn in_substree(dom_node: &DomNodeRef, query: NodePtr) -> bool {
-            let dom_node_lock = dom_node.lock().unwrap();
-            let cfg_ptr = Arc::as_ptr(&dom_node_lock.cfg_node);
-            if cfg_ptr == query {
-                return true;
-            }
-            for child in &dom_node_lock.successors {
-                if in_substree(&child.upgrade().unwrap(), query) {
-                    return true;
-                }
+    /// keeps the tree valid after the edge `from -> to` is added to `cfg`
+    pub fn insert_edge(&mut self, cfg: &Cfg, from: NodePtr, to: NodePtr) {
+        if !self.numbering.contains_key(&from) {
+            // the new edge is never taken
+        } else if !self.numbering.contains_key(&to) {
+            // blocks may become reachable from anywhere, no region to confine the update to
+            *self = Self::from_cfg(cfg);
+        } else {
+            let region_root = self.nearest_common_dominator(from, to);
+            self.recompute_below(region_root, &[]);
+        }
+        self.debug_assert_matches_recompute(cfg);
+    }
+
+    /// keeps the tree valid after the edge `from -> to` is removed from `cfg`
+    pub fn delete_edge(&mut self, cfg: &Cfg, from: NodePtr, to: NodePtr) {
+        if self.numbering.contains_key(&from) && self.numbering.contains_key(&to) {
+            let region_root = self.nearest_common_dominator(from, to);
+            self.recompute_below(region_root, &[]);
+            if !self.numbering.contains_key(&to) {
+                // blocks outside the region lose every path running through `to`
+                *self = Self::from_cfg(cfg);
             }
-            false
         }
-        let starting_node = self
+        self.debug_assert_matches_recompute(cfg);
+    }
+
+    /// keeps the tree valid after `node` is added to `cfg` and linked to its preds and succs,
+    /// edges from its preds to its succs may be removed in the same edit, e.g. for a preheader
+    pub fn insert_block(&mut self, cfg: &Cfg, node: &NodeRef) {
+        let node_ptr = Arc::as_ptr(node);
+        let (preds, succs): (Vec<NodePtr>, Vec<NodePtr>) = {
+            let node_lock = node.lock().unwrap();
+            (
+                node_lock.predecessors.iter().map(Weak::as_ptr).collect(),
+                node_lock.successors.iter().map(Weak::as_ptr).collect(),
+            )
+        };
+        self.nodes
+            .push(Arc::new(Mutex::new(DomNode::from_cfg_node(node))));
+        let mut endpoints = preds
+            .into_iter()
+            .filter(|pred| self.numbering.contains_key(pred))
+            .peekable();
+        if endpoints.peek().is_none() {
+            // unreachable, left out of the tree
+            *self = Self::with_links(self.root.clone(), std::mem::take(&mut self.nodes));
+        } else if succs
+            .iter()
+            .any(|succ| *succ != node_ptr && !self.numbering.contains_key(succ))
+        {
+            *self = Self::from_cfg(cfg);
+        } else {
+            let endpoints: Vec<NodePtr> = endpoints
+                .chain(succs.into_iter().filter(|succ| *succ != node_ptr))
+                .collect();
+            let region_root = endpoints[1..].iter().fold(endpoints[0], |acc, &endpoint| {
+                self.nearest_common_dominator(acc, endpoint)
+            });
+            self.recompute_below(region_root, &[Arc::clone(node)]);
+        }
+        self.debug_assert_matches_recompute(cfg);
+    }
+
+    fn nearest_common_dominator(&self, a: NodePtr, b: NodePtr) -> NodePtr {
+        let mut cur = a;
+        while !self.is_dominator_of(cur, b) {
+            cur = Arc::as_ptr(&self.idom(cur).unwrap());
+        }
+        cur
+    }
+
+    /// an edit confined to the subtree of `region_root` can only change idoms inside it,
+    /// every path reaching the subtree enters through `region_root`, so solving the
+    /// subgraph induced by the subtree (plus the inserted `extra` blocks) is enough
+    fn recompute_below(&mut self, region_root: NodePtr, extra: &[NodeRef]) {
+        let mut region = vec![];
+        let mut stack = vec![self
+            .preorder
+            .iter()
+            .find(|node| Arc::as_ptr(node) == region_root)
+            .cloned()
+            .unwrap()];
+        while let Some(node) = stack.pop() {
+            stack.extend(self.children(Arc::as_ptr(&node)).iter().rev().cloned());
+            region.push(node);
+        }
+        region.extend(extra.iter().cloned());
+        let idx_of: HashMap<NodePtr, usize> = region
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (Arc::as_ptr(node), i))
+            .collect();
+        let succs: Vec<Vec<usize>> = region
+            .iter()
+            .map(|node| {
+                node.lock()
+                    .unwrap()
+                    .successors
+                    .iter()
+                    .filter_map(|succ| idx_of.get(&Weak::as_ptr(succ)).copied())
+                    .collect()
+            })
+            .collect();
+        let idoms = immediate_dominators(&succs, 0);
+
+        let dom_node_of: HashMap<NodePtr, DomNodeRef> = self
             .nodes
             .iter()
-            .find(|dom_node| {
-                let cfg_node = &dom_node.lock().unwrap().cfg_node;
-                Arc::as_ptr(cfg_node) == a
+            .map(|dom_node| {
+                let cfg_ptr = Arc::as_ptr(&dom_node.lock().unwrap().cfg_node);
+                (cfg_ptr, Arc::clone(dom_node))
             })
-            .cloned()
-            .unwrap();
-        in_substree(&starting_node, b)
+            .collect();
+        let region_dom_nodes: Vec<&DomNodeRef> = region
+            .iter()
+            .map(|node| dom_node_of.get(&Arc::as_ptr(node)).unwrap())
+            .collect();
+        for dom_node in &region_dom_nodes {
+            dom_node.lock().unwrap().successors.clear();
+        }
+        for (i, idom) in idoms.into_iter().enumerate() {
+            if let Some(idom) = idom.filter(|&idom| idom != i) {
+                region_dom_nodes[idom]
+                    .lock()
+                    .unwrap()
+                    .successors
+                    .push(Arc::downgrade(region_dom_nodes[i]));
+            }
+        }
+        *self = Self::with_links(self.root.clone(), std::mem::take(&mut self.nodes));
+    }
+
+    fn debug_assert_matches_recompute(&self, cfg: &Cfg) {
+        debug_assert_eq!(
+            self.immediate_dominators(),
+            Self::from_cfg(cfg).immediate_dominators(),
+            "incrementally maintained dom tree diverges from recompute"
+        );
+    }
+
+    /// reflexive, false if either block is left out of the tree
+    pub fn is_dominator_of(&self, a: NodePtr, b: NodePtr) -> bool {
+        match (self.numbering.get(&a), self.numbering.get(&b)) {
+            (Some((a_pre, a_post)), Some((b_pre, b_post))) => a_pre <= b_pre && b_post <= a_post,
+            _ => false,
+        }
+    }
+
+    pub fn strictly_dominates(&self, a: NodePtr, b: NodePtr) -> bool {
+        a != b && self.is_dominator_of(a, b)
+    }
+
+    /// dominance frontier of every block in the tree following cfg edges, computed once
+    ///
+    /// only meaningful for forward dominators, not the tree inside a `PostDomTree`
+    pub fn dominance_frontiers(&self) -> &HashMap<NodePtr, HashSet<NodePtr>> {
+        self.frontiers
+            .get_or_init(|| self.compute_dominance_frontiers())
+    }
+
+    fn compute_dominance_frontiers(&self) -> HashMap<NodePtr, HashSet<NodePtr>> {
+        let mut frontiers: HashMap<NodePtr, HashSet<NodePtr>> = self
+            .preorder
+            .iter()
+            .map(|node| (Arc::as_ptr(node), HashSet::new()))
+            .collect();
+        for node in &self.preorder {
+            let node_ptr = Arc::as_ptr(node);
+            let preds: Vec<NodePtr> = node
+                .lock()
+                .unwrap()
+                .predecessors
+                .iter()
+                .map(Weak::as_ptr)
+                .collect();
+            let idom = self.idom(node_ptr).map(|idom| Arc::as_ptr(&idom));
+            for pred in preds {
+                // walk up from the pred until reaching the block dominating both
+                let mut runner = Some(pred).filter(|pred| self.numbering.contains_key(pred));
+                while let Some(cur) = runner.filter(|&cur| Some(cur) != idom) {
+                    frontiers.get_mut(&cur).unwrap().insert(node_ptr);
+                    runner = self.idom(cur).map(|idom| Arc::as_ptr(&idom));
+                }
+            }
+        }
+        frontiers
+    }
+
+    /// closure of dominance frontiers starting from `blocks`, where phis of a var
+    /// defined in `blocks` belong
+    pub fn iterated_dominance_frontier(
+        &self,
+        blocks: impl IntoIterator<Item = NodePtr>,
+    ) -> HashSet<NodePtr> {
+        let frontiers = self.dominance_frontiers();
+        let mut idf = HashSet::new();
+        let mut worklist: Vec<NodePtr> = blocks.into_iter().collect();
+        while let Some(block) = worklist.pop() {
+            for &frontier in frontiers.get(&block).into_iter().flatten() {
+                if idf.insert(frontier) {
+                    worklist.push(frontier);
+                }
+            }
+        }
+        idf
     }
 }
 
@@ -343,3 +668,69 @@ impl DomTreeConstCtx {
         }
     }
 }
+
+/// iterative immediate dominator algo (Cooper, Harvey, Kennedy) over reverse postorder,
+/// `idoms[root] == Some(root)` and `None` for blocks unreachable from `root`
+pub(crate) fn immediate_dominators(succs: &[Vec<usize>], root: usize) -> Vec<Option<usize>> {
+    let mut preds = vec![vec![]; succs.len()];
+    for (blk, blk_succs) in succs.iter().enumerate() {
+        for &succ in blk_succs {
+            preds[succ].push(blk);
+        }
+    }
+    let mut postorder = vec![];
+    let mut visited = vec![false; succs.len()];
+    let mut stack = vec![(root, 0)];
+    visited[root] = true;
+    while let Some((blk, i)) = stack.pop() {
+        if let Some(&succ) = succs[blk].get(i) {
+            stack.push((blk, i + 1));
+            if !visited[succ] {
+                visited[succ] = true;
+                stack.push((succ, 0));
+            }
+        } else {
+            postorder.push(blk);
+        }
+    }
+    let mut po_num = vec![usize::MAX; succs.len()];
+    for (i, &blk) in postorder.iter().enumerate() {
+        po_num[blk] = i;
+    }
+
+    // walk both fingers up the tree until they meet
+    let intersect = |idoms: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while po_num[a] < po_num[b] {
+                a = idoms[a].unwrap();
+            }
+            while po_num[b] < po_num[a] {
+                b = idoms[b].unwrap();
+            }
+        }
+        a
+    };
+    let mut idoms = vec![None; succs.len()];
+    idoms[root] = Some(root);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &blk in postorder.iter().rev().filter(|&&blk| blk != root) {
+            let mut new_idom = None;
+            for &pred in &preds[blk] {
+                if idoms[pred].is_none() {
+                    continue;
+                }
+                new_idom = Some(match new_idom {
+                    None => pred,
+                    Some(cur) => intersect(&idoms, pred, cur),
+                });
+            }
+            if new_idom.is_some() && idoms[blk] != new_idom {
+                idoms[blk] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idoms
+}
@@ -0,0 +1,179 @@
+//! interprocedural effect analysis
+//!
+//! Each function is classified by the most severe effect it may have when called
+//!     Pure < ReadOnly < Effectful
+//! Effects are propagated from callees to callers until a fixpoint is reached, functions start
+//! as `Pure` so that recursion alone never makes a function effectful.
+//!
+//! Apart from its effect, a function is partial if a call to it may trap or never return,
+//! e.g. it divides, loads, loops or recurses, or calls a partial function. Dropping or numbering
+//! such a call would turn a crash or a hang into a normal exit, so neither happens.
+use crate::analyzer::{callgraph::CallGraph, scc::find_sccs};
+use crate::bril::LabelOrInst;
+use crate::cfg::{Cfg, ProgCfgs};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Effect {
+    /// result only depends on args, no observable side effect
+    Pure,
+    /// may read memory, but never writes it or does io
+    ReadOnly,
+    /// may print, write or allocate memory
+    Effectful,
+}
+
+impl Effect {
+    /// whether a call can be dropped when its result is never used
+    pub fn is_removable(self) -> bool {
+        self != Effect::Effectful
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct EffectSummary {
+    pub effects: HashMap<String, Effect>,
+    /// functions whose calls may trap or never return
+    pub partial: HashSet<String>,
+}
+
+impl EffectSummary {
+    pub fn from_prog(prog: &ProgCfgs) -> Self {
+        let mut summary = Self {
+            effects: prog
+                .0
+                .iter()
+                .map(|cfg| (cfg.func_ctx.name.clone(), Effect::Pure))
+                .collect(),
+            partial: HashSet::new(),
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for cfg in &prog.0 {
+                let effect = summary.effect_of_body(cfg);
+                let entry = summary.effects.get_mut(&cfg.func_ctx.name).unwrap();
+                if effect > *entry {
+                    *entry = effect;
+                    changed = true;
+                }
+            }
+        }
+
+        let call_graph = CallGraph::from_prog(prog);
+        let locally_partial: HashSet<&String> = prog
+            .0
+            .iter()
+            .filter(|cfg| may_trap_or_loop(cfg) || call_graph.is_recursive(&cfg.func_ctx.name))
+            .map(|cfg| &cfg.func_ctx.name)
+            .collect();
+        summary.partial = prog
+            .0
+            .iter()
+            .map(|cfg| &cfg.func_ctx.name)
+            .filter(|func| {
+                call_graph
+                    .reachable_from(func)
+                    .iter()
+                    .any(|callee| locally_partial.contains(callee))
+            })
+            .cloned()
+            .collect();
+        summary
+    }
+
+    /// functions outside of the summary are assumed to be effectful
+    pub fn effect_of(&self, func: &str) -> Effect {
+        self.effects.get(func).copied().unwrap_or(Effect::Effectful)
+    }
+
+    pub fn is_pure(&self, func: &str) -> bool {
+        self.effect_of(func) == Effect::Pure
+    }
+
+    /// functions outside of the summary may do anything
+    pub fn is_partial(&self, func: &str) -> bool {
+        !self.effects.contains_key(func) || self.partial.contains(func)
+    }
+
+    /// calls to pure functions that always return may be numbered, the same args give the same
+    /// result
+    pub fn pure_total_funcs(&self) -> HashSet<String> {
+        self.effects
+            .keys()
+            .filter(|func| self.is_pure(func) && !self.is_partial(func))
+            .cloned()
+            .collect()
+    }
+
+    /// whether `inst` can be dropped when its result is never used
+    pub fn is_removable(&self, inst: &LabelOrInst) -> bool {
+        match inst {
+            LabelOrInst::Inst {
+                op,
+                funcs: Some(funcs),
+                ..
+            } if op == "call" => {
+                !self.is_partial(&funcs[0]) && self.effect_of_inst(inst).is_removable()
+            }
+            _ => self.effect_of_inst(inst).is_removable(),
+        }
+    }
+
+    /// effect of a single instruction given current summary of callees
+    pub fn effect_of_inst(&self, inst: &LabelOrInst) -> Effect {
+        match inst {
+            LabelOrInst::Inst {
+                op,
+                funcs: Some(funcs),
+                ..
+            } if op == "call" => self.effect_of(&funcs[0]),
+            LabelOrInst::Inst { op, .. } => match op.as_str() {
+                "print" | "store" | "alloc" | "free" | "speculate" | "commit" | "guard" => {
+                    Effect::Effectful
+                }
+                "load" => Effect::ReadOnly,
+                _ => Effect::Pure,
+            },
+            LabelOrInst::Label { .. } => Effect::Pure,
+        }
+    }
+
+    fn effect_of_body(&self, cfg: &Cfg) -> Effect {
+        cfg.nodes
+            .iter()
+            .flat_map(|node| {
+                node.lock()
+                    .unwrap()
+                    .blk
+                    .instrs
+                    .iter()
+                    .map(|inst| self.effect_of_inst(inst))
+                    .collect::<Vec<_>>()
+            })
+            .max()
+            .unwrap_or(Effect::Pure)
+    }
+}
+
+/// `div` may divide by zero and `load` may be out of bounds, a cycle of blocks may loop forever
+fn may_trap_or_loop(cfg: &Cfg) -> bool {
+    let traps = cfg.nodes.iter().any(|node| {
+        node.lock().unwrap().blk.instrs.iter().any(|inst| {
+            matches!(inst, LabelOrInst::Inst { op, .. } if matches!(op.as_str(), "div" | "load"))
+        })
+    });
+    let self_loop = cfg.nodes.iter().any(|node| {
+        let node_lock = node.lock().unwrap();
+        node_lock
+            .successors
+            .iter()
+            .any(|succ| Weak::as_ptr(succ) == Arc::as_ptr(node))
+    });
+    traps
+        || self_loop
+        || find_sccs(cfg)
+            .iter()
+            .any(|comp| comp.lock().unwrap().size() > 1)
+}
@@ -1,8 +1,12 @@
+pub mod callgraph;
 pub mod dom;
+pub mod effect;
+pub mod postdom;
 pub mod scc;
+pub mod verify;
 use crate::bril::{LabelOrInst, ValueLit};
 use crate::cfg::{Cfg, NodePtr, NodeRef};
-use crate::optim::{self, dflow::WorkListAlgo};
+use crate::optim::{self, dce::eval_const_expr, dflow::WorkListAlgo};
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
 use std::sync::{Arc, Weak};
@@ -98,16 +102,15 @@ impl WorkListAlgo for GlobalConstPropAlgo {
                         .iter()
                         .all(|arg| matches!(var_tys.get(arg), Some(VarType::Const(_))));
                     let var_ty = if can_be_folded {
-                        let const_args: Vec<_> =
-                            args.iter().map(|arg| var_tys.get(arg).unwrap()).collect();
-                        match op.as_str() {
-                            "add" => crate::const_eval!(const_args[0], +, const_args[1]),
-                            "sub" => crate::const_eval!(const_args[0], -, const_args[1]),
-                            "mul" => crate::const_eval!(const_args[0], *, const_args[1]),
-                            "div" => crate::const_eval!(const_args[0], /, const_args[1]),
-                            "id" => *const_args[0],
-                            _ => unreachable!(),
-                        }
+                        let const_args: Vec<_> = args
+                            .iter()
+                            .map(|arg| match var_tys.get(arg) {
+                                Some(VarType::Const(lit)) => *lit,
+                                _ => unreachable!(),
+                            })
+                            .collect();
+                        // results out of range and division by zero are left to runtime
+                        eval_const_expr(op, &const_args).map_or(VarType::NonConst, VarType::Const)
                     } else if args
                         .iter()
                         .any(|arg| matches!(var_tys.get(arg), Some(VarType::NonConst)))
@@ -117,6 +120,9 @@ impl WorkListAlgo for GlobalConstPropAlgo {
                         VarType::Unknown
                     };
                     var_tys.insert(dest.clone(), var_ty);
+                } else if op != "const" {
+                    // any other def, e.g. call or comparison, overwrites previous const binding
+                    var_tys.insert(dest.clone(), VarType::NonConst);
                 }
             }
         }
@@ -124,7 +130,6 @@ impl WorkListAlgo for GlobalConstPropAlgo {
     }
 
     fn merge(out_flows: Vec<Self::OutFlowType>) -> Self::InFlowType {
-        dbg!(&out_flows);
         out_flows
             .into_iter()
             .reduce(|mut out_a, out_b| {
@@ -149,16 +154,6 @@ impl WorkListAlgo for GlobalConstPropAlgo {
     }
 }
 
-#[macro_export]
-macro_rules! const_eval {
-    ($lhs: expr, $op: tt, $rhs: expr) => {
-        match ($lhs, $rhs) {
-            (VarType::Const(ValueLit::Int(lhs)), VarType::Const(ValueLit::Int(rhs))) => VarType::Const(ValueLit::Int(lhs $op rhs)),
-            _ => unreachable!()
-        }
-    }
-}
-
 pub fn uninitialized_var_detection(cfg: &Cfg) -> Result<(), String> {
     let mut algo = UninitDetectAlgo::new(cfg);
     algo.execute(cfg);
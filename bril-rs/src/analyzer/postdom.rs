@@ -0,0 +1,288 @@
+//! post-dominance and control dependence
+//!
+//! `A` post-dominates `B` if every path from `B` to the end of the function goes through `A`.
+//! A function may `ret` at several places, a virtual exit placed after every returning block,
+//! and after the last block if control falls off the end, roots the post-dominator tree.
+//!
+//! `B` is control dependent on `A` if `A` has an edge to a block post-dominated by `B` while
+//! `B` does not strictly post-dominate `A`, i.e. the branch of `A` decides whether `B` runs.
+//! Blocks `A` controls are exactly those having `A` in their post-dominance frontier.
+//!     .entry:                    control dependence:
+//!       br c .then .join;          .then  <- .entry
+//!     .then:                       .entry, .join depend on nothing
+//!       ...
+//!     .join:
+//!       ret;
+//!
+//! Blocks that never reach the exit, e.g. stuck in an infinite loop, have no post-dominator
+//! and are left out of the tree.
+use crate::analyzer::dom::{immediate_dominators, DomNode, DomTree};
+use crate::bril::LabelOrInst;
+use crate::cfg::{BasicBlock, Cfg, CfgNode, NodePtr, NodeRef, ProgCfgs};
+use crate::graphviz_prelude::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+pub fn draw_prog_with_post_dom_as_dot_string(prog: &ProgCfgs) -> String {
+    draw_prog_with(prog, draw_cfg_with_post_dom)
+}
+
+pub fn draw_cfg_with_post_dom_as_dot_string(cfg: &Cfg) -> String {
+    draw_cfg_with_post_dom(cfg).print(&mut PrinterContext::default())
+}
+
+pub fn draw_prog_with_cdg_as_dot_string(prog: &ProgCfgs) -> String {
+    draw_prog_with(prog, draw_cfg_with_cdg)
+}
+
+pub fn draw_cfg_with_cdg_as_dot_string(cfg: &Cfg) -> String {
+    draw_cfg_with_cdg(cfg).print(&mut PrinterContext::default())
+}
+
+fn draw_prog_with(prog: &ProgCfgs, draw_cfg: fn(&Cfg) -> Graph) -> String {
+    let mut g = graph!(di id!("Prog"));
+    for (i, cfg) in prog.0.iter().enumerate() {
+        if let Graph::DiGraph { stmts, .. } = draw_cfg(cfg) {
+            g.add_stmt(stmt!(Subgraph {
+                id: id!(format!("cluster_{i}")),
+                stmts
+            }));
+        }
+    }
+    g.print(&mut PrinterContext::default())
+}
+
+fn draw_cfg_with_post_dom(cfg: &Cfg) -> Graph {
+    let post_dom_tree = PostDomTree::from_cfg(cfg);
+    let func_name = &cfg.func_ctx.name;
+    let Graph::DiGraph { stmts, .. } = post_dom_tree
+        .tree
+        .port_as_dot_with_scope(|i| format!("{func_name}_post_dom_{i}"))
+    else {
+        unreachable!()
+    };
+    draw_cfg_alongside(cfg, "POST_DOM", stmts)
+}
+
+fn draw_cfg_with_cdg(cfg: &Cfg) -> Graph {
+    let cdg = ControlDependenceGraph::from_post_dom_tree(&PostDomTree::from_cfg(cfg));
+    let func_name = &cfg.func_ctx.name;
+    let scoper = |i: usize| format!("{func_name}_cdg_{i}");
+    let idx_of: HashMap<NodePtr, usize> = cfg
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (Arc::as_ptr(node), i))
+        .collect();
+    let mut stmts = vec![];
+    for (i, node) in cfg.nodes.iter().enumerate() {
+        let caption = node.lock().unwrap().caption();
+        let node_id = scoper(i);
+        stmts.push(stmt!(
+            node!(node_id; attr!("label", &caption), attr!("shape", "box"))
+        ));
+    }
+    for (i, node) in cfg.nodes.iter().enumerate() {
+        for controller in cdg.controllers_of(Arc::as_ptr(node)) {
+            let (u_id, v_id) = (scoper(*idx_of.get(controller).unwrap()), scoper(i));
+            stmts.push(stmt!(edge!(node_id!(u_id) => node_id!(v_id))));
+        }
+    }
+    draw_cfg_alongside(cfg, "CDG", stmts)
+}
+
+/// cfg and the graph derived from it side by side in two clusters
+fn draw_cfg_alongside(cfg: &Cfg, name: &str, mut derived_stmts: Vec<Stmt>) -> Graph {
+    let func_name = &cfg.func_ctx.name;
+    let mut g = graph!(di id!(func_name));
+    let scope = format!(r#""@{}""#, &func_name);
+    let mut g_stmts = vec![
+        stmt!(attr!("label", scope)),
+        stmt!(attr!("labelloc", "t")),
+        stmt!(attr!("labeljust", "l")),
+        stmt!(attr!("style", "solid")),
+        stmt!(attr!("fontcolor", "brown")),
+    ];
+    let derived_caption = format!(r#""{name}""#);
+    derived_stmts.extend(vec![
+        stmt!(attr!("style", "solid")),
+        stmt!(attr!("color", "blue")),
+        stmt!(attr!("label", derived_caption)),
+    ]);
+    g_stmts.push(stmt!(Subgraph {
+        id: id!(format!("cluster_{}_{func_name}", name.to_lowercase())),
+        stmts: derived_stmts
+    }));
+
+    let (dot_nodes_map, dot_edges) = cfg.nodes_and_edges_in_dot(|i| format!("{func_name}_cfg_{i}"));
+    let mut cfg_stmts = vec![];
+    for dot_node in dot_nodes_map.into_values() {
+        cfg_stmts.push(stmt!(dot_node));
+    }
+    for dot_edge in dot_edges {
+        cfg_stmts.push(stmt!(dot_edge));
+    }
+    cfg_stmts.push(stmt!(attr!("label", "CFG")));
+    g_stmts.push(stmt!(Subgraph {
+        id: id!(format!("cluster_cfg_{func_name}")),
+        stmts: cfg_stmts
+    }));
+
+    for stmt in g_stmts {
+        g.add_stmt(stmt);
+    }
+    g
+}
+
+pub struct PostDomTree {
+    /// dominator tree of the reversed cfg, rooted at the virtual exit
+    pub tree: DomTree,
+    /// virtual exit, not part of the cfg
+    pub exit: NodeRef,
+    /// successors where every returning block leads to the virtual exit
+    successors: HashMap<NodePtr, Vec<NodePtr>>,
+}
+
+impl PostDomTree {
+    pub fn from_cfg(cfg: &Cfg) -> Self {
+        let exit = Arc::new(Mutex::new(CfgNode {
+            label: Some("virtual.exit".to_string()),
+            blk: BasicBlock {
+                label: None,
+                instrs: vec![],
+            },
+            successors: vec![],
+            predecessors: vec![],
+        }));
+        let mut ptrs: Vec<NodePtr> = cfg.nodes.iter().map(Arc::as_ptr).collect();
+        ptrs.push(Arc::as_ptr(&exit));
+        let exit_idx = cfg.nodes.len();
+        let idx_of: HashMap<NodePtr, usize> =
+            ptrs.iter().enumerate().map(|(i, ptr)| (*ptr, i)).collect();
+
+        let mut succs = vec![];
+        for node in &cfg.nodes {
+            let node_lock = node.lock().unwrap();
+            let returns = matches!(
+                node_lock.blk.instrs.last(),
+                Some(LabelOrInst::Inst { op, .. }) if op == "ret"
+            );
+            // the fallthrough edge out of a returning block is never taken
+            succs.push(if returns || node_lock.successors.is_empty() {
+                vec![exit_idx]
+            } else {
+                node_lock
+                    .successors
+                    .iter()
+                    .map(|succ| *idx_of.get(&Weak::as_ptr(succ)).unwrap())
+                    .collect()
+            });
+        }
+        succs.push(vec![]);
+        let mut reversed = vec![vec![]; succs.len()];
+        for (blk, blk_succs) in succs.iter().enumerate() {
+            for &succ in blk_succs {
+                reversed[succ].push(blk);
+            }
+        }
+        let ipdom_idx = immediate_dominators(&reversed, exit_idx);
+
+        let dom_nodes: Vec<_> = cfg
+            .nodes
+            .iter()
+            .chain(std::iter::once(&exit))
+            .map(|node| {
+                Arc::new(Mutex::new(DomNode {
+                    cfg_node: Arc::clone(node),
+                    successors: vec![],
+                }))
+            })
+            .collect();
+        for (i, ipdom) in ipdom_idx.iter().enumerate() {
+            if let Some(ipdom) = ipdom.filter(|_| i != exit_idx) {
+                dom_nodes[ipdom]
+                    .lock()
+                    .unwrap()
+                    .successors
+                    .push(Arc::downgrade(&dom_nodes[i]));
+            }
+        }
+        let tree = DomTree::with_links(
+            Arc::downgrade(&dom_nodes[exit_idx]),
+            dom_nodes
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| ipdom_idx[*i].is_some())
+                .map(|(_, dom_node)| dom_node)
+                .collect(),
+        );
+        let successors = succs
+            .into_iter()
+            .enumerate()
+            .map(|(i, succs)| (ptrs[i], succs.into_iter().map(|succ| ptrs[succ]).collect()))
+            .collect();
+        Self {
+            tree,
+            exit,
+            successors,
+        }
+    }
+
+    #[inline]
+    pub fn exit_ptr(&self) -> NodePtr {
+        Arc::as_ptr(&self.exit)
+    }
+
+    /// `None` for the virtual exit and blocks never reaching it
+    pub fn immediate_post_dominator(&self, node: NodePtr) -> Option<NodePtr> {
+        self.tree.idom(node).map(|ipdom| Arc::as_ptr(&ipdom))
+    }
+
+    /// reflexive, every block reaching the exit is post-dominated by itself
+    pub fn is_post_dominator_of(&self, a: NodePtr, b: NodePtr) -> bool {
+        self.tree.is_dominator_of(a, b)
+    }
+}
+
+pub struct ControlDependenceGraph {
+    /// block -> blocks whose branch decides whether it runs
+    controllers: HashMap<NodePtr, Vec<NodePtr>>,
+}
+
+impl ControlDependenceGraph {
+    /// for every edge `A -> S`, blocks from `S` up the post-dominator tree until the
+    /// immediate post-dominator of `A` are control dependent on `A`
+    pub fn from_post_dom_tree(post_dom_tree: &PostDomTree) -> Self {
+        let mut controllers: HashMap<NodePtr, Vec<NodePtr>> = HashMap::new();
+        for (&node, succs) in &post_dom_tree.successors {
+            let Some(ipdom) = post_dom_tree.immediate_post_dominator(node) else {
+                continue;
+            };
+            for &succ in succs {
+                let mut runner = Some(succ);
+                while let Some(cur) = runner.filter(|&cur| cur != ipdom) {
+                    let cur_controllers = controllers.entry(cur).or_default();
+                    if !cur_controllers.contains(&node) {
+                        cur_controllers.push(node);
+                    }
+                    runner = post_dom_tree.immediate_post_dominator(cur);
+                }
+            }
+        }
+        Self { controllers }
+    }
+
+    /// also known as the post-dominance frontier of the block
+    pub fn controllers_of(&self, node: NodePtr) -> &[NodePtr] {
+        self.controllers.get(&node).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn dependents_of(&self, node: NodePtr) -> Vec<NodePtr> {
+        self.controllers
+            .iter()
+            .filter(|(_, controllers)| controllers.contains(&node))
+            .map(|(dependent, _)| *dependent)
+            .collect()
+    }
+}
@@ -0,0 +1,299 @@
+//! ssa verifier for cfg in set/get form
+//!
+//! checks that
+//!   - every var has exactly one static def, params count as defs at the entry
+//!   - every use is dominated by the def of the var
+//!   - every `get` is preceded by a `set` of its shadow on all incoming paths
+//!
+//! Unreachable blocks are ignored, they are never executed and have no dominator.
+use crate::analyzer::dom::DomTree;
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::optim::dflow::WorkListAlgo;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Weak};
+
+/// location of an instr, blocks without label are named after their position in the cfg
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstLoc {
+    pub blk: String,
+    pub idx: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SsaViolation {
+    MultipleDefs {
+        var: String,
+        defs: Vec<InstLoc>,
+    },
+    UseWithoutDef {
+        var: String,
+        at: InstLoc,
+    },
+    UseNotDominated {
+        var: String,
+        def: InstLoc,
+        at: InstLoc,
+    },
+    GetWithoutSet {
+        var: String,
+        at: InstLoc,
+    },
+    SetWithoutGet {
+        var: String,
+        at: InstLoc,
+    },
+}
+
+impl fmt::Display for SsaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MultipleDefs { var, defs } => {
+                let defs: Vec<_> = defs.iter().map(ToString::to_string).collect();
+                write!(
+                    f,
+                    "`{var}` defined {} times: {}",
+                    defs.len(),
+                    defs.join(", ")
+                )
+            }
+            Self::UseWithoutDef { var, at } => write!(f, "`{var}` used at {at} is never defined"),
+            Self::UseNotDominated { var, def, at } => {
+                write!(
+                    f,
+                    "`{var}` used at {at} is not dominated by its def at {def}"
+                )
+            }
+            Self::GetWithoutSet { var, at } => {
+                write!(f, "`{var}` got at {at} is not set on every incoming path")
+            }
+            Self::SetWithoutGet { var, at } => write!(f, "`{var}` set at {at} is never got"),
+        }
+    }
+}
+
+impl fmt::Display for InstLoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}]", self.blk, self.idx)
+    }
+}
+
+pub fn verify_ssa(cfg: &Cfg) -> Result<(), Vec<SsaViolation>> {
+    let reachable = reachable_nodes(cfg);
+    if reachable.len() != cfg.nodes.len() {
+        let mut pruned = Cfg {
+            root: Default::default(),
+            nodes: Cfg::clone_nodes(&cfg.nodes, &HashMap::new()),
+            func_ctx: cfg.func_ctx.clone(),
+        };
+        pruned.relink();
+        pruned.prune_unreachable();
+        return verify_ssa(&pruned);
+    }
+
+    let blk_names: HashMap<NodePtr, String> = cfg
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let name = node
+                .lock()
+                .unwrap()
+                .label
+                .as_ref()
+                .map_or(format!("blk.{i}"), |label| format!(".{label}"));
+            (Arc::as_ptr(node), name)
+        })
+        .collect();
+    let loc = |node_ptr: NodePtr, idx: usize| InstLoc {
+        blk: blk_names.get(&node_ptr).unwrap().clone(),
+        idx,
+    };
+
+    let mut violations = vec![];
+    let root_ptr = Weak::as_ptr(&cfg.root);
+    // var -> (block, idx), params are defined before the first instr of the entry
+    let mut defs: HashMap<String, Vec<(NodePtr, Option<usize>)>> = HashMap::new();
+    for arg in cfg.func_ctx.args_name().unwrap_or_default() {
+        defs.entry(arg).or_default().push((root_ptr, None));
+    }
+    let (mut gets, mut sets) = (HashSet::new(), vec![]);
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        for (idx, inst) in node.lock().unwrap().blk.instrs.iter().enumerate() {
+            let LabelOrInst::Inst { op, dest, args, .. } = inst else {
+                continue;
+            };
+            if let Some(dest) = dest {
+                defs.entry(dest.clone())
+                    .or_default()
+                    .push((node_ptr, Some(idx)));
+                if op == "get" {
+                    gets.insert(dest.clone());
+                }
+            }
+            if op == "set" {
+                sets.push((args.as_ref().unwrap()[0].clone(), loc(node_ptr, idx)));
+            }
+        }
+    }
+    let def_loc = |&(node_ptr, idx): &(NodePtr, Option<usize>)| match idx {
+        Some(idx) => loc(node_ptr, idx),
+        None => InstLoc {
+            blk: "params".to_string(),
+            idx: 0,
+        },
+    };
+    let mut multiply_defined: Vec<_> = defs.iter().filter(|(_, defs)| defs.len() > 1).collect();
+    multiply_defined.sort_by_key(|(var, _)| *var);
+    for (var, var_defs) in multiply_defined {
+        violations.push(SsaViolation::MultipleDefs {
+            var: var.clone(),
+            defs: var_defs.iter().map(def_loc).collect(),
+        });
+    }
+
+    let dom_tree = DomTree::from_cfg(cfg);
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        for (idx, inst) in node.lock().unwrap().blk.instrs.iter().enumerate() {
+            let LabelOrInst::Inst {
+                op,
+                args: Some(args),
+                ..
+            } = inst
+            else {
+                continue;
+            };
+            // the shadow written by `set` is not a use
+            let uses = if op == "set" { &args[1..] } else { &args[..] };
+            for var in uses {
+                let Some(&def) = defs.get(var).and_then(|defs| defs.first()) else {
+                    violations.push(SsaViolation::UseWithoutDef {
+                        var: var.clone(),
+                        at: loc(node_ptr, idx),
+                    });
+                    continue;
+                };
+                let dominated = match def {
+                    (def_ptr, Some(def_idx)) if def_ptr == node_ptr => def_idx < idx,
+                    (def_ptr, _) => dom_tree.is_dominator_of(def_ptr, node_ptr),
+                };
+                if !dominated {
+                    violations.push(SsaViolation::UseNotDominated {
+                        var: var.clone(),
+                        def: def_loc(&def),
+                        at: loc(node_ptr, idx),
+                    });
+                }
+            }
+        }
+    }
+
+    for (var, at) in sets {
+        if !gets.contains(&var) {
+            violations.push(SsaViolation::SetWithoutGet { var, at });
+        }
+    }
+    let must_set = MustBeSetAnalysis { root_ptr }.execute(cfg);
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        let node_lock = node.lock().unwrap();
+        let mut set_so_far: HashSet<String> = MustBeSetAnalysis::merge(
+            node_lock
+                .predecessors
+                .iter()
+                .map(|pred| must_set.get(&Weak::as_ptr(pred)).unwrap().clone())
+                .collect(),
+        );
+        if node_ptr == root_ptr {
+            set_so_far.clear();
+        }
+        for (idx, inst) in node_lock.blk.instrs.iter().enumerate() {
+            match inst {
+                LabelOrInst::Inst {
+                    op,
+                    dest: Some(dest),
+                    ..
+                } if op == "get" && !set_so_far.contains(dest) => {
+                    violations.push(SsaViolation::GetWithoutSet {
+                        var: dest.clone(),
+                        at: loc(node_ptr, idx),
+                    });
+                }
+                LabelOrInst::Inst {
+                    op,
+                    args: Some(args),
+                    ..
+                } if op == "set" => {
+                    set_so_far.insert(args[0].clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+fn reachable_nodes(cfg: &Cfg) -> HashSet<NodePtr> {
+    let mut reachable = HashSet::new();
+    let mut stack = vec![cfg.root.upgrade().unwrap()];
+    while let Some(node) = stack.pop() {
+        if reachable.insert(Arc::as_ptr(&node)) {
+            stack.extend(
+                node.lock()
+                    .unwrap()
+                    .successors
+                    .iter()
+                    .map(|succ| succ.upgrade().unwrap()),
+            );
+        }
+    }
+    reachable
+}
+
+/// shadows set on every path from the entry to the end of each block
+struct MustBeSetAnalysis {
+    root_ptr: NodePtr,
+}
+
+impl WorkListAlgo for MustBeSetAnalysis {
+    const FORWARD_PASS: bool = true;
+    type InFlowType = HashSet<String>;
+    type OutFlowType = HashSet<String>;
+
+    fn transfer(&mut self, node: &NodeRef, in_flow: Option<Self::InFlowType>) -> Self::OutFlowType {
+        let mut out_flow = if Arc::as_ptr(node) == self.root_ptr {
+            HashSet::new()
+        } else {
+            in_flow.unwrap_or_default()
+        };
+        for inst in &node.lock().unwrap().blk.instrs {
+            if let LabelOrInst::Inst {
+                op,
+                args: Some(args),
+                ..
+            } = inst
+            {
+                if op == "set" {
+                    out_flow.insert(args[0].clone());
+                }
+            }
+        }
+        out_flow
+    }
+
+    fn merge(out_flows: Vec<Self::OutFlowType>) -> Self::InFlowType {
+        out_flows
+            .into_iter()
+            .reduce(|a, b| a.intersection(&b).cloned().collect())
+            .unwrap_or_default()
+    }
+}
@@ -1,5 +1,8 @@
 //! Bril syntax reference spec: https://capra.cs.cornell.edu/bril/lang/syntax.html
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+pub mod text;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Prog {
@@ -11,6 +14,11 @@ impl Prog {
     pub fn from_json(json: &str) -> serde_json::Result<Self> {
         serde_json::from_str(json)
     }
+
+    /// parses the text format printed by `Display`, e.g. `@main { v: int = const 1; print v; }`
+    pub fn from_text(src: &str) -> Result<Self, text::ParseError> {
+        text::parse_prog(src)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -18,7 +26,12 @@ pub struct Function {
     pub name: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub args: Option<Vec<Arg>>,
-    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    #[serde(
+        default,
+        rename = "type",
+        skip_serializing_if = "Option::is_none",
+        with = "ty_serde::option"
+    )]
     pub ty: Option<String>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub instrs: Vec<LabelOrInst>,
@@ -27,7 +40,7 @@ pub struct Function {
 #[derive(Serialize, Deserialize, Debug, Clone, Hash)]
 pub struct Arg {
     pub name: String,
-    #[serde(rename = "type")]
+    #[serde(rename = "type", with = "ty_serde")]
     pub ty: String,
 }
 
@@ -46,7 +59,12 @@ pub enum LabelOrInst {
         #[serde(default, skip_serializing_if = "Option::is_none")]
         dest: Option<String>,
 
-        #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+        #[serde(
+            default,
+            rename = "type",
+            skip_serializing_if = "Option::is_none",
+            with = "ty_serde::option"
+        )]
         ty: Option<String>,
 
         #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -63,3 +81,142 @@ pub enum LabelOrInst {
         label: String,
     },
 }
+
+impl fmt::Display for ValueLit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueLit::Int(v) => write!(f, "{v}"),
+            ValueLit::Bool(b) => write!(f, "{b}"),
+        }
+    }
+}
+
+/// prints in bril text syntax, which [`Prog::from_text`] reads back
+impl fmt::Display for Prog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for func in &self.functions {
+            writeln!(f, "{func}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@{}", self.name)?;
+        if let Some(args) = self.args.as_ref().filter(|args| !args.is_empty()) {
+            let args: Vec<String> = args
+                .iter()
+                .map(|arg| format!("{}: {}", arg.name, arg.ty))
+                .collect();
+            write!(f, "({})", args.join(", "))?;
+        }
+        if let Some(ty) = &self.ty {
+            write!(f, ": {ty}")?;
+        }
+        writeln!(f, " {{")?;
+        for inst in &self.instrs {
+            match inst {
+                LabelOrInst::Label { .. } => writeln!(f, "{inst}")?,
+                LabelOrInst::Inst { .. } => writeln!(f, "  {inst}")?,
+            }
+        }
+        write!(f, "}}")
+    }
+}
+
+/// prints in bril text syntax, e.g. `v0: int = add a b;` or `.loop:`
+impl fmt::Display for LabelOrInst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelOrInst::Label { label } => write!(f, ".{label}:"),
+            LabelOrInst::Inst {
+                op,
+                dest,
+                ty,
+                args,
+                funcs,
+                labels,
+                value,
+            } => {
+                if let Some(dest) = dest {
+                    write!(f, "{dest}")?;
+                    if let Some(ty) = ty {
+                        write!(f, ": {ty}")?;
+                    }
+                    write!(f, " = ")?;
+                }
+                write!(f, "{op}")?;
+                if let Some(value) = value {
+                    write!(f, " {value}")?;
+                }
+                for func in funcs.iter().flatten() {
+                    write!(f, " @{func}")?;
+                }
+                for arg in args.iter().flatten() {
+                    write!(f, " {arg}")?;
+                }
+                for label in labels.iter().flatten() {
+                    write!(f, " .{label}")?;
+                }
+                write!(f, ";")
+            }
+        }
+    }
+}
+
+/// types are kept as strings, pointer types such as `{"ptr": {"ptr": "int"}}` read as `ptr<ptr<int>>`
+mod ty_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Prim(String),
+        Ptr { ptr: Box<Repr> },
+    }
+
+    impl Repr {
+        fn from_ty(ty: &str) -> Self {
+            match ty.strip_prefix("ptr<").and_then(|ty| ty.strip_suffix('>')) {
+                Some(pointee) => Repr::Ptr {
+                    ptr: Box::new(Repr::from_ty(pointee)),
+                },
+                None => Repr::Prim(ty.to_string()),
+            }
+        }
+
+        fn into_ty(self) -> String {
+            match self {
+                Repr::Prim(ty) => ty,
+                Repr::Ptr { ptr } => format!("ptr<{}>", ptr.into_ty()),
+            }
+        }
+    }
+
+    pub fn serialize<S: Serializer>(ty: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        Repr::from_ty(ty).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Repr::deserialize(deserializer).map(Repr::into_ty)
+    }
+
+    pub mod option {
+        use super::Repr;
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            ty: &Option<String>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            ty.as_deref().map(Repr::from_ty).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<String>, D::Error> {
+            Option::<Repr>::deserialize(deserializer).map(|ty| ty.map(Repr::into_ty))
+        }
+    }
+}
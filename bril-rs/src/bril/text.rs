@@ -0,0 +1,230 @@
+//! parser of the bril text format
+//!
+//! @add(a: int, b: int): int {        <- args and return type are optional
+//!   s: int = add a b;                <- value op, dest and type come first
+//!   br c .then .else;                <- `@` marks funcs and `.` labels among the operands
+//! .then:
+//!   ret s;                           # comments run till the end of the line
+//! }
+//!
+//! Literals of `const` are ints and bools, as in `ValueLit`.
+use super::{Arg, Function, LabelOrInst, Prog, ValueLit};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+const PUNCTS: &str = "{}();:=,<>";
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+pub(super) fn parse_prog(src: &str) -> Result<Prog, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(src),
+        pos: 0,
+    };
+    let mut functions = vec![];
+    while parser.peek().is_some() {
+        functions.push(parser.function()?);
+    }
+    Ok(Prog { functions })
+}
+
+fn tokenize(src: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    for (i, line) in src.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        let mut rest = code.trim_start();
+        while let Some(first) = rest.chars().next() {
+            let len = if PUNCTS.contains(first) {
+                1
+            } else {
+                rest.find(|c: char| c.is_whitespace() || PUNCTS.contains(c))
+                    .unwrap_or(rest.len())
+            };
+            tokens.push(Token {
+                text: &rest[..len],
+                line: i + 1,
+            });
+            rest = rest[len..].trim_start();
+        }
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|token| token.text)
+    }
+
+    fn error(&self, msg: String) -> ParseError {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |token| token.line);
+        ParseError { line, msg }
+    }
+
+    fn found(&self) -> String {
+        self.peek()
+            .map_or("end of input".to_string(), |text| format!("`{text}`"))
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        let matched = self.peek() == Some(punct);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ParseError> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{punct}`, found {}", self.found())))
+        }
+    }
+
+    /// anything but punctuation, e.g. a var, an op, `@func` or `.label`
+    fn word(&mut self) -> Result<&'a str, ParseError> {
+        match self.peek() {
+            Some(text) if !PUNCTS.contains(text) => {
+                self.pos += 1;
+                Ok(text)
+            }
+            _ => Err(self.error(format!("expected a name, found {}", self.found()))),
+        }
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        let name = self.word()?;
+        let Some(name) = name.strip_prefix('@') else {
+            return Err(self.error(format!("expected a function, found `{name}`")));
+        };
+        let mut args = vec![];
+        if self.eat("(") && !self.eat(")") {
+            loop {
+                let name = self.word()?.to_string();
+                self.expect(":")?;
+                args.push(Arg {
+                    name,
+                    ty: self.ty()?,
+                });
+                if !self.eat(",") {
+                    self.expect(")")?;
+                    break;
+                }
+            }
+        }
+        let ty = if self.eat(":") {
+            Some(self.ty()?)
+        } else {
+            None
+        };
+        self.expect("{")?;
+        let mut instrs = vec![];
+        while !self.eat("}") {
+            instrs.push(self.label_or_inst()?);
+        }
+        Ok(Function {
+            name: name.to_string(),
+            args: (!args.is_empty()).then_some(args),
+            ty,
+            instrs,
+        })
+    }
+
+    fn ty(&mut self) -> Result<String, ParseError> {
+        let ty = self.word()?;
+        if self.eat("<") {
+            let pointee = self.ty()?;
+            self.expect(">")?;
+            Ok(format!("{ty}<{pointee}>"))
+        } else {
+            Ok(ty.to_string())
+        }
+    }
+
+    fn label_or_inst(&mut self) -> Result<LabelOrInst, ParseError> {
+        let first = self.word()?;
+        if !self.eat(":") {
+            return self.operands(first.to_string(), None, None);
+        }
+        if let Some(label) = first.strip_prefix('.') {
+            return Ok(LabelOrInst::Label {
+                label: label.to_string(),
+            });
+        }
+        let ty = self.ty()?;
+        self.expect("=")?;
+        let op = self.word()?.to_string();
+        self.operands(op, Some(first.to_string()), Some(ty))
+    }
+
+    /// operands of `op` up to the closing `;`
+    fn operands(
+        &mut self,
+        op: String,
+        dest: Option<String>,
+        ty: Option<String>,
+    ) -> Result<LabelOrInst, ParseError> {
+        let (mut args, mut funcs, mut labels) = (vec![], vec![], vec![]);
+        while !self.eat(";") {
+            let operand = self.word()?;
+            if let Some(func) = operand.strip_prefix('@') {
+                funcs.push(func.to_string());
+            } else if let Some(label) = operand.strip_prefix('.') {
+                labels.push(label.to_string());
+            } else {
+                args.push(operand.to_string());
+            }
+        }
+        let value = if op == "const" {
+            let [lit] = args.as_slice() else {
+                return Err(self.error("`const` takes a single literal".to_string()));
+            };
+            let value = match lit.as_str() {
+                "true" => ValueLit::Bool(true),
+                "false" => ValueLit::Bool(false),
+                _ => ValueLit::Int(lit.parse().map_err(|_| {
+                    self.error(format!("`{lit}` is neither an int nor a bool literal"))
+                })?),
+            };
+            args.clear();
+            Some(value)
+        } else {
+            None
+        };
+        let non_empty = |v: Vec<String>| (!v.is_empty()).then_some(v);
+        Ok(LabelOrInst::Inst {
+            op,
+            dest,
+            ty,
+            args: non_empty(args),
+            funcs: non_empty(funcs),
+            labels: non_empty(labels),
+            value,
+        })
+    }
+}
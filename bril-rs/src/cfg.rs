@@ -156,9 +156,16 @@ impl Cfg {
                 }))
             })
             .collect();
+        Self::link_nodes(&nodes);
+        let root = Arc::downgrade(&nodes[0]);
+        (root, nodes)
+    }
 
+    /// connect nodes according to the terminator of each block, falling through
+    /// to the next node in layout order if there is no explicit jump
+    fn link_nodes(nodes: &[NodeRef]) {
         let mut node_by_label = HashMap::<String, WeakNodeRef>::new();
-        for node in &nodes {
+        for node in nodes {
             if let Some(label) = &node.lock().unwrap().label {
                 node_by_label.insert(String::from(label), Arc::downgrade(node));
             }
@@ -167,33 +174,19 @@ impl Cfg {
         for (i, node) in nodes.iter().enumerate() {
             let mut node_lock = node.lock().unwrap();
 
-            let successors = match node_lock.blk.instrs.last().unwrap() {
-                LabelOrInst::Inst { op, labels, .. } => {
-                    if is_terminator_op(op) {
-                        Some(
-                            labels
-                                .as_ref()
-                                .unwrap()
-                                .iter()
-                                .map(|label| node_by_label.get(label).cloned().unwrap())
-                                .collect::<Vec<_>>(),
-                        )
-                    } else if i < nodes.len() - 1 {
-                        // if non-terminator, we try to execute the following block
-                        Some(vec![Arc::downgrade(&nodes[i + 1])])
-                    } else {
-                        None
-                    }
-                }
-                // handle the case where a basic label may only contain one label no instr
-                LabelOrInst::Label { .. } => {
-                    debug_assert!(node_lock.label.is_some());
-                    if i < nodes.len() - 1 {
-                        Some(vec![Arc::downgrade(&nodes[i + 1])])
-                    } else {
-                        None
-                    }
-                }
+            let successors = match node_lock.blk.instrs.last() {
+                Some(LabelOrInst::Inst { op, labels, .. }) if is_terminator_op(op) => Some(
+                    labels
+                        .as_ref()
+                        .unwrap()
+                        .iter()
+                        .map(|label| node_by_label.get(label).cloned().unwrap())
+                        .collect::<Vec<_>>(),
+                ),
+                // if non-terminator, we try to execute the following block
+                // this also handles the case where a basic block may only contain one label no instr
+                _ if i < nodes.len() - 1 => Some(vec![Arc::downgrade(&nodes[i + 1])]),
+                _ => None,
             };
 
             // do this in two pass to accommondate borrow checker
@@ -201,18 +194,174 @@ impl Cfg {
                 // update predecessor info
                 for successor in &successors {
                     let successor = successor.upgrade().unwrap();
-                    successor
-                        .lock()
-                        .unwrap()
-                        .predecessors
-                        .push(Arc::downgrade(node));
+                    let pred = Arc::downgrade(node);
+                    if Arc::ptr_eq(&successor, node) {
+                        node_lock.predecessors.push(pred);
+                    } else {
+                        successor.lock().unwrap().predecessors.push(pred);
+                    }
                 }
 
                 node_lock.successors.extend(successors);
             }
         }
-        let root = Arc::downgrade(&nodes[0]);
-        (root, nodes)
+    }
+
+    /// recompute successors and predecessors of every node from the block terminators
+    /// should be called after blocks are inserted, removed or re-targeted
+    pub fn relink(&mut self) {
+        for node in &self.nodes {
+            let mut node_lock = node.lock().unwrap();
+            node_lock.successors.clear();
+            node_lock.predecessors.clear();
+        }
+        Self::link_nodes(&self.nodes);
+        self.root = Arc::downgrade(&self.nodes[0]);
+    }
+
+    pub fn node_by_label(&self, label: &str) -> Option<NodeRef> {
+        self.nodes
+            .iter()
+            .find(|node| node.lock().unwrap().label.as_deref() == Some(label))
+            .cloned()
+    }
+
+    /// returns `hint` if no block is labeled as it, otherwise `hint` suffixed by a counter
+    pub fn fresh_label(&self, hint: &str) -> String {
+        let labels: HashSet<String> = self
+            .nodes
+            .iter()
+            .filter_map(|node| node.lock().unwrap().label.clone())
+            .collect();
+        if !labels.contains(hint) {
+            return hint.to_string();
+        }
+        (1..)
+            .map(|i| format!("{hint}.{i}"))
+            .find(|label| !labels.contains(label))
+            .unwrap()
+    }
+
+    /// one var per hint, named like `fresh_label` does, distinct from each other and from
+    /// the params and every var the function reads or writes
+    pub fn fresh_vars(&self, hints: &[String]) -> Vec<String> {
+        let mut names: HashSet<String> = self
+            .func_ctx
+            .args_name()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        for node in &self.nodes {
+            for inst in &node.lock().unwrap().blk.instrs {
+                if let LabelOrInst::Inst { dest, args, .. } = inst {
+                    names.extend(dest.iter().chain(args.iter().flatten()).cloned());
+                }
+            }
+        }
+        hints
+            .iter()
+            .map(|hint| {
+                let name = std::iter::once(hint.clone())
+                    .chain((1..).map(|i| format!("{hint}.{i}")))
+                    .find(|name| !names.contains(name))
+                    .unwrap();
+                names.insert(name.clone());
+                name
+            })
+            .collect()
+    }
+
+    /// make all control transfer explicit, every non-entry block gets a label
+    /// and every block falling through to its layout successor ends with a `jmp`
+    ///
+    /// after this, nodes can be cloned and reordered without changing semantics
+    pub fn make_jumps_explicit(&mut self) {
+        for i in 1..self.nodes.len() {
+            if self.nodes[i].lock().unwrap().label.is_none() {
+                let label = self.fresh_label(&format!("{}.blk.{i}", self.func_ctx.name));
+                let mut node_lock = self.nodes[i].lock().unwrap();
+                node_lock.blk.set_label(label.clone());
+                node_lock.label = Some(label);
+            }
+        }
+        for i in 0..self.nodes.len().saturating_sub(1) {
+            let next_label = self.nodes[i + 1].lock().unwrap().label.clone().unwrap();
+            let mut node_lock = self.nodes[i].lock().unwrap();
+            if node_lock.blk.falls_through() {
+                node_lock.blk.instrs.push(LabelOrInst::Inst {
+                    op: "jmp".to_string(),
+                    dest: None,
+                    ty: None,
+                    args: None,
+                    funcs: None,
+                    labels: Some(vec![next_label]),
+                    value: None,
+                });
+            }
+        }
+        self.relink();
+    }
+
+    /// inverse of [`Cfg::make_jumps_explicit`], drops every `jmp` targeting the next block
+    /// in layout order, unlabeled blocks left empty are removed
+    pub fn elide_fallthrough_jumps(&mut self) {
+        for i in 0..self.nodes.len().saturating_sub(1) {
+            let next_label = self.nodes[i + 1].lock().unwrap().label.clone();
+            let mut node_lock = self.nodes[i].lock().unwrap();
+            if let Some(LabelOrInst::Inst {
+                op,
+                labels: Some(labels),
+                ..
+            }) = node_lock.blk.instrs.last()
+            {
+                if op == "jmp" && next_label.as_ref() == labels.first() {
+                    node_lock.blk.instrs.pop();
+                }
+            }
+        }
+        if self.nodes.len() > 1 {
+            self.nodes
+                .retain(|node| !node.lock().unwrap().blk.instrs.is_empty());
+        }
+        self.relink();
+    }
+
+    /// drop nodes not reachable from the root
+    pub fn prune_unreachable(&mut self) {
+        let mut reachable = HashSet::new();
+        let mut stack = vec![self.root.upgrade().unwrap()];
+        while let Some(node) = stack.pop() {
+            if reachable.insert(Arc::as_ptr(&node)) {
+                stack.extend(
+                    node.lock()
+                        .unwrap()
+                        .successors
+                        .iter()
+                        .map(|succ| succ.upgrade().unwrap()),
+                );
+            }
+        }
+        self.nodes
+            .retain(|node| reachable.contains(&Arc::as_ptr(node)));
+        self.relink();
+    }
+
+    /// duplicate blocks of `nodes`, labels defined or targeted within the copies are renamed
+    /// according to `scheme`, the copies are not linked, nor inserted into the cfg
+    pub fn clone_nodes(nodes: &[NodeRef], scheme: &HashMap<String, String>) -> Vec<NodeRef> {
+        nodes
+            .iter()
+            .map(|node| {
+                let mut blk = node.lock().unwrap().blk.clone();
+                blk.rename_labels(scheme);
+                Arc::new(Mutex::new(CfgNode {
+                    label: blk.label.clone(),
+                    blk,
+                    successors: vec![],
+                    predecessors: vec![],
+                }))
+            })
+            .collect()
     }
 
     pub fn port_as_dot_string(&self) -> String {
@@ -432,6 +581,78 @@ impl BasicBlock {
         def
     }
 
+    /// label the block, replacing the existing label if there is one
+    pub fn set_label(&mut self, label: String) {
+        if self.label.is_some() {
+            self.instrs.remove(0);
+        }
+        self.instrs.insert(
+            0,
+            LabelOrInst::Label {
+                label: label.clone(),
+            },
+        );
+        self.label = Some(label);
+    }
+
+    /// rename block label and jump targets, labels not found in `scheme` are kept
+    pub fn rename_labels(&mut self, scheme: &HashMap<String, String>) {
+        if let Some(label) = self.label.as_ref().and_then(|label| scheme.get(label)) {
+            self.set_label(label.clone());
+        }
+        for inst in &mut self.instrs {
+            if let LabelOrInst::Inst {
+                labels: Some(labels),
+                ..
+            } = inst
+            {
+                for label in labels.iter_mut() {
+                    if let Some(renamed) = scheme.get(label) {
+                        *label = renamed.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// rename dest and args of every instr, vars not found in `scheme` are kept
+    pub fn rename_vars(&mut self, scheme: &HashMap<String, String>) {
+        for inst in &mut self.instrs {
+            if let LabelOrInst::Inst { dest, args, .. } = inst {
+                for var in dest.iter_mut().chain(args.iter_mut().flatten()) {
+                    if let Some(renamed) = scheme.get(var) {
+                        *var = renamed.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    /// re-target jumps to `from` at the end of the block to `to`, block label is untouched
+    pub fn redirect_jumps(&mut self, from: &str, to: &str) {
+        if let Some(LabelOrInst::Inst {
+            op,
+            labels: Some(labels),
+            ..
+        }) = self.instrs.last_mut()
+        {
+            if is_terminator_op(op) {
+                labels
+                    .iter_mut()
+                    .filter(|label| *label == from)
+                    .for_each(|label| *label = to.to_string());
+            }
+        }
+    }
+
+    /// whether control may reach the following block in layout order
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self.instrs.last(),
+            Some(LabelOrInst::Inst { op, .. }) if matches!(op.as_str(), "br" | "jmp" | "ret")
+        )
+    }
+
     fn new_with_label(label: String) -> Self {
         Self {
             label: Some(label.clone()),
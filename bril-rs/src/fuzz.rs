@@ -0,0 +1,540 @@
+//! random well-formed bril programs for fuzzing passes
+//!
+//! Generated programs are well typed, never read an undefined variable, and always terminate:
+//!     @main(a0: int) {
+//!       v0: int = id a0;  v1: int = const 4;  b0: bool = const true;  k0: int = const 3;
+//!       i2: int = const 0;  n2: int = const 5;
+//!     .loop.2:
+//!       c2: bool = lt i2 n2;
+//!       br c2 .body.2 .exit.2;
+//!     .body.2:
+//!       v1: int = div v0 k0;
+//!       v0: int = call @f0 v1 i2;
+//!       ...
+//! Every variable of a function is assigned right at its entry, later assignments only overwrite
+//! it with a value of the same type. Loops run on a dedicated counter nothing else writes, up to
+//! a bounded trip count, helper `@f{i}` only calls `@f{j}` with `j > i`, and `div` only divides
+//! by the nonzero constants set up at entry.
+//!
+//! The dynamic instruction count is estimated while generating, once the estimate exceeds the
+//! budget no more loops or calls are emitted, so a single run stays cheap.
+use crate::bril::{Arg, Function, LabelOrInst, Prog, ValueLit};
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+/// ops the mix is made of
+pub const OPS: [&str; 14] = [
+    "const", "id", "add", "sub", "mul", "div", "eq", "lt", "gt", "le", "ge", "and", "or", "not",
+];
+
+pub struct ProgGenerator {
+    rng: StdRng,
+    op_weights: Vec<(&'static str, u32)>,
+    num_funcs: usize,
+    num_vars: usize,
+    num_main_args: usize,
+    stmts_per_block: usize,
+    max_nesting: usize,
+    max_loop_depth: usize,
+    max_trip_count: i32,
+    max_dyn_inst: u64,
+}
+
+impl ProgGenerator {
+    /// the same seed and settings always produce the same programs
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            op_weights: vec![
+                ("const", 2),
+                ("id", 1),
+                ("add", 4),
+                ("sub", 3),
+                ("mul", 2),
+                ("div", 1),
+                ("eq", 1),
+                ("lt", 1),
+                ("gt", 1),
+                ("le", 1),
+                ("ge", 1),
+                ("and", 1),
+                ("or", 1),
+                ("not", 1),
+            ],
+            num_funcs: 2,
+            num_vars: 6,
+            num_main_args: 2,
+            stmts_per_block: 5,
+            max_nesting: 3,
+            max_loop_depth: 2,
+            max_trip_count: 6,
+            max_dyn_inst: 50_000,
+        }
+    }
+
+    /// relative frequency of `op` among assignments, 0 disables it
+    pub fn op_weight(mut self, op: &str, weight: u32) -> Self {
+        let entry = self
+            .op_weights
+            .iter_mut()
+            .find(|(known, _)| *known == op)
+            .unwrap_or_else(|| panic!("{op} is not one of {OPS:?}"));
+        entry.1 = weight;
+        self
+    }
+
+    /// helper functions besides `main`
+    pub fn num_funcs(mut self, num_funcs: usize) -> Self {
+        self.num_funcs = num_funcs;
+        self
+    }
+
+    /// variables of each function, at least one int and one bool
+    pub fn num_vars(mut self, num_vars: usize) -> Self {
+        self.num_vars = num_vars.max(2);
+        self
+    }
+
+    /// int params of `main`
+    pub fn num_main_args(mut self, num_main_args: usize) -> Self {
+        self.num_main_args = num_main_args;
+        self
+    }
+
+    pub fn stmts_per_block(mut self, stmts_per_block: usize) -> Self {
+        self.stmts_per_block = stmts_per_block.max(1);
+        self
+    }
+
+    /// loops and branches nested in each other
+    pub fn max_nesting(mut self, max_nesting: usize) -> Self {
+        self.max_nesting = max_nesting;
+        self
+    }
+
+    pub fn max_loop_depth(mut self, max_loop_depth: usize) -> Self {
+        self.max_loop_depth = max_loop_depth;
+        self
+    }
+
+    pub fn max_trip_count(mut self, max_trip_count: i32) -> Self {
+        self.max_trip_count = max_trip_count.max(1);
+        self
+    }
+
+    /// rough bound on instructions a single run executes
+    pub fn max_dyn_inst(mut self, max_dyn_inst: u64) -> Self {
+        self.max_dyn_inst = max_dyn_inst;
+        self
+    }
+
+    pub fn generate(&mut self) -> Prog {
+        let mut functions = vec![];
+        // (name, arity, estimated cost), callees are generated before their callers
+        let mut callees: Vec<(String, usize, u64)> = vec![];
+        for i in (0..self.num_funcs).rev() {
+            let name = format!("f{i}");
+            let arity = self.rng.random_range(1..=3);
+            let params = (0..arity).map(|j| format!("p{j}")).collect();
+            let (func, cost) = FuncGen::new(self, callees.clone(), params, true).finish(&name);
+            callees.push((name, arity, cost));
+            functions.push(func);
+        }
+        let params = (0..self.num_main_args).map(|j| format!("a{j}")).collect();
+        let (main, _) = FuncGen::new(self, callees, params, false).finish("main");
+        functions.push(main);
+        functions.reverse();
+        Prog { functions }
+    }
+
+    /// random int args for the `main` of a generated program
+    pub fn arg_sets(&mut self, num_sets: usize) -> Vec<Vec<String>> {
+        (0..num_sets)
+            .map(|_| {
+                (0..self.num_main_args)
+                    .map(|_| self.rng.random_range(-10..=10).to_string())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+struct FuncGen<'g> {
+    generator: &'g mut ProgGenerator,
+    instrs: Vec<LabelOrInst>,
+    params: Vec<String>,
+    /// assignable variables
+    ints: Vec<String>,
+    bools: Vec<String>,
+    /// nonzero constants, divisors of `div`
+    divisors: Vec<String>,
+    /// counters of the enclosing loops, read only
+    counters: Vec<String>,
+    callees: Vec<(String, usize, u64)>,
+    returns_int: bool,
+    /// estimated dynamic instructions so far
+    cost: u64,
+    /// times the current position runs per call, product of the enclosing trip counts
+    multiplier: u64,
+    next_id: usize,
+}
+
+impl<'g> FuncGen<'g> {
+    fn new(
+        generator: &'g mut ProgGenerator,
+        callees: Vec<(String, usize, u64)>,
+        params: Vec<String>,
+        returns_int: bool,
+    ) -> Self {
+        let num_ints = generator.rng.random_range(1..generator.num_vars);
+        let ints = (0..num_ints).map(|i| format!("v{i}")).collect();
+        let bools = (0..generator.num_vars - num_ints)
+            .map(|i| format!("b{i}"))
+            .collect();
+        Self {
+            generator,
+            instrs: vec![],
+            params,
+            ints,
+            bools,
+            divisors: vec!["k0".to_string(), "k1".to_string()],
+            counters: vec![],
+            callees,
+            returns_int,
+            cost: 0,
+            multiplier: 1,
+            next_id: 0,
+        }
+    }
+
+    fn finish(mut self, name: &str) -> (Function, u64) {
+        for var in self.ints.clone() {
+            if !self.params.is_empty() && self.generator.rng.random_bool(0.5) {
+                let param = self.params.choose(&mut self.generator.rng).unwrap().clone();
+                self.emit(inst("id", Some((&var, "int")), vec![param], None));
+            } else {
+                let value = self.random_int();
+                self.emit_const(&var, ValueLit::Int(value));
+            }
+        }
+        for var in self.bools.clone() {
+            let value = self.generator.rng.random_bool(0.5);
+            self.emit_const(&var, ValueLit::Bool(value));
+        }
+        for divisor in self.divisors.clone() {
+            let value = self.generator.rng.random_range(1..=7);
+            self.emit_const(&divisor, ValueLit::Int(value));
+        }
+        self.block(0, 0);
+        if self.returns_int {
+            self.emit_ret();
+        } else {
+            let observed: Vec<String> = self.ints.iter().chain(&self.bools).cloned().collect();
+            self.emit(inst("print", None, observed, None));
+        }
+        let func = Function {
+            name: name.to_string(),
+            args: (!self.params.is_empty()).then(|| {
+                self.params
+                    .iter()
+                    .map(|param| Arg {
+                        name: param.clone(),
+                        ty: "int".to_string(),
+                    })
+                    .collect()
+            }),
+            ty: self.returns_int.then(|| "int".to_string()),
+            instrs: self.instrs,
+        };
+        (func, self.cost)
+    }
+
+    /// the body of the function itself is longer than nested blocks
+    fn block(&mut self, nesting: usize, loop_depth: usize) {
+        let max_stmts = self.generator.stmts_per_block;
+        let num_stmts = if nesting == 0 {
+            self.generator.rng.random_range(max_stmts..=2 * max_stmts)
+        } else {
+            self.generator.rng.random_range(1..=max_stmts)
+        };
+        for _ in 0..num_stmts {
+            self.stmt(nesting, loop_depth);
+        }
+    }
+
+    fn stmt(&mut self, nesting: usize, loop_depth: usize) {
+        let affordable = self.cost < self.generator.max_dyn_inst;
+        let can_nest = nesting < self.generator.max_nesting;
+        let can_loop = can_nest && affordable && loop_depth < self.generator.max_loop_depth;
+        let callee = self.affordable_callee();
+        let choices = [
+            (0, 8),
+            (1, 1),
+            (2, if callee.is_some() { 3 } else { 0 }),
+            (3, if can_nest { 3 } else { 0 }),
+            (4, if can_loop { 3 } else { 0 }),
+        ];
+        let (kind, _) = choices
+            .choose_weighted(&mut self.generator.rng, |(_, weight)| *weight)
+            .unwrap();
+        match kind {
+            0 => self.assign(),
+            1 => {
+                let var = self.readable_var();
+                self.emit(inst("print", None, vec![var], None));
+            }
+            2 => self.call(callee.unwrap()),
+            3 => self.branch(nesting, loop_depth),
+            _ => self.counted_loop(nesting, loop_depth),
+        }
+    }
+
+    fn assign(&mut self) {
+        let total: u32 = self.generator.op_weights.iter().map(|(_, w)| w).sum();
+        if total == 0 {
+            return;
+        }
+        let (op, _) = *self
+            .generator
+            .op_weights
+            .choose_weighted(&mut self.generator.rng, |(_, weight)| *weight)
+            .unwrap();
+        let int_typed = self.generator.rng.random_bool(0.5);
+        match op {
+            "const" if int_typed => {
+                let (dest, value) = (self.int_var(), self.random_int());
+                self.emit_const(&dest, ValueLit::Int(value));
+            }
+            "const" => {
+                let (dest, value) = (self.bool_var(), self.generator.rng.random_bool(0.5));
+                self.emit_const(&dest, ValueLit::Bool(value));
+            }
+            "id" if int_typed => {
+                let (dest, arg) = (self.int_var(), self.readable_int());
+                self.emit(inst("id", Some((&dest, "int")), vec![arg], None));
+            }
+            "id" => {
+                let (dest, arg) = (self.bool_var(), self.bool_var());
+                self.emit(inst("id", Some((&dest, "bool")), vec![arg], None));
+            }
+            "add" | "sub" | "mul" | "div" => {
+                let (dest, lhs) = (self.int_var(), self.readable_int());
+                let rhs = if op == "div" {
+                    self.divisors
+                        .choose(&mut self.generator.rng)
+                        .unwrap()
+                        .clone()
+                } else {
+                    self.readable_int()
+                };
+                self.emit(inst(op, Some((&dest, "int")), vec![lhs, rhs], None));
+            }
+            "eq" | "lt" | "gt" | "le" | "ge" => {
+                let dest = self.bool_var();
+                let args = vec![self.readable_int(), self.readable_int()];
+                self.emit(inst(op, Some((&dest, "bool")), args, None));
+            }
+            _ => {
+                let dest = self.bool_var();
+                let num_args = if op == "not" { 1 } else { 2 };
+                let args = (0..num_args).map(|_| self.bool_var()).collect();
+                self.emit(inst(op, Some((&dest, "bool")), args, None));
+            }
+        }
+    }
+
+    fn call(&mut self, (callee, arity, cost): (String, usize, u64)) {
+        let dest = self.int_var();
+        let args = (0..arity).map(|_| self.readable_int()).collect();
+        self.emit(LabelOrInst::Inst {
+            op: "call".to_string(),
+            dest: Some(dest),
+            ty: Some("int".to_string()),
+            args: Some(args),
+            funcs: Some(vec![callee]),
+            labels: None,
+            value: None,
+        });
+        self.cost += cost * self.multiplier;
+    }
+
+    /// both arms count towards the cost, one of them sometimes returns early
+    fn branch(&mut self, nesting: usize, loop_depth: usize) {
+        let id = self.fresh_id();
+        let (then_label, else_label, join_label) = (
+            format!("then.{id}"),
+            format!("else.{id}"),
+            format!("join.{id}"),
+        );
+        let cond = self.bool_var();
+        let has_else = self.generator.rng.random_bool(0.5);
+        let false_target = if has_else { &else_label } else { &join_label };
+        self.emit(inst(
+            "br",
+            None,
+            vec![cond],
+            Some(vec![then_label.clone(), false_target.clone()]),
+        ));
+        self.emit_label(&then_label);
+        self.block(nesting + 1, loop_depth);
+        if self.generator.rng.random_bool(0.1) {
+            self.emit_ret();
+        } else if has_else {
+            self.emit(inst("jmp", None, vec![], Some(vec![join_label.clone()])));
+        }
+        if has_else {
+            self.emit_label(&else_label);
+            self.block(nesting + 1, loop_depth);
+        }
+        self.emit_label(&join_label);
+    }
+
+    fn counted_loop(&mut self, nesting: usize, loop_depth: usize) {
+        let id = self.fresh_id();
+        let (counter, bound, step, cond) = (
+            format!("i{id}"),
+            format!("n{id}"),
+            format!("s{id}"),
+            format!("c{id}"),
+        );
+        let (header, body, exit) = (
+            format!("loop.{id}"),
+            format!("body.{id}"),
+            format!("exit.{id}"),
+        );
+        let trip_count = self
+            .generator
+            .rng
+            .random_range(1..=self.generator.max_trip_count);
+        self.emit_const(&counter, ValueLit::Int(0));
+        self.emit_const(&bound, ValueLit::Int(trip_count));
+        self.emit_const(&step, ValueLit::Int(1));
+
+        let outer_multiplier = self.multiplier;
+        self.multiplier *= trip_count as u64 + 1;
+        self.emit_label(&header);
+        self.emit(inst(
+            "lt",
+            Some((&cond, "bool")),
+            vec![counter.clone(), bound],
+            None,
+        ));
+        self.emit(inst(
+            "br",
+            None,
+            vec![cond],
+            Some(vec![body.clone(), exit.clone()]),
+        ));
+        self.emit_label(&body);
+        self.counters.push(counter.clone());
+        self.block(nesting + 1, loop_depth + 1);
+        self.counters.pop();
+        self.emit(inst(
+            "add",
+            Some((&counter, "int")),
+            vec![counter.clone(), step],
+            None,
+        ));
+        self.emit(inst("jmp", None, vec![], Some(vec![header])));
+        self.multiplier = outer_multiplier;
+        self.emit_label(&exit);
+    }
+
+    fn affordable_callee(&mut self) -> Option<(String, usize, u64)> {
+        let budget = self.generator.max_dyn_inst.saturating_sub(self.cost);
+        let affordable: Vec<_> = self
+            .callees
+            .iter()
+            .filter(|(_, _, cost)| cost.saturating_mul(self.multiplier) <= budget)
+            .collect();
+        affordable.choose(&mut self.generator.rng).cloned().cloned()
+    }
+
+    fn readable_int(&mut self) -> String {
+        let candidates: Vec<&String> = self
+            .ints
+            .iter()
+            .chain(&self.params)
+            .chain(&self.counters)
+            .collect();
+        candidates
+            .choose(&mut self.generator.rng)
+            .map(|var| var.to_string())
+            .unwrap()
+    }
+
+    fn readable_var(&mut self) -> String {
+        if self.generator.rng.random_bool(0.7) {
+            self.readable_int()
+        } else {
+            self.bool_var()
+        }
+    }
+
+    fn int_var(&mut self) -> String {
+        self.ints.choose(&mut self.generator.rng).unwrap().clone()
+    }
+
+    fn bool_var(&mut self) -> String {
+        self.bools.choose(&mut self.generator.rng).unwrap().clone()
+    }
+
+    fn random_int(&mut self) -> i32 {
+        self.generator.rng.random_range(-20..=20)
+    }
+
+    fn fresh_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn emit_ret(&mut self) {
+        let args = if self.returns_int {
+            vec![self.readable_int()]
+        } else {
+            vec![]
+        };
+        self.emit(inst("ret", None, args, None));
+    }
+
+    fn emit_const(&mut self, dest: &str, value: ValueLit) {
+        let ty = match value {
+            ValueLit::Int(_) => "int",
+            ValueLit::Bool(_) => "bool",
+        };
+        let mut constant = inst("const", Some((dest, ty)), vec![], None);
+        if let LabelOrInst::Inst { value: slot, .. } = &mut constant {
+            *slot = Some(value);
+        }
+        self.emit(constant);
+    }
+
+    fn emit_label(&mut self, label: &str) {
+        self.instrs.push(LabelOrInst::Label {
+            label: label.to_string(),
+        });
+    }
+
+    fn emit(&mut self, inst: LabelOrInst) {
+        self.cost += self.multiplier;
+        self.instrs.push(inst);
+    }
+}
+
+fn inst(
+    op: &str,
+    dest: Option<(&str, &str)>,
+    args: Vec<String>,
+    labels: Option<Vec<String>>,
+) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: op.to_string(),
+        dest: dest.map(|(dest, _)| dest.to_string()),
+        ty: dest.map(|(_, ty)| ty.to_string()),
+        args: (!args.is_empty()).then_some(args),
+        funcs: None,
+        labels,
+        value: None,
+    }
+}
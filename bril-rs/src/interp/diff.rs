@@ -0,0 +1,291 @@
+//! differential testing of passes
+//!
+//! Every program of the corpus runs on each of its arg sets before and after the pipeline,
+//! the pass is considered correct on that input if both runs print the same and agree on the
+//! exit status `brili` would report, i.e. both succeed or both fail:
+//!     baseline:  print 6; exit 0           args: 3
+//!     optimized: print 5; exit 0           mismatch, shrunk to args: 1
+//! A failing input is shrunk towards zero as long as the mismatch persists and the baseline
+//! keeps succeeding, so what gets reported is the smallest input still exposing the bug.
+use super::{main_of, parse_main_args, InterpError, Interpreter};
+use crate::bril::Prog;
+use crate::cfg::ProgCfgs;
+use crate::optim::fuel;
+
+use std::panic::{self, AssertUnwindSafe};
+
+/// program of the corpus together with the inputs it runs on
+pub struct Case {
+    pub name: String,
+    pub prog: Prog,
+    /// `main` runs once per arg set
+    pub arg_sets: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Run {
+    pub output: String,
+    pub error: Option<InterpError>,
+    pub total_dyn_inst: u64,
+}
+
+impl Run {
+    pub fn agrees_with(&self, other: &Run) -> bool {
+        self.output == other.output && self.error.is_some() == other.error.is_some()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Execution {
+    pub args: Vec<String>,
+    pub baseline: Run,
+    pub optimized: Run,
+}
+
+impl Execution {
+    pub fn is_mismatch(&self) -> bool {
+        !self.baseline.agrees_with(&self.optimized)
+    }
+
+    /// dynamic instructions saved by the pipeline, negative if it slowed the program down
+    pub fn dyn_inst_delta(&self) -> i64 {
+        self.baseline.total_dyn_inst as i64 - self.optimized.total_dyn_inst as i64
+    }
+}
+
+pub struct CaseReport {
+    pub name: String,
+    pub executions: Vec<Execution>,
+    /// the pipeline itself crashed on this program, nothing got executed
+    pub pass_panic: Option<String>,
+    /// shortest running mismatch after shrinking its args
+    pub minimal_mismatch: Option<Execution>,
+}
+
+impl CaseReport {
+    pub fn passed(&self) -> bool {
+        self.pass_panic.is_none() && self.minimal_mismatch.is_none()
+    }
+
+    /// summed over executions where both runs finished, failed runs count no instructions
+    pub fn dyn_inst_delta(&self) -> i64 {
+        self.executions
+            .iter()
+            .filter(|execution| execution.baseline.error.is_none())
+            .filter(|execution| execution.optimized.error.is_none())
+            .map(Execution::dyn_inst_delta)
+            .sum()
+    }
+}
+
+/// outcome of [`DiffTester::bisect`]
+pub struct Bisection {
+    /// rewrites performed by the pipeline when fuel is unlimited
+    pub num_rewrites: u64,
+    /// number and description of the first rewrite the case fails after,
+    /// `None` if it fails without any rewrite, e.g. the pipeline crashes regardless
+    pub first_bad: Option<(u64, String)>,
+}
+
+pub struct DiffTester<F: Fn(ProgCfgs) -> ProgCfgs> {
+    pipeline: F,
+    step_limit: u64,
+    shrink_budget: usize,
+}
+
+impl<F: Fn(ProgCfgs) -> ProgCfgs> DiffTester<F> {
+    pub fn new(pipeline: F) -> Self {
+        Self {
+            pipeline,
+            step_limit: 100_000_000,
+            shrink_budget: 200,
+        }
+    }
+
+    /// dynamic instructions a baseline run may take, the optimized program is additionally
+    /// cut off once it runs far longer than the baseline, an infinite loop is a mismatch
+    pub fn step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = limit;
+        self
+    }
+
+    /// number of extra runs spent shrinking failing args
+    pub fn shrink_budget(mut self, budget: usize) -> Self {
+        self.shrink_budget = budget;
+        self
+    }
+
+    pub fn check_corpus(&self, corpus: &[Case]) -> Vec<CaseReport> {
+        corpus.iter().map(|case| self.check(case)).collect()
+    }
+
+    pub fn check(&self, case: &Case) -> CaseReport {
+        let mut report = CaseReport {
+            name: case.name.clone(),
+            executions: vec![],
+            pass_panic: None,
+            minimal_mismatch: None,
+        };
+        let optimized = match self.optimize(&case.prog) {
+            Ok(optimized) => optimized,
+            Err(msg) => {
+                report.pass_panic = Some(msg);
+                return report;
+            }
+        };
+        for args in &case.arg_sets {
+            report
+                .executions
+                .push(self.execute(&case.prog, &optimized, args));
+        }
+        let mut budget = self.shrink_budget;
+        report.minimal_mismatch = report
+            .executions
+            .iter()
+            .filter(|execution| execution.is_mismatch())
+            .map(|execution| self.shrink(&case.prog, &optimized, execution.clone(), &mut budget))
+            .min_by_key(|execution| execution.baseline.total_dyn_inst);
+        report
+    }
+
+    /// binary searches the fuel given to the pipeline for the first rewrite after which `case`
+    /// fails on any of its arg sets, `None` if the case does not fail in the first place
+    ///
+    /// Rewrites are numbered in the order passes ask for fuel, refusing one may shift the
+    /// numbering of those after it. The search assumes that a pipeline failing with some
+    /// budget keeps failing with any larger one, which holds as long as a single rewrite is
+    /// to blame.
+    pub fn bisect(&self, case: &Case) -> Option<Bisection> {
+        let (fails, tally) = self.fails_with_fuel(case, u64::MAX);
+        if !fails {
+            return None;
+        }
+        let num_rewrites = tally.requested;
+        if self.fails_with_fuel(case, 0).0 {
+            return Some(Bisection {
+                num_rewrites,
+                first_bad: None,
+            });
+        }
+        // passes with `good` units of fuel, fails with `bad`
+        let (mut good, mut bad) = (0, num_rewrites);
+        while bad - good > 1 {
+            let mid = good + (bad - good) / 2;
+            if self.fails_with_fuel(case, mid).0 {
+                bad = mid;
+            } else {
+                good = mid;
+            }
+        }
+        let (_, tally) = self.fails_with_fuel(case, bad);
+        Some(Bisection {
+            num_rewrites,
+            first_bad: Some((bad, tally.last_granted.unwrap_or_default())),
+        })
+    }
+
+    fn fails_with_fuel(&self, case: &Case, budget: u64) -> (bool, fuel::Tally) {
+        let (optimized, tally) = fuel::with_budget(budget, || self.optimize(&case.prog));
+        let fails = match optimized {
+            Ok(optimized) => case
+                .arg_sets
+                .iter()
+                .any(|args| self.execute(&case.prog, &optimized, args).is_mismatch()),
+            Err(_) => true,
+        };
+        (fails, tally)
+    }
+
+    fn optimize(&self, prog: &Prog) -> Result<Prog, String> {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            (self.pipeline)(ProgCfgs::from_bril_prog(prog)).into_bril_prog()
+        }))
+        .map_err(|payload| {
+            payload
+                .downcast_ref::<&str>()
+                .map(|msg| msg.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "pass panicked".to_string())
+        })
+    }
+
+    fn execute(&self, baseline: &Prog, optimized: &Prog, args: &[String]) -> Execution {
+        let baseline = run(baseline, args, self.step_limit);
+        let optimized_limit = if baseline.error.is_none() {
+            self.step_limit
+                .min(baseline.total_dyn_inst.saturating_mul(8) + 100_000)
+        } else {
+            self.step_limit
+        };
+        Execution {
+            args: args.to_vec(),
+            baseline,
+            optimized: run(optimized, args, optimized_limit),
+        }
+    }
+
+    /// greedily moves one arg at a time towards zero, ints are halved, decremented or zeroed,
+    /// bools become false
+    fn shrink(
+        &self,
+        baseline: &Prog,
+        optimized: &Prog,
+        mut smallest: Execution,
+        budget: &mut usize,
+    ) -> Execution {
+        'shrinking: while *budget > 0 {
+            for i in 0..smallest.args.len() {
+                for candidate in smaller_args(&smallest.args[i]) {
+                    if *budget == 0 {
+                        break 'shrinking;
+                    }
+                    *budget -= 1;
+                    let mut args = smallest.args.clone();
+                    args[i] = candidate;
+                    let execution = self.execute(baseline, optimized, &args);
+                    if execution.baseline.error.is_none() && execution.is_mismatch() {
+                        smallest = execution;
+                        continue 'shrinking;
+                    }
+                }
+            }
+            break;
+        }
+        smallest
+    }
+}
+
+fn smaller_args(arg: &str) -> Vec<String> {
+    if let Ok(v) = arg.parse::<i64>() {
+        let mut candidates = vec![0, v / 2, v - v.signum()];
+        candidates.dedup();
+        candidates
+            .into_iter()
+            .filter(|candidate| candidate.unsigned_abs() < v.unsigned_abs())
+            .map(|candidate| candidate.to_string())
+            .collect()
+    } else if arg == "true" {
+        vec!["false".to_string()]
+    } else {
+        vec![]
+    }
+}
+
+fn run(prog: &Prog, args: &[String], step_limit: u64) -> Run {
+    let mut out = vec![];
+    let ret = main_of(prog).and_then(|main| {
+        let main_args = parse_main_args(main, args)?;
+        Interpreter::new(prog, &mut out)
+            .step_limit(step_limit)
+            .run(main, main_args)
+    });
+    let (error, total_dyn_inst) = match ret {
+        Ok(profile) => (None, profile.total_dyn_inst),
+        Err(e) => (Some(e), 0),
+    };
+    Run {
+        output: String::from_utf8_lossy(&out).into_owned(),
+        error,
+        total_dyn_inst,
+    }
+}
@@ -0,0 +1,127 @@
+//! segmented heap of the memory extension
+//!
+//! Every `alloc` gets its own segment, a pointer is the segment plus an offset into it.
+//! Freed segments are never reused, so a stale pointer is caught instead of silently
+//! reading a later allocation:
+//!     p: ptr<int> = alloc n;        segment 0, n cells, shown as ptr(0+0)
+//!     q: ptr<int> = ptradd p n;     fine, only checked once dereferenced
+//!     store q one;                  out of bounds, offset n of a segment with n cells
+//!     free p;
+//!     free p;                       double free
+use super::{InterpError, Value};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pointer {
+    pub segment: usize,
+    pub offset: i64,
+}
+
+impl fmt::Display for Pointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ptr({}{:+})", self.segment, self.offset)
+    }
+}
+
+/// allocations made by one function, in number of cells
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FuncAllocStat {
+    pub num_alloc: usize,
+    pub total_alloc_size: usize,
+}
+
+/// allocation still live when `main` returns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Leak {
+    pub func: String,
+    pub size: usize,
+}
+
+struct Segment {
+    /// `None` until stored
+    cells: Vec<Option<Value>>,
+    size: usize,
+    /// function doing the `alloc`
+    func: String,
+    freed: bool,
+}
+
+#[derive(Default)]
+pub(crate) struct Heap {
+    segments: Vec<Segment>,
+    pub(crate) stats: BTreeMap<String, FuncAllocStat>,
+}
+
+impl Heap {
+    pub(crate) fn alloc(&mut self, size: i64, func: &str) -> Result<Pointer, InterpError> {
+        if size <= 0 {
+            return Err(InterpError::BadAllocSize(size));
+        }
+        let size = size as usize;
+        let stat = self.stats.entry(func.to_string()).or_default();
+        stat.num_alloc += 1;
+        stat.total_alloc_size += size;
+        self.segments.push(Segment {
+            cells: vec![None; size],
+            size,
+            func: func.to_string(),
+            freed: false,
+        });
+        Ok(Pointer {
+            segment: self.segments.len() - 1,
+            offset: 0,
+        })
+    }
+
+    pub(crate) fn free(&mut self, ptr: Pointer) -> Result<(), InterpError> {
+        let segment = &mut self.segments[ptr.segment];
+        if segment.freed {
+            return Err(InterpError::DoubleFree(ptr));
+        }
+        if ptr.offset != 0 {
+            return Err(InterpError::FreeInterior(ptr));
+        }
+        segment.freed = true;
+        segment.cells = vec![];
+        Ok(())
+    }
+
+    pub(crate) fn load(&self, ptr: Pointer) -> Result<Value, InterpError> {
+        let idx = self.check(ptr)?;
+        self.segments[ptr.segment].cells[idx].ok_or(InterpError::UninitLoad(ptr))
+    }
+
+    pub(crate) fn store(&mut self, ptr: Pointer, value: Value) -> Result<(), InterpError> {
+        let idx = self.check(ptr)?;
+        self.segments[ptr.segment].cells[idx] = Some(value);
+        Ok(())
+    }
+
+    /// index of the cell `ptr` points to, if it can be dereferenced
+    fn check(&self, ptr: Pointer) -> Result<usize, InterpError> {
+        let segment = &self.segments[ptr.segment];
+        if segment.freed {
+            return Err(InterpError::UseAfterFree(ptr));
+        }
+        if ptr.offset < 0 || ptr.offset as usize >= segment.size {
+            return Err(InterpError::OutOfBounds {
+                ptr,
+                size: segment.size,
+            });
+        }
+        Ok(ptr.offset as usize)
+    }
+
+    pub(crate) fn leaks(&self) -> Vec<Leak> {
+        self.segments
+            .iter()
+            .filter(|segment| !segment.freed)
+            .map(|segment| Leak {
+                func: segment.func.clone(),
+                size: segment.size,
+            })
+            .collect()
+    }
+}
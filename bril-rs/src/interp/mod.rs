@@ -0,0 +1,565 @@
+//! reference interpreter for bril, following the semantics of `brili`
+//!
+//! Ints are 64 bit and wrap on overflow, `print` writes its args separated by a space,
+//! every executed instruction (labels excluded) counts as one dynamic instruction:
+//!     @main(n: int) {                      $ brili -p 3
+//!       one: int = const 1;                4
+//!       r: int = add n one;                total_dyn_inst: 3
+//!       print r;
+//!     }
+//! Ssa instructions are supported in both encodings, `set`/`get`/`undef` as well as `phi`,
+//! where a phi picks the arg of the block executed right before. Undefined values flow
+//! through `set`, `get` and `phi` without complaint, only reading one elsewhere is an error.
+//!
+//! Memory extension runs on a segmented heap, see [`heap`]. Allocations still live when
+//! `main` returns are reported as leaks, just like `brili` refusing to exit cleanly.
+pub mod diff;
+pub mod heap;
+
+use crate::bril::{Function, LabelOrInst, Prog, ValueLit};
+use heap::{FuncAllocStat, Heap, Leak, Pointer};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+    Ptr(Pointer),
+}
+
+impl From<ValueLit> for Value {
+    fn from(lit: ValueLit) -> Self {
+        match lit {
+            ValueLit::Int(i) => Value::Int(i as i64),
+            ValueLit::Bool(b) => Value::Bool(b),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(i) => write!(f, "{i}"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Ptr(ptr) => write!(f, "{ptr}"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterpError {
+    NoMain,
+    UndefinedFunc(String),
+    UndefinedVar(String),
+    UndefinedLabel(String),
+    UnknownOp(String),
+    /// `args` or `labels` of an instruction do not fit its op
+    MalformedInst(String),
+    ArgArity {
+        func: String,
+        expected: usize,
+        got: usize,
+    },
+    BadMainArg {
+        arg: String,
+        ty: String,
+    },
+    TypeMismatch {
+        op: String,
+        expected: &'static str,
+        got: Value,
+    },
+    DivisionByZero,
+    /// fell off the end of a function declaring a return type
+    ImplicitReturn(String),
+    /// `call` with a dest on a function returning nothing
+    NoReturnValue(String),
+    BadAllocSize(i64),
+    OutOfBounds {
+        ptr: Pointer,
+        size: usize,
+    },
+    UseAfterFree(Pointer),
+    DoubleFree(Pointer),
+    /// `free` of a pointer not at the start of its allocation
+    FreeInterior(Pointer),
+    UninitLoad(Pointer),
+    Leaked(Vec<Leak>),
+    /// more dynamic instructions executed than the interpreter was allowed to
+    StepLimit(u64),
+    Output(String),
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpError::NoMain => write!(f, "no main function defined"),
+            InterpError::UndefinedFunc(func) => write!(f, "undefined function {func}"),
+            InterpError::UndefinedVar(var) => write!(f, "undefined variable {var}"),
+            InterpError::UndefinedLabel(label) => write!(f, "undefined label {label}"),
+            InterpError::UnknownOp(op) => write!(f, "unknown opcode {op}"),
+            InterpError::MalformedInst(op) => write!(f, "malformed {op} instruction"),
+            InterpError::ArgArity {
+                func,
+                expected,
+                got,
+            } => write!(
+                f,
+                "function @{func} expects {expected} arguments, got {got}"
+            ),
+            InterpError::BadMainArg { arg, ty } => {
+                write!(f, "argument {arg} of main is not a valid {ty}")
+            }
+            InterpError::TypeMismatch { op, expected, got } => {
+                write!(f, "{op} expects {expected}, got {got}")
+            }
+            InterpError::DivisionByZero => write!(f, "division by zero"),
+            InterpError::ImplicitReturn(func) => {
+                write!(f, "implicit return in function @{func} with return type")
+            }
+            InterpError::NoReturnValue(func) => {
+                write!(f, "function @{func} returns no value to assign")
+            }
+            InterpError::BadAllocSize(size) => write!(f, "cannot allocate {size} entries"),
+            InterpError::OutOfBounds { ptr, size } => write!(
+                f,
+                "{ptr} out of bounds, offset {} of an allocation of {size}",
+                ptr.offset
+            ),
+            InterpError::UseAfterFree(ptr) => write!(f, "{ptr} used after being freed"),
+            InterpError::DoubleFree(ptr) => write!(f, "{ptr} freed twice"),
+            InterpError::FreeInterior(ptr) => {
+                write!(f, "{ptr} freed at nonzero offset {}", ptr.offset)
+            }
+            InterpError::UninitLoad(ptr) => write!(f, "{ptr} points to uninitialized data"),
+            InterpError::Leaked(leaks) => {
+                write!(
+                    f,
+                    "{} allocations not freed by end of execution:",
+                    leaks.len()
+                )?;
+                for leak in leaks {
+                    write!(f, "\n  {} entries allocated in @{}", leak.size, leak.func)?;
+                }
+                Ok(())
+            }
+            InterpError::StepLimit(limit) => {
+                write!(f, "gave up after executing {limit} instructions")
+            }
+            InterpError::Output(msg) => write!(f, "failed to write output: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for InterpError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub total_dyn_inst: u64,
+    /// dynamic instructions executed inside each function, excluding its callees
+    pub dyn_inst_per_func: BTreeMap<String, u64>,
+    pub alloc_per_func: BTreeMap<String, FuncAllocStat>,
+}
+
+/// runs `main` with command-line `args`, printing into `out`
+pub fn run_prog<W: Write>(
+    prog: &Prog,
+    args: &[String],
+    out: &mut W,
+) -> Result<Profile, InterpError> {
+    let main = main_of(prog)?;
+    let main_args = parse_main_args(main, args)?;
+    Interpreter::new(prog, out).run(main, main_args)
+}
+
+pub fn main_of(prog: &Prog) -> Result<&Function, InterpError> {
+    prog.functions
+        .iter()
+        .find(|func| func.name == "main")
+        .ok_or(InterpError::NoMain)
+}
+
+/// args are typed by the params of `main`, ints accept anything `i64` parses
+pub fn parse_main_args(main: &Function, args: &[String]) -> Result<Vec<Value>, InterpError> {
+    let params = main.args.as_deref().unwrap_or_default();
+    if params.len() != args.len() {
+        return Err(InterpError::ArgArity {
+            func: main.name.clone(),
+            expected: params.len(),
+            got: args.len(),
+        });
+    }
+    params
+        .iter()
+        .zip(args)
+        .map(|(param, arg)| {
+            let bad_arg = || InterpError::BadMainArg {
+                arg: arg.clone(),
+                ty: param.ty.clone(),
+            };
+            match param.ty.as_str() {
+                "int" => arg.parse().map(Value::Int).map_err(|_| bad_arg()),
+                "bool" => arg.parse().map(Value::Bool).map_err(|_| bad_arg()),
+                _ => Err(bad_arg()),
+            }
+        })
+        .collect()
+}
+
+/// function with its labels resolved to instruction indices
+struct FuncInfo<'a> {
+    func: &'a Function,
+    labels: HashMap<&'a str, usize>,
+}
+
+struct Frame<'f, 'a> {
+    info: &'f FuncInfo<'a>,
+    pc: usize,
+    env: HashMap<&'a str, Value>,
+    /// written by `set`, read by `get`
+    shadow_env: HashMap<&'a str, Value>,
+    cur_label: Option<&'a str>,
+    last_label: Option<&'a str>,
+    /// var of the caller receiving the return value
+    ret_dest: Option<&'a str>,
+}
+
+impl<'f, 'a> Frame<'f, 'a> {
+    fn new(info: &'f FuncInfo<'a>, args: Vec<Value>, ret_dest: Option<&'a str>) -> Self {
+        let env = info
+            .func
+            .args
+            .iter()
+            .flatten()
+            .map(|param| param.name.as_str())
+            .zip(args)
+            .collect();
+        Self {
+            info,
+            pc: 0,
+            env,
+            shadow_env: HashMap::new(),
+            cur_label: None,
+            last_label: None,
+            ret_dest,
+        }
+    }
+
+    fn get(&self, var: &str) -> Result<Value, InterpError> {
+        self.env
+            .get(var)
+            .copied()
+            .ok_or_else(|| InterpError::UndefinedVar(var.to_string()))
+    }
+
+    fn jump(&mut self, label: &str) -> Result<(), InterpError> {
+        self.pc = *self
+            .info
+            .labels
+            .get(label)
+            .ok_or_else(|| InterpError::UndefinedLabel(label.to_string()))?;
+        Ok(())
+    }
+}
+
+/// what the frame on top of the stack does next
+enum Action<'a> {
+    Next,
+    Call {
+        func: &'a str,
+        args: Vec<Value>,
+        dest: Option<&'a str>,
+    },
+    Return(Option<Value>),
+}
+
+pub struct Interpreter<'a, W: Write> {
+    funcs: HashMap<&'a str, FuncInfo<'a>>,
+    out: &'a mut W,
+    heap: Heap,
+    profile: Profile,
+    step_limit: Option<u64>,
+}
+
+impl<'a, W: Write> Interpreter<'a, W> {
+    pub fn new(prog: &'a Prog, out: &'a mut W) -> Self {
+        let funcs = prog
+            .functions
+            .iter()
+            .map(|func| {
+                let labels = func
+                    .instrs
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, inst)| match inst {
+                        LabelOrInst::Label { label } => Some((label.as_str(), i)),
+                        _ => None,
+                    })
+                    .collect();
+                (func.name.as_str(), FuncInfo { func, labels })
+            })
+            .collect();
+        Self {
+            funcs,
+            out,
+            heap: Heap::default(),
+            profile: Profile::default(),
+            step_limit: None,
+        }
+    }
+
+    /// stop with [`InterpError::StepLimit`] instead of running forever
+    pub fn step_limit(mut self, limit: u64) -> Self {
+        self.step_limit = Some(limit);
+        self
+    }
+
+    /// frames live on an explicit stack, deep bril recursion never exhausts the native one
+    pub fn run(mut self, entry: &Function, args: Vec<Value>) -> Result<Profile, InterpError> {
+        let funcs = std::mem::take(&mut self.funcs);
+        let entry = funcs
+            .get(entry.name.as_str())
+            .ok_or_else(|| InterpError::UndefinedFunc(entry.name.clone()))?;
+        let mut stack = vec![Frame::new(entry, args, None)];
+        while let Some(frame) = stack.last_mut() {
+            let action = match frame.info.func.instrs.get(frame.pc) {
+                None => {
+                    if frame.info.func.ty.is_some() {
+                        return Err(InterpError::ImplicitReturn(frame.info.func.name.clone()));
+                    }
+                    Action::Return(None)
+                }
+                Some(LabelOrInst::Label { label }) => {
+                    frame.last_label = frame.cur_label;
+                    frame.cur_label = Some(label);
+                    frame.pc += 1;
+                    Action::Next
+                }
+                Some(inst) => {
+                    if self.step_limit == Some(self.profile.total_dyn_inst) {
+                        return Err(InterpError::StepLimit(self.profile.total_dyn_inst));
+                    }
+                    self.profile.total_dyn_inst += 1;
+                    *self
+                        .profile
+                        .dyn_inst_per_func
+                        .entry(frame.info.func.name.clone())
+                        .or_default() += 1;
+                    frame.pc += 1;
+                    Self::exec(frame, inst, &mut *self.out, &mut self.heap)?
+                }
+            };
+            match action {
+                Action::Next => {}
+                Action::Call { func, args, dest } => {
+                    let callee = funcs
+                        .get(func)
+                        .ok_or_else(|| InterpError::UndefinedFunc(func.to_string()))?;
+                    let num_params = callee.func.args.as_ref().map_or(0, Vec::len);
+                    if num_params != args.len() {
+                        return Err(InterpError::ArgArity {
+                            func: func.to_string(),
+                            expected: num_params,
+                            got: args.len(),
+                        });
+                    }
+                    stack.push(Frame::new(callee, args, dest));
+                }
+                Action::Return(value) => {
+                    let returned = stack.pop().unwrap();
+                    if let (Some(dest), Some(caller)) = (returned.ret_dest, stack.last_mut()) {
+                        let value = value.ok_or_else(|| {
+                            InterpError::NoReturnValue(returned.info.func.name.clone())
+                        })?;
+                        caller.env.insert(dest, value);
+                    }
+                }
+            }
+        }
+        let leaks = self.heap.leaks();
+        if !leaks.is_empty() {
+            return Err(InterpError::Leaked(leaks));
+        }
+        self.profile.alloc_per_func = std::mem::take(&mut self.heap.stats);
+        Ok(self.profile)
+    }
+
+    fn exec(
+        frame: &mut Frame<'_, 'a>,
+        inst: &'a LabelOrInst,
+        out: &mut W,
+        heap: &mut Heap,
+    ) -> Result<Action<'a>, InterpError> {
+        let LabelOrInst::Inst {
+            op,
+            dest,
+            args,
+            funcs,
+            labels,
+            value,
+            ..
+        } = inst
+        else {
+            unreachable!()
+        };
+        let args: &'a [String] = args.as_deref().unwrap_or_default();
+        let labels: &'a [String] = labels.as_deref().unwrap_or_default();
+        let malformed = || InterpError::MalformedInst(op.clone());
+        let arg = |i: usize| -> Result<Value, InterpError> {
+            frame.get(args.get(i).ok_or_else(malformed)?)
+        };
+        let int_arg = |i: usize| match arg(i)? {
+            Value::Int(v) => Ok(v),
+            got => Err(InterpError::TypeMismatch {
+                op: op.clone(),
+                expected: "int",
+                got,
+            }),
+        };
+        let bool_arg = |i: usize| match arg(i)? {
+            Value::Bool(v) => Ok(v),
+            got => Err(InterpError::TypeMismatch {
+                op: op.clone(),
+                expected: "bool",
+                got,
+            }),
+        };
+
+        let ptr_arg = |i: usize| match arg(i)? {
+            Value::Ptr(v) => Ok(v),
+            got => Err(InterpError::TypeMismatch {
+                op: op.clone(),
+                expected: "ptr",
+                got,
+            }),
+        };
+
+        let result = match op.as_str() {
+            "const" => Value::from(value.ok_or_else(malformed)?),
+            "id" => arg(0)?,
+            "add" => Value::Int(int_arg(0)?.wrapping_add(int_arg(1)?)),
+            "sub" => Value::Int(int_arg(0)?.wrapping_sub(int_arg(1)?)),
+            "mul" => Value::Int(int_arg(0)?.wrapping_mul(int_arg(1)?)),
+            "div" => {
+                let (lhs, rhs) = (int_arg(0)?, int_arg(1)?);
+                if rhs == 0 {
+                    return Err(InterpError::DivisionByZero);
+                }
+                Value::Int(lhs.wrapping_div(rhs))
+            }
+            "eq" => Value::Bool(int_arg(0)? == int_arg(1)?),
+            "lt" => Value::Bool(int_arg(0)? < int_arg(1)?),
+            "gt" => Value::Bool(int_arg(0)? > int_arg(1)?),
+            "le" => Value::Bool(int_arg(0)? <= int_arg(1)?),
+            "ge" => Value::Bool(int_arg(0)? >= int_arg(1)?),
+            "not" => Value::Bool(!bool_arg(0)?),
+            "and" => Value::Bool(bool_arg(0)? && bool_arg(1)?),
+            "or" => Value::Bool(bool_arg(0)? || bool_arg(1)?),
+            "print" => {
+                let values = (0..args.len())
+                    .map(arg)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                writeln!(out, "{values}").map_err(|e| InterpError::Output(e.to_string()))?;
+                return Ok(Action::Next);
+            }
+            "nop" => return Ok(Action::Next),
+            "jmp" => {
+                frame.jump(labels.first().ok_or_else(malformed)?)?;
+                return Ok(Action::Next);
+            }
+            "br" => {
+                let target = if bool_arg(0)? {
+                    labels.first()
+                } else {
+                    labels.get(1)
+                };
+                frame.jump(target.ok_or_else(malformed)?)?;
+                return Ok(Action::Next);
+            }
+            "ret" => {
+                let value = args.first().map(|var| frame.get(var)).transpose()?;
+                return Ok(Action::Return(value));
+            }
+            "call" => {
+                let func = funcs
+                    .as_deref()
+                    .and_then(<[String]>::first)
+                    .ok_or_else(malformed)?;
+                let args = (0..args.len()).map(arg).collect::<Result<_, _>>()?;
+                return Ok(Action::Call {
+                    func,
+                    args,
+                    dest: dest.as_deref(),
+                });
+            }
+            "set" => {
+                let shadow = args.first().ok_or_else(malformed)?;
+                let src = args.get(1).ok_or_else(malformed)?;
+                // setting an undefined value leaves the shadow undefined, as `brili` does
+                match frame.env.get(src.as_str()) {
+                    Some(&value) => frame.shadow_env.insert(shadow, value),
+                    None => frame.shadow_env.remove(shadow.as_str()),
+                };
+                return Ok(Action::Next);
+            }
+            "get" => {
+                let dest = dest.as_deref().ok_or_else(malformed)?;
+                match frame.shadow_env.get(dest) {
+                    Some(&value) => value,
+                    // never set along the path taken, reading it later is an error
+                    None => {
+                        frame.env.remove(dest);
+                        return Ok(Action::Next);
+                    }
+                }
+            }
+            "undef" => {
+                frame.env.remove(dest.as_deref().ok_or_else(malformed)?);
+                return Ok(Action::Next);
+            }
+            "phi" => {
+                let dest = dest.as_deref().ok_or_else(malformed)?;
+                let incoming = frame
+                    .last_label
+                    .and_then(|last| labels.iter().position(|label| label == last))
+                    .and_then(|i| args.get(i))
+                    .and_then(|var| frame.env.get(var.as_str()).copied());
+                match incoming {
+                    Some(value) => value,
+                    None => {
+                        frame.env.remove(dest);
+                        return Ok(Action::Next);
+                    }
+                }
+            }
+            "alloc" => Value::Ptr(heap.alloc(int_arg(0)?, &frame.info.func.name)?),
+            "free" => {
+                heap.free(ptr_arg(0)?)?;
+                return Ok(Action::Next);
+            }
+            "store" => {
+                heap.store(ptr_arg(0)?, arg(1)?)?;
+                return Ok(Action::Next);
+            }
+            "load" => heap.load(ptr_arg(0)?)?,
+            "ptradd" => {
+                let ptr = ptr_arg(0)?;
+                Value::Ptr(Pointer {
+                    offset: ptr.offset.wrapping_add(int_arg(1)?),
+                    ..ptr
+                })
+            }
+            _ => return Err(InterpError::UnknownOp(op.clone())),
+        };
+        let dest = dest.as_deref().ok_or_else(malformed)?;
+        frame.env.insert(dest, result);
+        Ok(Action::Next)
+    }
+}
@@ -1,7 +1,11 @@
 pub mod analyzer;
 pub mod bril;
 pub mod cfg;
+pub mod fuzz;
+pub mod interp;
 pub mod optim;
+pub mod pipeline;
+pub mod reduce;
 pub mod transform;
 
 mod graphviz_prelude {
@@ -0,0 +1,319 @@
+//! aggressive dead code elimination, mark-and-sweep on ssa
+//!
+//! Rather than deleting what is proven dead, only what is proven live is kept
+//!   - `print`, `ret`, calls and memory writes are live
+//!   - defs of the args of a live instr are live, for a live `get` every `set` of its shadow
+//!   - branches that a block holding live instrs is control dependent on are live
+//!
+//! Everything else is swept. A dead `br` becomes a `jmp` to its nearest post-dominator
+//! holding live instrs, so loops and conditionals computing nothing observable are skipped
+//! .loop:
+//!     i.1: int = add i.0 one;
+//!     c: bool = lt i.1 n;
+//!     br c .loop .exit;                 => jmp .exit;
+//! .exit:
+//!     print n;
+//!
+//! As usual for adce, loops that never terminate but have no observable effect are removed.
+use crate::analyzer::postdom::{ControlDependenceGraph, PostDomTree};
+use crate::bril::LabelOrInst;
+use crate::cfg::{Cfg, NodePtr};
+use crate::optim::fuel;
+use crate::transform::cytron::{self, PhiPlacement};
+use crate::transform::{phi, phi::SsaForm, ssa};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// accepts cfg in either `phi` or `set`/`get` form and keeps the encoding,
+/// cfg not in ssa is converted into pruned ssa and back, which adds no copies of its own
+/// when adce leaves the program unchanged
+pub fn aggressive_dce(cfg: Cfg) -> Cfg {
+    match phi::ssa_form_of(&cfg) {
+        Some(SsaForm::SetGet) => adce_on_ssa(cfg),
+        Some(SsaForm::Phi) => phi::set_get_into_phi(adce_on_ssa(phi::phi_into_set_get(cfg))),
+        None => ssa::cfg_from_ssa(adce_on_ssa(cytron::cfg_into_ssa_cytron(
+            cfg,
+            PhiPlacement::Pruned,
+        ))),
+    }
+}
+
+fn adce_on_ssa(mut cfg: Cfg) -> Cfg {
+    let ctx = AdceCtx::new(&cfg);
+    let mut marker = Marker::new(&ctx);
+    // a branch of a block that never reaches the exit has no post-dominator to skip to
+    for (blk, ipdom) in ctx.ipdoms.iter().enumerate().take(ctx.exit) {
+        if ipdom.is_none() {
+            marker.mark_branch(blk);
+        }
+    }
+    marker.propagate();
+    // sweeping an instr, or turning a dead branch into a jump, is a rewrite,
+    // refused ones are kept live together with what they depend on,
+    // a `set` is swept along with the `get` of its shadow
+    for (blk, instrs) in ctx.instrs.iter().enumerate() {
+        for (idx, inst) in instrs.iter().enumerate() {
+            let swept = match inst {
+                LabelOrInst::Inst { op, .. } => {
+                    !matches!(op.as_str(), "jmp" | "set") && !marker.live[blk][idx]
+                }
+                LabelOrInst::Label { .. } => false,
+            };
+            if swept && !fuel::consume(|| format!("adce: sweep `{inst}`")) {
+                marker.mark(blk, idx);
+                marker.propagate();
+            }
+        }
+    }
+
+    let redirects = loop {
+        let mut redirects = HashMap::new();
+        let mut unresolved = vec![];
+        for blk in 0..ctx.exit {
+            if ctx.branch_of(blk).is_none_or(|idx| marker.live[blk][idx]) {
+                continue;
+            }
+            match ctx.nearest_live_post_dominator(blk, &marker.blk_live) {
+                Some(target) => {
+                    redirects.insert(blk, target);
+                }
+                None => unresolved.push(blk),
+            }
+        }
+        if unresolved.is_empty() {
+            break redirects;
+        }
+        // paths leaving the block end at different exits, the branch is kept
+        for blk in unresolved {
+            marker.mark_branch(blk);
+        }
+        marker.propagate();
+    };
+
+    // unlabeled targets are only labeled after sweeping, instr indices stay valid till then
+    let mut labels: HashMap<usize, (String, bool)> = HashMap::new();
+    for &target in redirects.values() {
+        let label = cfg.nodes[target].lock().unwrap().label.clone();
+        let label = match label {
+            Some(label) => (label, false),
+            None => (
+                cfg.fresh_label(&format!("{}.blk.{target}", cfg.func_ctx.name)),
+                true,
+            ),
+        };
+        labels.insert(target, label);
+    }
+
+    let mut num_removed = 0;
+    for (blk, node) in cfg.nodes.iter().enumerate() {
+        let mut node_lock = node.lock().unwrap();
+        let instrs = std::mem::take(&mut node_lock.blk.instrs);
+        for (idx, inst) in instrs.into_iter().enumerate() {
+            match inst {
+                LabelOrInst::Label { .. } => node_lock.blk.instrs.push(inst),
+                LabelOrInst::Inst { ref op, .. } if op == "jmp" || marker.live[blk][idx] => {
+                    node_lock.blk.instrs.push(inst)
+                }
+                LabelOrInst::Inst { ref op, .. } if op == "br" => {
+                    let (target, _) = labels.get(redirects.get(&blk).unwrap()).unwrap();
+                    node_lock.blk.instrs.push(LabelOrInst::Inst {
+                        op: "jmp".to_string(),
+                        dest: None,
+                        ty: None,
+                        args: None,
+                        funcs: None,
+                        labels: Some(vec![target.clone()]),
+                        value: None,
+                    });
+                    num_removed += 1;
+                }
+                _ => num_removed += 1,
+            }
+        }
+    }
+    for (target, (label, fresh)) in labels {
+        if fresh {
+            let mut node_lock = cfg.nodes[target].lock().unwrap();
+            node_lock.blk.set_label(label.clone());
+            node_lock.label = Some(label);
+        }
+    }
+    eprintln!("{num_removed} inst removed by adce");
+    cfg.relink();
+    cfg.prune_unreachable();
+    cfg
+}
+
+struct AdceCtx {
+    instrs: Vec<Vec<LabelOrInst>>,
+    /// index of the virtual exit, every `ret` and the end of the function lead to it
+    exit: usize,
+    ipdoms: Vec<Option<usize>>,
+    /// blocks whose branch decides whether the block is executed
+    control_deps: Vec<Vec<usize>>,
+}
+
+impl AdceCtx {
+    fn new(cfg: &Cfg) -> Self {
+        let idx_of: HashMap<NodePtr, usize> = cfg
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (Arc::as_ptr(node), i))
+            .collect();
+        let exit = cfg.nodes.len();
+        let instrs = cfg
+            .nodes
+            .iter()
+            .map(|node| node.lock().unwrap().blk.instrs.clone())
+            .collect();
+        let post_dom_tree = PostDomTree::from_cfg(cfg);
+        let cdg = ControlDependenceGraph::from_post_dom_tree(&post_dom_tree);
+        let exit_ptr = post_dom_tree.exit_ptr();
+        let idx_of = |ptr: NodePtr| {
+            if ptr == exit_ptr {
+                exit
+            } else {
+                *idx_of.get(&ptr).unwrap()
+            }
+        };
+        let mut ipdoms = vec![None; exit + 1];
+        let mut control_deps = vec![vec![]; exit + 1];
+        for node in &cfg.nodes {
+            let blk = idx_of(Arc::as_ptr(node));
+            ipdoms[blk] = post_dom_tree
+                .immediate_post_dominator(Arc::as_ptr(node))
+                .map(idx_of);
+            control_deps[blk] = cdg
+                .controllers_of(Arc::as_ptr(node))
+                .iter()
+                .map(|&ptr| idx_of(ptr))
+                .collect();
+        }
+        Self {
+            instrs,
+            exit,
+            ipdoms,
+            control_deps,
+        }
+    }
+
+    fn branch_of(&self, blk: usize) -> Option<usize> {
+        match self.instrs[blk].last() {
+            Some(LabelOrInst::Inst { op, .. }) if op == "br" => Some(self.instrs[blk].len() - 1),
+            _ => None,
+        }
+    }
+
+    /// `None` if no block holding live instrs post-dominates `blk` before the virtual exit
+    fn nearest_live_post_dominator(&self, blk: usize, blk_live: &[bool]) -> Option<usize> {
+        let mut cur = self.ipdoms[blk]?;
+        while cur != self.exit {
+            // the end of the function is a valid target even if nothing there is live
+            let falls_off = cur == self.exit - 1
+                && !matches!(
+                    self.instrs[cur].last(),
+                    Some(LabelOrInst::Inst { op, .. }) if matches!(op.as_str(), "jmp" | "br")
+                );
+            if blk_live[cur] || falls_off {
+                return Some(cur);
+            }
+            cur = self.ipdoms[cur]?;
+        }
+        None
+    }
+}
+
+struct Marker<'a> {
+    ctx: &'a AdceCtx,
+    live: Vec<Vec<bool>>,
+    blk_live: Vec<bool>,
+    worklist: Vec<(usize, usize)>,
+    def_of: HashMap<&'a str, (usize, usize)>,
+    sets_of: HashMap<&'a str, Vec<(usize, usize)>>,
+}
+
+impl<'a> Marker<'a> {
+    fn new(ctx: &'a AdceCtx) -> Self {
+        let mut marker = Self {
+            ctx,
+            live: ctx
+                .instrs
+                .iter()
+                .map(|instrs| vec![false; instrs.len()])
+                .collect(),
+            blk_live: vec![false; ctx.exit],
+            worklist: vec![],
+            def_of: HashMap::new(),
+            sets_of: HashMap::new(),
+        };
+        for (blk, instrs) in ctx.instrs.iter().enumerate() {
+            for (idx, inst) in instrs.iter().enumerate() {
+                let LabelOrInst::Inst { op, dest, args, .. } = inst else {
+                    continue;
+                };
+                if let Some(dest) = dest {
+                    marker.def_of.insert(dest, (blk, idx));
+                }
+                match op.as_str() {
+                    "set" => marker
+                        .sets_of
+                        .entry(&args.as_ref().unwrap()[0])
+                        .or_default()
+                        .push((blk, idx)),
+                    "print" | "ret" | "call" | "store" | "free" | "speculate" | "commit"
+                    | "guard" => marker.mark(blk, idx),
+                    _ => {}
+                }
+            }
+        }
+        marker
+    }
+
+    fn mark(&mut self, blk: usize, idx: usize) {
+        if !self.live[blk][idx] {
+            self.live[blk][idx] = true;
+            self.worklist.push((blk, idx));
+        }
+    }
+
+    fn mark_branch(&mut self, blk: usize) {
+        if let Some(idx) = self.ctx.branch_of(blk) {
+            self.mark(blk, idx);
+        }
+    }
+
+    fn propagate(&mut self) {
+        while let Some((blk, idx)) = self.worklist.pop() {
+            let LabelOrInst::Inst { op, dest, args, .. } = &self.ctx.instrs[blk][idx] else {
+                unreachable!()
+            };
+            let mut deps = vec![];
+            match op.as_str() {
+                // the shadow written by `set` is not a use
+                "set" => deps.extend(self.def_of.get(args.as_ref().unwrap()[1].as_str())),
+                "get" => deps.extend(
+                    self.sets_of
+                        .get(dest.as_ref().unwrap().as_str())
+                        .into_iter()
+                        .flatten(),
+                ),
+                _ => deps.extend(
+                    args.iter()
+                        .flatten()
+                        .filter_map(|arg| self.def_of.get(arg.as_str())),
+                ),
+            }
+            for (dep_blk, dep_idx) in deps {
+                self.mark(dep_blk, dep_idx);
+            }
+            if !self.blk_live[blk] {
+                self.blk_live[blk] = true;
+                for dep_blk in self.ctx.control_deps[blk].clone() {
+                    self.mark_branch(dep_blk);
+                }
+            }
+        }
+    }
+}
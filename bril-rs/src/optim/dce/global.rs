@@ -61,9 +61,7 @@ impl<'a> WorkListAlgo for ReachingDefAnalysis<'a> {
     fn transfer(&mut self, node: &NodeRef, in_flow: Option<Self::InFlowType>) -> Self::OutFlowType {
         let mut out_flow = in_flow.unwrap_or_else(|| {
             if Arc::as_ptr(node) == Weak::as_ptr(&self.0.root) {
-                Self::InFlowType::from_iter(
-                    self.0.func_ctx.args_name().unwrap_or_default().into_iter(),
-                )
+                Self::InFlowType::from_iter(self.0.func_ctx.args_name().unwrap_or_default())
             } else {
                 HashSet::new()
             }
@@ -1,10 +1,13 @@
 //! basic dead-code-elimination algorithm, which is able to
 //!   - delete unused var
 //!   - compile time const folding
+//!   - delete dead calls to functions without side effect
+pub mod adce;
 pub mod global;
-use crate::analyzer;
+use crate::analyzer::{self, effect::EffectSummary};
 use crate::bril::{LabelOrInst, ValueLit};
 use crate::cfg::{BasicBlock, Cfg, NodePtr};
+use crate::optim::fuel;
 
 use std::collections::{HashMap, HashSet};
 use std::default::Default;
@@ -15,7 +18,14 @@ use std::sync::{
 
 static RENAME_COUNTER: AtomicUsize = AtomicUsize::new(7654);
 
+/// every call is conservatively treated as effectful
 pub fn dce(cfg: Cfg, global_const_folding: bool) -> Cfg {
+    effect_aware_dce(cfg, global_const_folding, &EffectSummary::default())
+}
+
+/// dead calls are deleted if the callee has no side effect according to `effects`,
+/// calls to pure functions are also numbered by lvn
+pub fn effect_aware_dce(cfg: Cfg, global_const_folding: bool, effects: &EffectSummary) -> Cfg {
     let global_const_folding_ctx = if global_const_folding {
         Some(analyzer::find_global_const_folding_ctx(&cfg))
     } else {
@@ -27,7 +37,7 @@ pub fn dce(cfg: Cfg, global_const_folding: bool) -> Cfg {
         let node_ptr = Arc::as_ptr(node);
         let mut node_lock = node.lock().unwrap();
 
-        let vn_ctx_builder = ValueNumberingCtxBuilder::new();
+        let vn_ctx_builder = ValueNumberingCtxBuilder::new().effect_summary(effects);
         let vn_ctx = if global_const_folding {
             let const_folding_ctx = global_const_folding_ctx
                 .as_ref()
@@ -48,15 +58,19 @@ pub fn dce(cfg: Cfg, global_const_folding: bool) -> Cfg {
         let node_ptr = Arc::as_ptr(node);
         let mut node_lock = node.lock().unwrap();
         let delete_live_on_exit = unused_dangling_vars.get(&node_ptr).unwrap();
-        node_lock.blk = dce_on_blk(node_lock.blk.clone(), delete_live_on_exit);
+        node_lock.blk = dce_on_blk(node_lock.blk.clone(), delete_live_on_exit, effects);
     }
     cfg
 }
 
-fn dce_on_blk(mut blk: BasicBlock, delete_live_on_exit: &HashSet<String>) -> BasicBlock {
+fn dce_on_blk(
+    mut blk: BasicBlock,
+    delete_live_on_exit: &HashSet<String>,
+    effects: &EffectSummary,
+) -> BasicBlock {
     let mut updated;
     loop {
-        (blk, updated) = dce_on_blk_one_pass(blk, delete_live_on_exit);
+        (blk, updated) = dce_on_blk_one_pass(blk, delete_live_on_exit, effects);
         if !updated {
             break blk;
         }
@@ -66,6 +80,7 @@ fn dce_on_blk(mut blk: BasicBlock, delete_live_on_exit: &HashSet<String>) -> Bas
 fn dce_on_blk_one_pass(
     mut blk: BasicBlock,
     delete_live_on_exit: &HashSet<String>,
+    effects: &EffectSummary,
 ) -> (BasicBlock, bool) {
     let mut to_be_deleted = vec![];
     let mut unused_variable: HashMap<String, usize> = HashMap::new();
@@ -79,17 +94,28 @@ fn dce_on_blk_one_pass(
                 let _ = unused_variable.remove(arg);
             }
         }
-        if let LabelOrInst::Inst {
-            dest: Some(dest), ..
-        } = &inst
-        {
-            if let Some(last_assign_idx) = unused_variable.insert(dest.clone(), i) {
-                to_be_deleted.push(last_assign_idx);
+        match &inst {
+            LabelOrInst::Inst {
+                dest: Some(dest), ..
+            } => {
+                if let Some(last_assign_idx) = unused_variable.insert(dest.clone(), i) {
+                    to_be_deleted.push(last_assign_idx);
+                }
+                if !effects.is_removable(inst) {
+                    // side effect, trap or divergence has to be kept even if the result is never used
+                    unused_variable.remove(dest);
+                }
+            }
+            LabelOrInst::Inst { op, .. } if op == "call" && effects.is_removable(inst) => {
+                // result of a call without dest is never used
+                to_be_deleted.push(i);
             }
+            _ => {}
         }
     }
     unused_variable.retain(|var, _| delete_live_on_exit.contains(var));
     to_be_deleted.extend(unused_variable.into_values());
+    to_be_deleted.retain(|&idx| fuel::consume(|| format!("dce: delete `{}`", blk.instrs[idx])));
     let updated = !to_be_deleted.is_empty();
 
     if updated {
@@ -98,7 +124,7 @@ fn dce_on_blk_one_pass(
             .into_iter()
             .enumerate()
             .filter_map(|(idx, inst)| {
-                if to_be_deleted.iter().any(|&x| x == idx) {
+                if to_be_deleted.contains(&idx) {
                     None
                 } else {
                     Some(inst)
@@ -147,6 +173,8 @@ pub struct ValueNumberingCtx {
     var2numbering: HashMap<String, Arc<NumTableEntry>>,
     next_number: usize,
     const_folding: bool,
+    /// calls to these functions are numbered like any other deterministic op
+    pure_funcs: HashSet<String>,
 }
 
 pub struct ValueNumberingCtxBuilder(ValueNumberingCtx);
@@ -181,6 +209,11 @@ impl ValueNumberingCtxBuilder {
         self
     }
 
+    pub fn effect_summary(mut self, effects: &EffectSummary) -> Self {
+        self.0.pure_funcs = effects.pure_total_funcs();
+        self
+    }
+
     pub fn finish(self) -> ValueNumberingCtx {
         self.0
     }
@@ -189,89 +222,75 @@ impl ValueNumberingCtxBuilder {
 impl ValueNumberingCtx {
     pub fn numbering_scan(&mut self, mut blk: BasicBlock) -> BasicBlock {
         for inst in &mut blk.instrs {
-            if let LabelOrInst::Inst {
-                op,
-                args,
-                dest,
-                value,
-                ..
-            } = inst
-            {
-                if op == "const" {
-                    // op of const is considered to be `id`, so that later query of id `dest` will
-                    // be routed here
-                    let dest = dest.clone().unwrap();
+            let original = inst.clone();
+            self.number_inst(inst);
+            // numbering still describes the original inst, so later rewrites stay valid if this one is refused
+            if *inst != original && !fuel::consume(|| format!("lvn: `{original}` -> `{inst}`")) {
+                *inst = original;
+            }
+        }
+        blk
+    }
+
+    fn number_inst(&mut self, inst: &mut LabelOrInst) {
+        if let LabelOrInst::Inst {
+            op,
+            args,
+            dest,
+            funcs,
+            value,
+            ..
+        } = inst
+        {
+            if op == "const" {
+                // op of const is considered to be `id`, so that later query of id `dest` will
+                // be routed here
+                let dest = dest.clone().unwrap();
+                let new_entry = Arc::new(NumTableEntry {
+                    numbering: self.next_number,
+                    canonical_var: dest.clone(),
+                    const_lit: *value,
+                });
+                let _ = self.var2numbering.insert(dest, new_entry);
+                self.next_number += 1;
+            } else if op == "id" {
+                let arg = &args.as_ref().unwrap()[0];
+                let dest = dest.clone().unwrap();
+                if let Some(num_entry) = self.var2numbering.get(arg) {
+                    if self.const_folding && num_entry.const_lit.is_some() {
+                        *args = None;
+                        *op = "const".to_string();
+                        *value = num_entry.const_lit;
+                    } else {
+                        *args = Some(vec![num_entry.canonical_var.clone()]);
+                    }
+                    let _ = self.var2numbering.insert(dest, Arc::clone(num_entry));
+                } else {
+                    // otherwise, arg coming from upperstream basic block, we do not do anything
                     let new_entry = Arc::new(NumTableEntry {
                         numbering: self.next_number,
-                        canonical_var: dest.clone(),
-                        const_lit: *value,
+                        canonical_var: arg.clone(),
+                        const_lit: None,
                     });
                     let _ = self.var2numbering.insert(dest, new_entry);
                     self.next_number += 1;
-                } else if op == "id" {
-                    let arg = &args.as_ref().unwrap()[0];
-                    let dest = dest.clone().unwrap();
-                    if let Some(num_entry) = self.var2numbering.get(arg) {
-                        if self.const_folding && num_entry.const_lit.is_some() {
-                            *args = None;
-                            *op = "const".to_string();
-                            *value = num_entry.const_lit;
-                        } else {
-                            *args = Some(vec![num_entry.canonical_var.clone()]);
-                        }
-                        let _ = self.var2numbering.insert(dest, Arc::clone(num_entry));
-                    } else {
-                        // otherwise, arg coming from upperstream basic block, we do not do anything
-                        let new_entry = Arc::new(NumTableEntry {
-                            numbering: self.next_number,
-                            canonical_var: arg.clone(),
-                            const_lit: None,
-                        });
-                        let _ = self.var2numbering.insert(dest, new_entry);
-                        self.next_number += 1;
-                    }
-                } else if let Some(dest) = dest {
-                    // for function call, return value may be different even the (call, func, *args) tuple is the same
-                    if op == "call" {
-                        let new_entry = Arc::new(NumTableEntry {
-                            numbering: self.next_number,
-                            canonical_var: dest.clone(),
-                            const_lit: None,
-                        });
-                        self.next_number += 1;
-                        let _ = self.var2numbering.insert(dest.clone(), new_entry);
-                        continue;
-                    }
-
-                    // remaining are deterministic ops, for example add, lt, mul, ...
-                    let args_lit = &args.as_ref().unwrap();
-                    match self.numbering_query(op, args_lit) {
+                }
+            } else if let Some(dest) = dest {
+                if op == "call" && self.pure_funcs.contains(&funcs.as_ref().unwrap()[0]) {
+                    // pure function call is numbered as op `call @func`
+                    let op_with_func = format!("call @{}", funcs.as_ref().unwrap()[0]);
+                    let args_lit = args.clone().unwrap_or_default();
+                    match self.numbering_query(&op_with_func, &args_lit) {
                         Ok(num_entry) => {
-                            if self.const_folding && num_entry.const_lit.is_some() {
-                                *args = None;
-                                *op = "const".to_string();
-                                *value = num_entry.const_lit;
-                            } else {
-                                *op = "id".to_string();
-                                *args = Some(vec![num_entry.canonical_var.clone()]);
-                            }
+                            *op = "id".to_string();
+                            *funcs = None;
+                            *args = Some(vec![num_entry.canonical_var.clone()]);
                             self.var2numbering.insert(dest.clone(), num_entry);
                         }
                         Err(canon_form) => {
-                            // not found entry
-                            let const_lit = if self.const_folding {
-                                self.try_eval_const_expr(op, args_lit)
-                            } else {
-                                None
-                            };
-                            self.insert_new_numbering(dest, canon_form, const_lit);
-                            // this might fallback to another branch if any of the args can not be const evaled
-                            if let Some(const_lit) = const_lit {
-                                *op = "const".to_string();
-                                *args = None;
-                                *value = Some(const_lit);
-                            } else {
-                                let reduced_args = args_lit
+                            self.insert_new_numbering(dest, canon_form, None);
+                            if let Some(args) = args {
+                                *args = args
                                     .iter()
                                     .map(|arg| {
                                         self.var2numbering.get(arg).map_or(arg.clone(), |entry| {
@@ -279,23 +298,74 @@ impl ValueNumberingCtx {
                                         })
                                     })
                                     .collect();
-                                *args = Some(reduced_args);
                             }
                         }
-                    };
-                } else if let Some(ref mut args) = args {
-                    *args = args
-                        .iter()
-                        .map(|arg| {
-                            self.var2numbering
-                                .get(arg)
-                                .map_or(arg.clone(), |entry| entry.canonical_var.clone())
-                        })
-                        .collect();
+                    }
+                    return;
+                }
+                // for function call, return value may be different even the (call, func, *args) tuple is the same
+                if op == "call" {
+                    let new_entry = Arc::new(NumTableEntry {
+                        numbering: self.next_number,
+                        canonical_var: dest.clone(),
+                        const_lit: None,
+                    });
+                    self.next_number += 1;
+                    let _ = self.var2numbering.insert(dest.clone(), new_entry);
+                    return;
                 }
+
+                // remaining are deterministic ops, for example add, lt, mul, ...
+                let args_lit = &args.as_ref().unwrap();
+                match self.numbering_query(op, args_lit) {
+                    Ok(num_entry) => {
+                        if self.const_folding && num_entry.const_lit.is_some() {
+                            *args = None;
+                            *op = "const".to_string();
+                            *value = num_entry.const_lit;
+                        } else {
+                            *op = "id".to_string();
+                            *args = Some(vec![num_entry.canonical_var.clone()]);
+                        }
+                        self.var2numbering.insert(dest.clone(), num_entry);
+                    }
+                    Err(canon_form) => {
+                        // not found entry
+                        let const_lit = if self.const_folding {
+                            self.try_eval_const_expr(op, args_lit)
+                        } else {
+                            None
+                        };
+                        self.insert_new_numbering(dest, canon_form, const_lit);
+                        // this might fallback to another branch if any of the args can not be const evaled
+                        if let Some(const_lit) = const_lit {
+                            *op = "const".to_string();
+                            *args = None;
+                            *value = Some(const_lit);
+                        } else {
+                            let reduced_args = args_lit
+                                .iter()
+                                .map(|arg| {
+                                    self.var2numbering
+                                        .get(arg)
+                                        .map_or(arg.clone(), |entry| entry.canonical_var.clone())
+                                })
+                                .collect();
+                            *args = Some(reduced_args);
+                        }
+                    }
+                };
+            } else if let Some(ref mut args) = args {
+                *args = args
+                    .iter()
+                    .map(|arg| {
+                        self.var2numbering
+                            .get(arg)
+                            .map_or(arg.clone(), |entry| entry.canonical_var.clone())
+                    })
+                    .collect();
             }
         }
-        blk
     }
 
     /// create new numbering for variable `dest`
@@ -353,68 +423,51 @@ impl ValueNumberingCtx {
         for arg in args {
             const_binding.push(self.var2numbering.get(arg)?.const_lit?);
         }
-        match op {
-            "id" => {
-                assert!(const_binding.len() == 1);
-                Some(const_binding[0])
-            }
-            "add" => {
-                assert!(const_binding.len() == 2);
-                if let (ValueLit::Int(a1), ValueLit::Int(a2)) = (const_binding[0], const_binding[1])
-                {
-                    Some(ValueLit::Int(a1 + a2))
-                } else {
-                    unreachable!()
-                }
-            }
-            "sub" => {
-                assert!(const_binding.len() == 2);
-                if let (ValueLit::Int(a1), ValueLit::Int(a2)) = (const_binding[0], const_binding[1])
-                {
-                    Some(ValueLit::Int(a1 - a2))
-                } else {
-                    unreachable!()
-                }
-            }
-            "mul" => {
-                assert!(const_binding.len() == 2);
-                if let (ValueLit::Int(a1), ValueLit::Int(a2)) = (const_binding[0], const_binding[1])
-                {
-                    Some(ValueLit::Int(a1 * a2))
-                } else {
-                    unreachable!()
-                }
-            }
-            "div" => {
-                assert!(const_binding.len() == 2);
-                if let (ValueLit::Int(a1), ValueLit::Int(a2)) = (const_binding[0], const_binding[1])
-                {
-                    Some(ValueLit::Int(a1 / a2))
-                } else {
-                    unreachable!()
-                }
+        eval_const_expr(op, &const_binding)
+    }
+}
+
+/// compile time evaluation of int arithmetic, division by zero is left to runtime,
+/// so is a result out of the range of `ValueLit::Int`, bril ints are 64-bit and would not wrap
+pub(crate) fn eval_const_expr(op: &str, const_binding: &[ValueLit]) -> Option<ValueLit> {
+    match op {
+        "id" => {
+            assert!(const_binding.len() == 1);
+            Some(const_binding[0])
+        }
+        "add" | "sub" | "mul" | "div" => {
+            assert!(const_binding.len() == 2);
+            let (ValueLit::Int(a1), ValueLit::Int(a2)) = (const_binding[0], const_binding[1])
+            else {
+                unreachable!()
+            };
+            match op {
+                "add" => a1.checked_add(a2).map(ValueLit::Int),
+                "sub" => a1.checked_sub(a2).map(ValueLit::Int),
+                "mul" => a1.checked_mul(a2).map(ValueLit::Int),
+                _ => a1.checked_div(a2).map(ValueLit::Int),
             }
-            _ => None,
         }
+        _ => None,
     }
 }
 
-#[derive(Eq, Hash, PartialEq, Debug)]
-struct CanonicalForm {
+#[derive(Clone, Eq, Hash, PartialEq, Debug)]
+pub(crate) struct CanonicalForm {
     op: String,
     // associativity is exploited
     numbered_args: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
-struct NumTableEntry {
-    canonical_var: String,
-    const_lit: Option<ValueLit>,
-    numbering: usize,
+pub(crate) struct NumTableEntry {
+    pub(crate) canonical_var: String,
+    pub(crate) const_lit: Option<ValueLit>,
+    pub(crate) numbering: usize,
 }
 
 impl CanonicalForm {
-    fn from_op_and_numbered_args(op: &str, numbered_args: &[String]) -> Self {
+    pub(crate) fn from_op_and_numbered_args(op: &str, numbered_args: &[String]) -> Self {
         let mut numbered_args: Vec<String> = numbered_args.to_vec();
         if matches!(op, "add" | "mul") {
             numbered_args.sort()
@@ -0,0 +1,70 @@
+//! optimization fuel, every individual rewrite of a pass asks for one unit before it is performed
+//!
+//! Outside of [`with_budget`] fuel never runs out, so passes behave as usual. Inside, rewrites are
+//! granted until the budget is spent and refused from then on, a refused rewrite leaves the code
+//! as it was. Binary searching the budget of a pipeline that miscompiles a program therefore
+//! narrows the miscompile down to the first bad rewrite, see `DiffTester::bisect`.
+//!
+//! Only optional rewrites consume fuel, moving into and out of ssa form is required by the passes
+//! that follow and always happens.
+use std::cell::RefCell;
+
+thread_local! {
+    static TANK: RefCell<Option<Tank>> = const { RefCell::new(None) };
+}
+
+struct Tank {
+    budget: u64,
+    tally: Tally,
+}
+
+/// what happened to the fuel during [`with_budget`]
+#[derive(Debug, Default, Clone)]
+pub struct Tally {
+    /// number of rewrites asking for fuel, including refused ones
+    pub requested: u64,
+    /// description of the last rewrite that was granted
+    pub last_granted: Option<String>,
+}
+
+/// runs `f` with `budget` units of fuel, returns its result with the tally of fuel requests
+pub fn with_budget<T>(budget: u64, f: impl FnOnce() -> T) -> (T, Tally) {
+    // restores the enclosing tank, also when `f` unwinds
+    struct Restore(Option<Tank>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let outer = self.0.take();
+            TANK.with(|tank| *tank.borrow_mut() = outer);
+        }
+    }
+
+    let outer = TANK.with(|tank| {
+        tank.borrow_mut().replace(Tank {
+            budget,
+            tally: Tally::default(),
+        })
+    });
+    let restore = Restore(outer);
+    let ret = f();
+    let tally = TANK.with(|tank| tank.borrow_mut().take().unwrap().tally);
+    drop(restore);
+    (ret, tally)
+}
+
+/// asks for one unit of fuel, a rewrite is only performed if this returns true,
+/// `describe` is called for granted rewrites while a budget is in effect
+pub fn consume(describe: impl FnOnce() -> String) -> bool {
+    TANK.with(|tank| {
+        let mut tank = tank.borrow_mut();
+        let Some(tank) = tank.as_mut() else {
+            return true;
+        };
+        tank.tally.requested += 1;
+        if tank.budget == 0 {
+            return false;
+        }
+        tank.budget -= 1;
+        tank.tally.last_granted = Some(describe());
+        true
+    })
+}
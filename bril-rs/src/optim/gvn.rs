@@ -0,0 +1,286 @@
+//! global value numbering on ssa, scoped by the dominator tree
+//!
+//! Blocks are numbered in preorder of the dominator tree. Exprs numbered in a block stay
+//! visible to every block it dominates and are dropped once its subtree is done, so
+//!     .entry:
+//!         s: int = add a b;
+//!         br c .then .else;
+//!     .then:
+//!         t: int = add b a;             => t: int = id s;
+//!         print t;                      => print s;
+//! is caught across blocks, while exprs of `.then` are never reused in `.else`.
+//! Since every var is defined once, the live-in restriction of lvn does not apply here.
+//!
+//! Consts are numbered by their literal and folded through arithmetic. Copies left behind
+//! are swept once the walk is done.
+use crate::analyzer::{dom::DomTree, effect::EffectSummary};
+use crate::bril::{LabelOrInst, ValueLit};
+use crate::cfg::{Cfg, NodeRef};
+use crate::optim::dce::{eval_const_expr, CanonicalForm, NumTableEntry};
+use crate::optim::fuel;
+use crate::transform::cytron::{self, PhiPlacement};
+use crate::transform::{phi, phi::SsaForm, ssa};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+/// every call is conservatively treated as effectful
+pub fn gvn(cfg: Cfg) -> Cfg {
+    effect_aware_gvn(cfg, &EffectSummary::default())
+}
+
+/// calls to pure functions according to `effects` are numbered as well, and swept when unused,
+/// unless the function may trap or never return
+///
+/// accepts cfg in either `phi` or `set`/`get` form and keeps the encoding,
+/// cfg not in ssa is converted into pruned ssa and back, which adds no copies of its own
+pub fn effect_aware_gvn(cfg: Cfg, effects: &EffectSummary) -> Cfg {
+    match phi::ssa_form_of(&cfg) {
+        Some(SsaForm::SetGet) => gvn_on_ssa(cfg, effects),
+        Some(SsaForm::Phi) => {
+            phi::set_get_into_phi(gvn_on_ssa(phi::phi_into_set_get(cfg), effects))
+        }
+        None => ssa::cfg_from_ssa(gvn_on_ssa(
+            cytron::cfg_into_ssa_cytron(cfg, PhiPlacement::Pruned),
+            effects,
+        )),
+    }
+}
+
+fn gvn_on_ssa(mut cfg: Cfg, effects: &EffectSummary) -> Cfg {
+    // unreachable blocks have no place in the dominator tree
+    cfg.prune_unreachable();
+    let mut ctx = GvnCtx {
+        num_table: HashMap::new(),
+        var2numbering: HashMap::new(),
+        next_number: 0,
+        pure_funcs: effects.pure_total_funcs(),
+        num_replaced: 0,
+    };
+    for arg in cfg.func_ctx.args_name().unwrap_or_default() {
+        ctx.fresh_numbering(&arg, None);
+    }
+    let dom_tree = DomTree::from_cfg(&cfg);
+    ctx.walk(&dom_tree, &Weak::upgrade(&cfg.root).unwrap());
+    eprintln!("{} redundant inst found by gvn", ctx.num_replaced);
+    sweep_dead_defs(&cfg, &ctx.pure_funcs);
+    cfg
+}
+
+struct GvnCtx {
+    num_table: HashMap<CanonicalForm, Arc<NumTableEntry>>,
+    /// vars are defined once, entries are never shadowed and need no scoping
+    var2numbering: HashMap<String, Arc<NumTableEntry>>,
+    next_number: usize,
+    pure_funcs: HashSet<String>,
+    num_replaced: usize,
+}
+
+impl GvnCtx {
+    fn walk(&mut self, dom_tree: &DomTree, cfg_node: &NodeRef) {
+        let mut scope = vec![];
+        for inst in cfg_node.lock().unwrap().blk.instrs.iter_mut() {
+            let original = inst.clone();
+            self.number_inst(inst, &mut scope);
+            // every var is defined once, numbering stays valid if a rewrite is refused
+            if *inst != original && !fuel::consume(|| format!("gvn: `{original}` -> `{inst}`")) {
+                *inst = original;
+            }
+        }
+        for child in dom_tree.children(Arc::as_ptr(cfg_node)) {
+            self.walk(dom_tree, child);
+        }
+        for canon_form in scope {
+            self.num_table.remove(&canon_form);
+        }
+    }
+
+    fn number_inst(&mut self, inst: &mut LabelOrInst, scope: &mut Vec<CanonicalForm>) {
+        let LabelOrInst::Inst {
+            op,
+            dest,
+            args,
+            funcs,
+            value,
+            ..
+        } = inst
+        else {
+            return;
+        };
+        // the shadow written by `set` is not a use
+        let uses = match (op.as_str(), args.as_mut()) {
+            ("set", Some(args)) => &mut args[1..],
+            (_, Some(args)) => &mut args[..],
+            (_, None) => &mut [],
+        };
+        for arg in uses.iter_mut() {
+            if let Some(entry) = self.var2numbering.get(arg) {
+                *arg = entry.canonical_var.clone();
+            }
+        }
+        let Some(dest) = dest else {
+            return;
+        };
+
+        let key_op = match op.as_str() {
+            "const" => {
+                let const_lit = value.unwrap();
+                let key = CanonicalForm::from_op_and_numbered_args("const", &[lit_key(const_lit)]);
+                if let Some(canonical_var) =
+                    self.lookup_or_insert(key, dest, Some(const_lit), scope)
+                {
+                    *op = "id".to_string();
+                    *args = Some(vec![canonical_var]);
+                    *value = None;
+                }
+                return;
+            }
+            "id" => {
+                let entry = self.numbering_of(&args.as_ref().unwrap()[0]);
+                if let Some(const_lit) = entry.const_lit {
+                    *op = "const".to_string();
+                    *args = None;
+                    *value = Some(const_lit);
+                }
+                self.var2numbering.insert(dest.clone(), entry);
+                return;
+            }
+            "call" if self.pure_funcs.contains(&funcs.as_ref().unwrap()[0]) => {
+                format!("call @{}", funcs.as_ref().unwrap()[0])
+            }
+            // results may differ even if args are the same
+            "call" | "get" | "load" | "alloc" | "undef" | "phi" => {
+                self.fresh_numbering(dest, None);
+                return;
+            }
+            _ => op.clone(),
+        };
+        let args_lit = args.clone().unwrap_or_default();
+        let numbered_args: Vec<String> = args_lit
+            .iter()
+            .map(|arg| self.numbering_of(arg).numbering.to_string())
+            .collect();
+        let const_binding: Option<Vec<ValueLit>> = args_lit
+            .iter()
+            .map(|arg| self.numbering_of(arg).const_lit)
+            .collect();
+        let const_lit = const_binding.and_then(|consts| eval_const_expr(&key_op, &consts));
+        let key = CanonicalForm::from_op_and_numbered_args(&key_op, &numbered_args);
+        if let Some(canonical_var) = self.lookup_or_insert(key, dest, const_lit, scope) {
+            *op = "id".to_string();
+            *args = Some(vec![canonical_var]);
+        } else if let Some(const_lit) = const_lit {
+            *op = "const".to_string();
+            *args = None;
+            *value = Some(const_lit);
+        } else {
+            return;
+        }
+        *funcs = None;
+    }
+
+    /// canonical var of the earlier equivalent expr if there is one,
+    /// otherwise `dest` is numbered and the expr registered in the current scope
+    fn lookup_or_insert(
+        &mut self,
+        key: CanonicalForm,
+        dest: &str,
+        const_lit: Option<ValueLit>,
+        scope: &mut Vec<CanonicalForm>,
+    ) -> Option<String> {
+        if let Some(entry) = self.num_table.get(&key).cloned() {
+            let canonical_var = entry.canonical_var.clone();
+            self.var2numbering.insert(dest.to_string(), entry);
+            self.num_replaced += 1;
+            return Some(canonical_var);
+        }
+        let entry = self.fresh_numbering(dest, const_lit);
+        self.num_table.insert(key.clone(), entry);
+        scope.push(key);
+        None
+    }
+
+    fn fresh_numbering(&mut self, var: &str, const_lit: Option<ValueLit>) -> Arc<NumTableEntry> {
+        let entry = Arc::new(NumTableEntry {
+            canonical_var: var.to_string(),
+            const_lit,
+            numbering: self.next_number,
+        });
+        self.next_number += 1;
+        self.var2numbering
+            .insert(var.to_string(), Arc::clone(&entry));
+        entry
+    }
+
+    /// vars never defined on the way down, e.g. bound by `undef`, get their own numbering
+    fn numbering_of(&mut self, var: &str) -> Arc<NumTableEntry> {
+        match self.var2numbering.get(var) {
+            Some(entry) => Arc::clone(entry),
+            None => self.fresh_numbering(var, None),
+        }
+    }
+}
+
+fn lit_key(const_lit: ValueLit) -> String {
+    match const_lit {
+        ValueLit::Int(i) => format!("int {i}"),
+        ValueLit::Bool(b) => format!("bool {b}"),
+    }
+}
+
+/// drop defs that are never used, together with `set`s of shadows that are never got
+fn sweep_dead_defs(cfg: &Cfg, pure_funcs: &HashSet<String>) {
+    loop {
+        let mut used = HashSet::new();
+        let mut got = HashSet::new();
+        for node in &cfg.nodes {
+            for inst in &node.lock().unwrap().blk.instrs {
+                let LabelOrInst::Inst { op, dest, args, .. } = inst else {
+                    continue;
+                };
+                match (op.as_str(), args) {
+                    ("set", Some(args)) => used.extend(args[1..].iter().cloned()),
+                    (_, Some(args)) => used.extend(args.iter().cloned()),
+                    _ => {}
+                }
+                if op == "get" {
+                    got.extend(dest.iter().cloned());
+                }
+            }
+        }
+        let mut updated = false;
+        for node in &cfg.nodes {
+            node.lock().unwrap().blk.instrs.retain(|inst| {
+                let dead = match inst {
+                    LabelOrInst::Inst {
+                        op,
+                        args: Some(args),
+                        ..
+                    } if op == "set" => !got.contains(&args[0]),
+                    LabelOrInst::Inst {
+                        op,
+                        dest: Some(dest),
+                        funcs,
+                        ..
+                    } => {
+                        let removable = match op.as_str() {
+                            "call" => pure_funcs.contains(&funcs.as_ref().unwrap()[0]),
+                            "alloc" => false,
+                            _ => true,
+                        };
+                        removable && !used.contains(dest)
+                    }
+                    _ => false,
+                };
+                let is_set = matches!(inst, LabelOrInst::Inst { op, .. } if op == "set");
+                // sets only go once the `get` of their shadow is gone, which already took fuel
+                let dead = dead && (is_set || fuel::consume(|| format!("gvn: sweep `{inst}`")));
+                updated |= dead;
+                !dead
+            });
+        }
+        if !updated {
+            break;
+        }
+    }
+}
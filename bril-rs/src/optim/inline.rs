@@ -0,0 +1,343 @@
+//! function inlining over the whole program
+//!
+//! A call site
+//!     dest: ty = call @callee a b;
+//! is replaced by a copy of the callee cfg, whose vars and labels are prefixed with
+//! `callee.inline.{n}`, so that they never clash with those of the caller
+//!     callee.inline.0.x: ty_x = id a;           <- params bound to args
+//!     callee.inline.0.y: ty_y = id b;
+//!     jmp .callee.inline.0.entry;
+//!     ...                                       <- callee body, `ret v` becomes
+//!     dest: ty = id callee.inline.0.v;          <- ret value bound to dest
+//!     jmp .callee.inline.0.ret;
+//! .callee.inline.0.ret:                         <- continuation, rest of the caller block
+//!
+//! Functions are visited bottom-up on the call graph so that callee bodies are already
+//! inlined when being copied. Functions on a call graph cycle are never inlined.
+use crate::analyzer::callgraph::CallGraph;
+use crate::bril::{Arg, LabelOrInst};
+use crate::cfg::prelude::*;
+use crate::cfg::ProgCfgs;
+use crate::optim::fuel;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// max estimated growth of a single call site to be inlined
+pub const INLINE_THRESHOLD: usize = 24;
+/// callees called only once are inlined more eagerly, they can be dropped afterwards
+pub const INLINE_SINGLE_CALL_SITE_THRESHOLD: usize = 64;
+/// a caller never grows past this number of instrs by inlining
+pub const INLINE_CALLER_BUDGET: usize = 1024;
+
+pub fn inline_functions(mut prog: ProgCfgs) -> ProgCfgs {
+    let call_graph = CallGraph::from_prog(&prog);
+    let idx_by_name: HashMap<String, usize> = prog
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, cfg)| (cfg.func_ctx.name.clone(), i))
+        .collect();
+    let inlinable: HashSet<&String> = call_graph
+        .funcs
+        .iter()
+        .filter(|func| *func != "main" && !call_graph.is_recursive(func))
+        .collect();
+
+    for caller in call_graph.bottom_up_order() {
+        let Some(&caller_idx) = idx_by_name.get(&caller) else {
+            continue;
+        };
+        prog.0[caller_idx].make_jumps_explicit();
+        let mut inline_cnt = 0;
+        loop {
+            let caller_cfg = &prog.0[caller_idx];
+            let caller_size = cfg_size(caller_cfg);
+            let Some(call_site) = find_call_site(caller_cfg, |callee| {
+                if !inlinable.contains(callee) {
+                    return None;
+                }
+                let callee_cfg = &prog.0[*idx_by_name.get(callee)?];
+                let cost = inline_cost(callee_cfg);
+                let threshold = if call_graph.num_call_sites.get(callee) == Some(&1) {
+                    INLINE_SINGLE_CALL_SITE_THRESHOLD
+                } else {
+                    INLINE_THRESHOLD
+                };
+                (cost <= threshold && caller_size + cost <= INLINE_CALLER_BUDGET)
+                    .then_some(callee_cfg)
+            }) else {
+                break;
+            };
+            // fuel never comes back once refused, the same call site would be found again
+            let describe = || format!("inline: @{} into @{caller}", call_site.callee);
+            if !fuel::consume(describe) {
+                break;
+            }
+            let prefix = loop {
+                let prefix = format!("{}.inline.{inline_cnt}", call_site.callee);
+                inline_cnt += 1;
+                if !uses_prefix(caller_cfg, &prefix) {
+                    break prefix;
+                }
+            };
+            eprintln!("@{} inlined into @{caller}", call_site.callee);
+            let callee_cfg = &prog.0[*idx_by_name.get(&call_site.callee).unwrap()];
+            let (callee_nodes, cont) =
+                instantiate_callee(callee_cfg, &prog.0[caller_idx], &prefix, &call_site);
+            splice_call_site(
+                &mut prog.0[caller_idx],
+                call_site,
+                callee_nodes,
+                &cont,
+                &prefix,
+            );
+        }
+        prog.0[caller_idx].elide_fallthrough_jumps();
+    }
+    prog
+}
+
+/// number of instrs of a function
+fn cfg_size(cfg: &Cfg) -> usize {
+    cfg.nodes
+        .iter()
+        .map(|node| {
+            node.lock()
+                .unwrap()
+                .blk
+                .instrs
+                .iter()
+                .filter(|inst| matches!(inst, LabelOrInst::Inst { .. }))
+                .count()
+        })
+        .sum()
+}
+
+/// estimated number of instrs added by inlining the callee once
+/// the callee body, plus one copy per param, minus the `call` being replaced
+fn inline_cost(callee: &Cfg) -> usize {
+    let num_params = callee.func_ctx.args.as_ref().map_or(0, Vec::len);
+    (cfg_size(callee) + num_params).saturating_sub(1)
+}
+
+struct CallSite {
+    node: NodeRef,
+    idx: usize,
+    callee: String,
+    dest: Option<String>,
+    ty: Option<String>,
+    args: Vec<String>,
+    params: Vec<Arg>,
+}
+
+fn find_call_site<'a, F>(cfg: &Cfg, mut should_inline: F) -> Option<CallSite>
+where
+    F: FnMut(&String) -> Option<&'a Cfg>,
+{
+    for node in &cfg.nodes {
+        let node_lock = node.lock().unwrap();
+        for (idx, inst) in node_lock.blk.instrs.iter().enumerate() {
+            let LabelOrInst::Inst {
+                op,
+                dest,
+                ty,
+                args,
+                funcs: Some(funcs),
+                ..
+            } = inst
+            else {
+                continue;
+            };
+            let Some(callee) = should_inline(&funcs[0]).filter(|_| op == "call") else {
+                continue;
+            };
+            return Some(CallSite {
+                node: Arc::clone(node),
+                idx,
+                callee: funcs[0].clone(),
+                dest: dest.clone(),
+                ty: ty.clone(),
+                args: args.clone().unwrap_or_default(),
+                params: callee.func_ctx.args.clone().unwrap_or_default(),
+            });
+        }
+    }
+    None
+}
+
+fn uses_prefix(cfg: &Cfg, prefix: &str) -> bool {
+    let prefix = format!("{prefix}.");
+    cfg.nodes.iter().any(|node| {
+        node.lock()
+            .unwrap()
+            .blk
+            .instrs
+            .iter()
+            .any(|inst| match inst {
+                LabelOrInst::Label { label } => label.starts_with(&prefix),
+                LabelOrInst::Inst { dest, args, .. } => dest
+                    .iter()
+                    .chain(args.iter().flatten())
+                    .any(|var| var.starts_with(&prefix)),
+            })
+    })
+}
+
+/// copy callee body with every var and label prefixed, `ret` is turned into a jump to
+/// the continuation block `{prefix}.ret` (suffixed if the callee has a block of that name),
+/// with ret value assigned to the dest of the call, returns the body along with the label
+fn instantiate_callee(
+    callee: &Cfg,
+    caller: &Cfg,
+    prefix: &str,
+    call_site: &CallSite,
+) -> (Vec<NodeRef>, String) {
+    let label_scheme: HashMap<String, String> = callee
+        .nodes
+        .iter()
+        .filter_map(|node| node.lock().unwrap().label.clone())
+        .map(|label| {
+            let renamed = format!("{prefix}.{label}");
+            (label, renamed)
+        })
+        .collect();
+    let mut body = Cfg {
+        root: Default::default(),
+        nodes: Cfg::clone_nodes(&callee.nodes, &label_scheme),
+        func_ctx: FuncCtx {
+            name: prefix.to_string(),
+            args: None,
+            ty: None,
+        },
+    };
+    body.relink();
+    body.make_jumps_explicit();
+    let root = body.root.upgrade().unwrap();
+    if root.lock().unwrap().label.is_none() {
+        let entry = body.fresh_label(&format!("{prefix}.entry"));
+        let mut root_lock = root.lock().unwrap();
+        root_lock.blk.set_label(entry.clone());
+        root_lock.label = Some(entry);
+    }
+
+    let mut vars: HashSet<String> = callee
+        .func_ctx
+        .args_name()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    for node in &body.nodes {
+        for inst in &node.lock().unwrap().blk.instrs {
+            if let LabelOrInst::Inst { dest, args, .. } = inst {
+                vars.extend(dest.iter().chain(args.iter().flatten()).cloned());
+            }
+        }
+    }
+    let var_scheme: HashMap<String, String> = vars
+        .into_iter()
+        .map(|var| {
+            let renamed = format!("{prefix}.{var}");
+            (var, renamed)
+        })
+        .collect();
+
+    let hint = format!("{prefix}.ret");
+    let cont = std::iter::once(hint.clone())
+        .chain((1..).map(|i| format!("{hint}.{i}")))
+        .find(|label| body.node_by_label(label).is_none() && caller.node_by_label(label).is_none())
+        .unwrap();
+    let num_nodes = body.nodes.len();
+    for (i, node) in body.nodes.iter().enumerate() {
+        let mut node_lock = node.lock().unwrap();
+        node_lock.blk.rename_vars(&var_scheme);
+        let instrs = &mut node_lock.blk.instrs;
+        if let Some(ret_idx) = instrs
+            .iter()
+            .position(|inst| matches!(inst, LabelOrInst::Inst { op, .. } if op == "ret"))
+        {
+            // anything after ret is dead
+            let ret_value = match instrs.drain(ret_idx..).next() {
+                Some(LabelOrInst::Inst {
+                    args: Some(args), ..
+                }) => args.first().cloned(),
+                _ => None,
+            };
+            if let (Some(dest), Some(ret_value)) = (&call_site.dest, ret_value) {
+                instrs.push(LabelOrInst::Inst {
+                    op: "id".to_string(),
+                    dest: Some(dest.clone()),
+                    ty: call_site.ty.clone(),
+                    args: Some(vec![ret_value]),
+                    funcs: None,
+                    labels: None,
+                    value: None,
+                });
+            }
+            instrs.push(jmp(&cont));
+        } else if i == num_nodes - 1 && node_lock.blk.falls_through() {
+            // falling off the end of a void function
+            node_lock.blk.instrs.push(jmp(&cont));
+        }
+    }
+    (body.nodes, cont)
+}
+
+fn splice_call_site(
+    cfg: &mut Cfg,
+    call_site: CallSite,
+    callee_nodes: Vec<NodeRef>,
+    cont_label: &str,
+    prefix: &str,
+) {
+    let entry_label = callee_nodes[0].lock().unwrap().label.clone().unwrap();
+    let cont = {
+        let mut node_lock = call_site.node.lock().unwrap();
+        let mut tail = node_lock.blk.instrs.split_off(call_site.idx);
+        tail.remove(0);
+        for (param, arg) in call_site.params.into_iter().zip(call_site.args) {
+            node_lock.blk.instrs.push(LabelOrInst::Inst {
+                op: "id".to_string(),
+                dest: Some(format!("{prefix}.{}", param.name)),
+                ty: Some(param.ty),
+                args: Some(vec![arg]),
+                funcs: None,
+                labels: None,
+                value: None,
+            });
+        }
+        node_lock.blk.instrs.push(jmp(&entry_label));
+        let mut blk = BasicBlock {
+            label: None,
+            instrs: tail,
+        };
+        blk.set_label(cont_label.to_string());
+        Arc::new(Mutex::new(CfgNode {
+            label: Some(cont_label.to_string()),
+            blk,
+            successors: vec![],
+            predecessors: vec![],
+        }))
+    };
+    let idx = cfg
+        .nodes
+        .iter()
+        .position(|node| Arc::ptr_eq(node, &call_site.node))
+        .unwrap();
+    let mut spliced = callee_nodes;
+    spliced.push(cont);
+    cfg.nodes.splice(idx + 1..idx + 1, spliced);
+    cfg.relink();
+}
+
+fn jmp(label: &str) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "jmp".to_string(),
+        dest: None,
+        ty: None,
+        args: None,
+        funcs: None,
+        labels: Some(vec![label.to_string()]),
+        value: None,
+    }
+}
@@ -4,7 +4,8 @@ use crate::cfg::prelude::*;
 use crate::cfg::FuncCtx;
 use crate::optim::dce::global::ReachingDefAnalysis;
 use crate::optim::dflow::WorkListAlgo;
-use crate::transform;
+use crate::optim::fuel;
+use crate::transform::{self, phi, phi::SsaForm};
 
 use std::collections::{HashMap, HashSet};
 use std::default::Default;
@@ -16,10 +17,16 @@ pub struct NaturalLoop<'a> {
     pub exits: Vec<NodeRef>,
 }
 
+/// accepts cfg in either `phi` or `set`/`get` form, cfg not in ssa is converted first
 pub fn loop_invariant_code_motion(cfg: Cfg) -> Cfg {
-    let mut cfg = transform::ssa::cfg_into_ssa(cfg);
+    let mut cfg = match phi::ssa_form_of(&cfg) {
+        Some(SsaForm::Phi) => phi::phi_into_set_get(cfg),
+        Some(SsaForm::SetGet) => cfg,
+        None => transform::ssa::cfg_into_ssa(cfg),
+    };
     let comps = find_sccs(&cfg);
-    let natural_loops = find_natural_loops(&cfg, &comps);
+    let mut dom_tree = DomTree::from_cfg(&cfg);
+    let natural_loops = find_natural_loops_in(&dom_tree, &comps);
     let reaching_def_ret = ReachingDefAnalysis(&cfg).execute(&cfg);
 
     for mut natural_loop in natural_loops {
@@ -36,20 +43,21 @@ pub fn loop_invariant_code_motion(cfg: Cfg) -> Cfg {
             .position(|node| Arc::as_ptr(node) == entry_ptr)
             .unwrap();
 
+        let hoisted = grant_hoists(&natural_loop, &invariants);
         let mut deleted_instrs = vec![];
         // safe to remove all loop variants, this only holds on ssa
         for node in &natural_loop.comp.lock().unwrap().cfg_nodes {
             let mut node_lock = node.lock().unwrap();
             let instrs = node_lock.blk.instrs.clone();
             let (removed, kept): (Vec<_>, Vec<_>) = instrs.into_iter().partition(
-                |inst| matches!(inst, LabelOrInst::Inst {dest: Some(dest), ..} if invariants.contains(dest.as_str())) 
+                |inst| matches!(inst, LabelOrInst::Inst {dest: Some(dest), ..} if hoisted.contains(dest.as_str())) 
             );
             node_lock.blk.instrs = kept;
             deleted_instrs.extend(removed);
         }
 
         if !deleted_instrs.is_empty() {
-            let preheader_node = natural_loop.inject_preheader_node();
+            let preheader_node = natural_loop.inject_preheader_node(&cfg);
             // topo sort removed instrs
             preheader_node
                 .lock()
@@ -57,7 +65,9 @@ pub fn loop_invariant_code_motion(cfg: Cfg) -> Cfg {
                 .blk
                 .instrs
                 .extend(topo_sort_instrs(&deleted_instrs));
-            cfg.nodes.insert(entry_idx, preheader_node);
+            cfg.nodes.insert(entry_idx, Arc::clone(&preheader_node));
+            // dom tree stays valid across injected preheaders
+            dom_tree.insert_block(&cfg, &preheader_node);
             eprintln!("{} inst moved", deleted_instrs.len());
         } else {
             eprintln!("no liom chance");
@@ -66,8 +76,54 @@ pub fn loop_invariant_code_motion(cfg: Cfg) -> Cfg {
     transform::ssa::cfg_from_ssa(cfg)
 }
 
+/// each hoist of an invariant consumes fuel, an invariant is only hoisted
+/// together with the invariants of the loop it depends on
+fn grant_hoists(natural_loop: &NaturalLoop<'_>, invariants: &HashSet<String>) -> HashSet<String> {
+    let candidates: Vec<LabelOrInst> = natural_loop
+        .comp
+        .lock()
+        .unwrap()
+        .cfg_nodes
+        .iter()
+        .flat_map(|node| node.lock().unwrap().blk.instrs.clone())
+        .filter(|inst| matches!(inst, LabelOrInst::Inst {dest: Some(dest), ..} if invariants.contains(dest.as_str())))
+        .collect();
+    let in_loop: HashSet<&String> = candidates
+        .iter()
+        .filter_map(|inst| match inst {
+            LabelOrInst::Inst { dest, .. } => dest.as_ref(),
+            LabelOrInst::Label { .. } => None,
+        })
+        .collect();
+    let mut hoisted = HashSet::new();
+    for inst in topo_sort_instrs(&candidates) {
+        let LabelOrInst::Inst {
+            dest: Some(dest),
+            args,
+            ..
+        } = &inst
+        else {
+            unreachable!()
+        };
+        let deps_hoisted = args
+            .iter()
+            .flatten()
+            .all(|arg| !in_loop.contains(arg) || hoisted.contains(arg));
+        if deps_hoisted && fuel::consume(|| format!("licm: hoist `{inst}`")) {
+            hoisted.insert(dest.clone());
+        }
+    }
+    hoisted
+}
+
 pub fn find_natural_loops<'a>(cfg: &Cfg, comps: &'a Vec<CompRef>) -> Vec<NaturalLoop<'a>> {
-    let dom_tree = DomTree::from_cfg(cfg);
+    find_natural_loops_in(&DomTree::from_cfg(cfg), comps)
+}
+
+pub fn find_natural_loops_in<'a>(
+    dom_tree: &DomTree,
+    comps: &'a Vec<CompRef>,
+) -> Vec<NaturalLoop<'a>> {
     let mut loops = vec![];
     for comp in comps {
         let (mut entries, exits) = {
@@ -80,7 +136,7 @@ pub fn find_natural_loops<'a>(cfg: &Cfg, comps: &'a Vec<CompRef>) -> Vec<Natural
             let entry = entries.pop().unwrap();
             // check whether the component contains at least one backedge
             // all backedge should also point to dominator
-            if validate_backedges(&entry, &comp.lock().unwrap(), &dom_tree) {
+            if validate_backedges(&entry, &comp.lock().unwrap(), dom_tree) {
                 let natural_loop = NaturalLoop { entry, comp, exits };
                 loops.push(natural_loop);
             }
@@ -243,7 +299,7 @@ fn validate_backedges(entry: &NodeRef, comp: &Component, dom_tree: &DomTree) ->
 }
 
 impl<'a> NaturalLoop<'a> {
-    fn entry_preds_outside_loop(&self) -> Vec<WeakNodeRef> {
+    pub(crate) fn entry_preds_outside_loop(&self) -> Vec<WeakNodeRef> {
         let entry_lock = self.entry.lock().unwrap();
         entry_lock
             .predecessors
@@ -253,9 +309,11 @@ impl<'a> NaturalLoop<'a> {
             .collect()
     }
 
-    fn inject_preheader_node(&mut self) -> NodeRef {
+    /// the preheader is labeled after the header, fresh in `cfg` which may hold
+    /// preheaders of earlier passes
+    pub(crate) fn inject_preheader_node(&mut self, cfg: &Cfg) -> NodeRef {
         let entry_label = self.entry.lock().unwrap().label.clone().unwrap();
-        let preheader_label = format!("{}.preheader", entry_label);
+        let preheader_label = cfg.fresh_label(&format!("{}.preheader", entry_label));
 
         let (header_preds, preheader_node) = {
             // excluding in-component backedge
@@ -279,7 +337,13 @@ impl<'a> NaturalLoop<'a> {
                 predecessors: entry_preds.clone(),
             };
             let preheader_node = Arc::new(Mutex::new(preheader_node));
-            entry_lock.predecessors = vec![Arc::downgrade(&preheader_node)];
+            // backedges still enter the header directly
+            entry_lock
+                .predecessors
+                .retain(|pred| self.comp.lock().unwrap().contains(&Weak::as_ptr(pred)));
+            entry_lock
+                .predecessors
+                .push(Arc::downgrade(&preheader_node));
             (entry_preds, preheader_node)
         };
 
@@ -1,4 +1,13 @@
 pub mod dce;
 pub mod dflow;
+pub mod fuel;
+pub mod gvn;
+pub mod inline;
 pub use dce::dce;
 pub mod loops;
+pub mod prune;
+pub mod rotate;
+pub mod specialize;
+pub mod tailrec;
+pub mod unroll;
+pub mod unswitch;
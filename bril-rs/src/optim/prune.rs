@@ -0,0 +1,209 @@
+//! whole program cleanup
+//!   - drop functions that are never called from `@main`
+//!   - drop params that the callee never reads, together with the args at every call site
+//!   - drop return values that no caller consumes
+//!
+//! `@main` keeps its signature, which is fixed by the command line.
+use crate::analyzer::callgraph::CallGraph;
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::cfg::ProgCfgs;
+use crate::optim::dce::global::LivenessAnalysis;
+use crate::optim::dflow::WorkListAlgo;
+use crate::optim::fuel;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+pub fn prune_program(prog: ProgCfgs) -> ProgCfgs {
+    let prog = dead_function_elimination(prog);
+    let prog = dead_argument_elimination(prog);
+    dead_return_value_elimination(prog)
+}
+
+pub fn dead_function_elimination(prog: ProgCfgs) -> ProgCfgs {
+    if !prog.0.iter().any(|cfg| cfg.func_ctx.name == "main") {
+        return prog;
+    }
+    let call_graph = CallGraph::from_prog(&prog);
+    let mut reachable = call_graph.reachable_from("main");
+    // functions refused to be dropped keep their callees alive as well
+    let refused: Vec<String> = prog
+        .0
+        .iter()
+        .map(|cfg| &cfg.func_ctx.name)
+        .filter(|name| {
+            !reachable.contains(*name) && !fuel::consume(|| format!("prune: drop @{name}"))
+        })
+        .cloned()
+        .collect();
+    for name in refused {
+        reachable.extend(call_graph.reachable_from(&name));
+    }
+    ProgCfgs(
+        prog.0
+            .into_iter()
+            .filter(|cfg| {
+                let keep = reachable.contains(&cfg.func_ctx.name);
+                if !keep {
+                    eprintln!("@{} is never called", cfg.func_ctx.name);
+                }
+                keep
+            })
+            .collect(),
+    )
+}
+
+pub fn dead_argument_elimination(mut prog: ProgCfgs) -> ProgCfgs {
+    // func name -> positions of params being dropped
+    let mut dead_params: HashMap<String, Vec<usize>> = HashMap::new();
+    for cfg in prog.0.iter_mut().filter(|cfg| cfg.func_ctx.name != "main") {
+        let Some(params) = cfg.func_ctx.args.take() else {
+            continue;
+        };
+        let live_in = live_in_at_entry(cfg);
+        let (mut kept, mut dropped) = (vec![], vec![]);
+        for (i, param) in params.into_iter().enumerate() {
+            let describe = || format!("prune: drop param {} of @{}", param.name, cfg.func_ctx.name);
+            if live_in.contains(&param.name) || !fuel::consume(describe) {
+                kept.push(param);
+            } else {
+                eprintln!(
+                    "param {} of @{} is never read",
+                    param.name, cfg.func_ctx.name
+                );
+                dropped.push(i);
+            }
+        }
+        cfg.func_ctx.args = (!kept.is_empty()).then_some(kept);
+        if !dropped.is_empty() {
+            dead_params.insert(cfg.func_ctx.name.clone(), dropped);
+        }
+    }
+
+    for cfg in &prog.0 {
+        for node in &cfg.nodes {
+            for inst in node.lock().unwrap().blk.instrs.iter_mut() {
+                let LabelOrInst::Inst {
+                    op,
+                    args,
+                    funcs: Some(funcs),
+                    ..
+                } = inst
+                else {
+                    continue;
+                };
+                let Some(dropped) = dead_params.get(&funcs[0]).filter(|_| op == "call") else {
+                    continue;
+                };
+                let kept: Vec<String> = args
+                    .take()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, arg)| (!dropped.contains(&i)).then_some(arg))
+                    .collect();
+                *args = (!kept.is_empty()).then_some(kept);
+            }
+        }
+    }
+    prog
+}
+
+pub fn dead_return_value_elimination(mut prog: ProgCfgs) -> ProgCfgs {
+    // a return value is consumed if any call site reads its dest afterwards
+    let mut consumed: HashSet<String> = HashSet::from(["main".to_string()]);
+    for cfg in &prog.0 {
+        let live_out = live_out_per_node(cfg);
+        for node in &cfg.nodes {
+            let mut live = live_out.get(&Arc::as_ptr(node)).unwrap().clone();
+            for inst in node.lock().unwrap().blk.instrs.iter().rev() {
+                let LabelOrInst::Inst {
+                    op,
+                    dest,
+                    args,
+                    funcs,
+                    ..
+                } = inst
+                else {
+                    continue;
+                };
+                if let (Some(dest), Some(funcs)) = (dest, funcs) {
+                    if op == "call" && live.contains(dest) {
+                        consumed.insert(funcs[0].clone());
+                    }
+                }
+                if let Some(dest) = dest {
+                    live.remove(dest);
+                }
+                live.extend(args.iter().flatten().cloned());
+            }
+        }
+    }
+
+    let dropped: HashSet<String> = prog
+        .0
+        .iter_mut()
+        .filter(|cfg| {
+            cfg.func_ctx.ty.is_some()
+                && !consumed.contains(&cfg.func_ctx.name)
+                && fuel::consume(|| format!("prune: drop return value of @{}", cfg.func_ctx.name))
+        })
+        .map(|cfg| {
+            eprintln!("return value of @{} is never used", cfg.func_ctx.name);
+            cfg.func_ctx.ty = None;
+            for node in &cfg.nodes {
+                for inst in node.lock().unwrap().blk.instrs.iter_mut() {
+                    if let LabelOrInst::Inst { op, args, .. } = inst {
+                        if op == "ret" {
+                            *args = None;
+                        }
+                    }
+                }
+            }
+            cfg.func_ctx.name.clone()
+        })
+        .collect();
+
+    for cfg in &prog.0 {
+        for node in &cfg.nodes {
+            for inst in node.lock().unwrap().blk.instrs.iter_mut() {
+                if let LabelOrInst::Inst {
+                    op,
+                    dest,
+                    ty,
+                    funcs: Some(funcs),
+                    ..
+                } = inst
+                {
+                    if op == "call" && dropped.contains(&funcs[0]) {
+                        *dest = None;
+                        *ty = None;
+                    }
+                }
+            }
+        }
+    }
+    prog
+}
+
+fn live_in_at_entry(cfg: &Cfg) -> HashSet<String> {
+    LivenessAnalysis
+        .execute(cfg)
+        .remove(&Weak::as_ptr(&cfg.root))
+        .unwrap()
+}
+
+fn live_out_per_node(cfg: &Cfg) -> HashMap<NodePtr, HashSet<String>> {
+    let live_in = LivenessAnalysis.execute(cfg);
+    cfg.nodes
+        .iter()
+        .map(|node| {
+            let live_out = LivenessAnalysis::predecessors(node)
+                .iter()
+                .flat_map(|succ| live_in.get(&Arc::as_ptr(succ)).unwrap().clone())
+                .collect();
+            (Arc::as_ptr(node), live_out)
+        })
+        .collect()
+}
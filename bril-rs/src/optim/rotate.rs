@@ -0,0 +1,139 @@
+//! loop rotation on non-ssa cfg
+//!
+//! A top-tested loop
+//!     .header:
+//!         cond: bool = ...;
+//!         br cond .body .exit;
+//!     .body:
+//!         ...
+//!         jmp .header;
+//! is turned into a guard followed by a bottom-tested loop
+//!     .header:                <- now the guard, executed only once
+//!         cond: bool = ...;
+//!         br cond .body .exit;
+//!     .body:
+//!         ...
+//!     .header.rotated:        <- copy of the header, placed after the last latch
+//!         cond: bool = ...;
+//!         br cond .body .exit;
+//! The exit test now sits at the latch, saving one jump per iteration, and `.body` dominates
+//! every block of the loop, so more instrs qualify for hoisting
+use crate::analyzer::scc::find_sccs;
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::optim::fuel;
+use crate::optim::loops::{find_natural_loops, NaturalLoop};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+/// headers with more instrs than this are not duplicated
+pub const ROTATE_MAX_HEADER_SIZE: usize = 8;
+
+pub fn loop_rotation(mut cfg: Cfg) -> Cfg {
+    cfg.make_jumps_explicit();
+    let mut visited = HashSet::new();
+    loop {
+        let comps = find_sccs(&cfg);
+        let natural_loops = find_natural_loops(&cfg, &comps);
+        let Some(natural_loop) = natural_loops.iter().find(|natural_loop| {
+            let Some(header) = natural_loop.entry.lock().unwrap().label.clone() else {
+                return false;
+            };
+            visited.insert(header)
+        }) else {
+            break;
+        };
+        if let Some(body) = rotate(&mut cfg, natural_loop) {
+            // the rotated loop is entered from the body, which should not be rotated again
+            visited.insert(body);
+        }
+    }
+    cfg.elide_fallthrough_jumps();
+    cfg
+}
+
+/// returns label of the new loop entry if `natural_loop` is rotated
+fn rotate(cfg: &mut Cfg, natural_loop: &NaturalLoop<'_>) -> Option<String> {
+    let comp = natural_loop.comp.lock().unwrap();
+    let header = natural_loop.entry.lock().unwrap();
+    let header_label = header.label.clone()?;
+    let size = header
+        .blk
+        .instrs
+        .iter()
+        .filter(|inst| matches!(inst, LabelOrInst::Inst { .. }))
+        .count();
+    let Some(LabelOrInst::Inst {
+        op,
+        labels: Some(br_labels),
+        ..
+    }) = header.blk.instrs.last()
+    else {
+        return None;
+    };
+    if op != "br" || size > ROTATE_MAX_HEADER_SIZE {
+        return None;
+    }
+    let in_loop: Vec<_> = br_labels
+        .iter()
+        .map(|label| {
+            header.successors.iter().any(|succ| {
+                let succ_ptr = Weak::as_ptr(succ);
+                let succ_label = if succ_ptr == Arc::as_ptr(&natural_loop.entry) {
+                    header.label.clone()
+                } else {
+                    succ.upgrade().unwrap().lock().unwrap().label.clone()
+                };
+                succ_label.as_ref() == Some(label) && comp.contains(&succ_ptr)
+            })
+        })
+        .collect();
+    // top-tested loop, header branches either into the loop body or out of the loop
+    if in_loop[0] == in_loop[1] {
+        return None;
+    }
+    let body = if in_loop[0] {
+        br_labels[0].clone()
+    } else {
+        br_labels[1].clone()
+    };
+    if body == header_label {
+        return None;
+    }
+    let latches: Vec<NodeRef> = header
+        .predecessors
+        .iter()
+        .filter(|pred| comp.contains(&Weak::as_ptr(pred)))
+        .map(|pred| pred.upgrade().unwrap())
+        .collect();
+    drop(header);
+    drop(comp);
+    if !fuel::consume(|| format!("rotate: loop .{header_label}")) {
+        return None;
+    }
+
+    let rotated_label = cfg.fresh_label(&format!("{header_label}.rotated"));
+    let rotated = Cfg::clone_nodes(
+        &[Arc::clone(&natural_loop.entry)],
+        &HashMap::from([(header_label.clone(), rotated_label.clone())]),
+    )
+    .pop()
+    .unwrap();
+    for latch in &latches {
+        latch
+            .lock()
+            .unwrap()
+            .blk
+            .redirect_jumps(&header_label, &rotated_label);
+    }
+    let last_latch_idx = cfg
+        .nodes
+        .iter()
+        .rposition(|node| latches.iter().any(|latch| Arc::ptr_eq(node, latch)))
+        .unwrap();
+    cfg.nodes.insert(last_latch_idx + 1, rotated);
+    cfg.relink();
+    eprintln!("loop .{header_label} rotated");
+    Some(body)
+}
@@ -0,0 +1,244 @@
+//! function specialization on constant call args
+//!
+//! `GlobalConstPropAlgo` treats params as non-const, constants passed by callers are lost at
+//! the function boundary. For a call site with const args
+//!     r: int = call @f x c;             <- c is const 4 at this point
+//! a copy of the callee is created with the const param dropped from its signature and bound
+//! at the entry instead
+//! @f.spec.0(x: int): int {
+//!     y: int = const 4;                 <- `y` is the param `c` is passed to
+//!     ...
+//! }
+//! then cleaned up by `optim::dce` with global const folding, and the call is retargeted
+//!     r: int = call @f.spec.0 x;
+//! Call sites passing the same consts to the same callee share a single specialized copy.
+use crate::analyzer::{self, callgraph::CallGraph};
+use crate::bril::{LabelOrInst, ValueLit};
+use crate::cfg::prelude::*;
+use crate::cfg::ProgCfgs;
+use crate::optim::{self, fuel};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// callees larger than this number of instrs are never copied
+pub const SPECIALIZE_SIZE_BUDGET: usize = 128;
+/// max number of specialized copies created for a single function
+pub const SPECIALIZE_MAX_CLONES: usize = 4;
+
+/// const binding of each param, `None` if not const at the call site
+type ConstArgs = Vec<Option<ValueLit>>;
+
+pub fn specialize_functions(mut prog: ProgCfgs) -> ProgCfgs {
+    let call_graph = CallGraph::from_prog(&prog);
+    let callees: HashMap<String, usize> = prog
+        .0
+        .iter()
+        .enumerate()
+        .filter(|(_, cfg)| cfg.func_ctx.name != "main" && cfg_size(cfg) <= SPECIALIZE_SIZE_BUDGET)
+        .map(|(i, cfg)| (cfg.func_ctx.name.clone(), i))
+        .collect();
+
+    let mut clones: HashMap<(String, ConstArgs), String> = HashMap::new();
+    let mut num_clones: HashMap<String, usize> = HashMap::new();
+    let mut specialized = vec![];
+    for caller in &prog.0 {
+        for call_site in find_const_call_sites(caller, &callees) {
+            let describe = || {
+                let inst = &call_site.node.lock().unwrap().blk.instrs[call_site.idx];
+                format!("specialize: `{inst}` in @{}", caller.func_ctx.name)
+            };
+            let key = (call_site.callee.clone(), call_site.const_args.clone());
+            let cnt = num_clones.entry(call_site.callee.clone()).or_default();
+            // a call left alone takes no fuel
+            if !clones.contains_key(&key) && *cnt >= SPECIALIZE_MAX_CLONES {
+                continue;
+            }
+            if !fuel::consume(describe) {
+                continue;
+            }
+            let clone_name = if let Some(clone_name) = clones.get(&key) {
+                clone_name.clone()
+            } else {
+                let clone_name = fresh_func_name(&call_graph, &call_site.callee, cnt);
+                let callee = &prog.0[*callees.get(&call_site.callee).unwrap()];
+                eprintln!(
+                    "@{} specialized as @{clone_name} for call in @{}",
+                    call_site.callee, caller.func_ctx.name
+                );
+                specialized.push(specialize(callee, &clone_name, &call_site.const_args));
+                clones.insert(key, clone_name.clone());
+                clone_name
+            };
+            retarget_call_site(call_site, clone_name);
+        }
+    }
+    prog.0.extend(specialized);
+    prog
+}
+
+fn cfg_size(cfg: &Cfg) -> usize {
+    cfg.nodes
+        .iter()
+        .map(|node| node.lock().unwrap().blk.instrs.len())
+        .sum()
+}
+
+fn fresh_func_name(call_graph: &CallGraph, func: &str, cnt: &mut usize) -> String {
+    loop {
+        let name = format!("{func}.spec.{cnt}");
+        *cnt += 1;
+        if !call_graph.funcs.contains(&name) {
+            break name;
+        }
+    }
+}
+
+struct ConstCallSite {
+    node: NodeRef,
+    idx: usize,
+    callee: String,
+    const_args: ConstArgs,
+}
+
+/// call sites to known callees with at least one const arg
+fn find_const_call_sites(cfg: &Cfg, callees: &HashMap<String, usize>) -> Vec<ConstCallSite> {
+    let const_out_flows = analyzer::find_global_const_folding_ctx(cfg);
+    let root_ptr = cfg.root.as_ptr();
+    let mut call_sites = vec![];
+    for node in &cfg.nodes {
+        // consts agreed on by every predecessor
+        let mut consts: HashMap<String, ValueLit> = if Arc::as_ptr(node) == root_ptr {
+            HashMap::new()
+        } else {
+            node.lock()
+                .unwrap()
+                .predecessors
+                .iter()
+                .map(|pred| const_out_flows.get(&pred.as_ptr()).unwrap().clone())
+                .reduce(|mut consts, pred_consts| {
+                    consts.retain(|var, lit| pred_consts.get(var) == Some(lit));
+                    consts
+                })
+                .unwrap_or_default()
+        };
+        for (idx, inst) in node.lock().unwrap().blk.instrs.iter().enumerate() {
+            let LabelOrInst::Inst {
+                op,
+                dest,
+                args,
+                funcs,
+                value,
+                ..
+            } = inst
+            else {
+                continue;
+            };
+            if let Some(callee) = funcs
+                .as_ref()
+                .filter(|_| op == "call")
+                .map(|funcs| &funcs[0])
+                .filter(|callee| callees.contains_key(*callee))
+            {
+                let const_args: ConstArgs = args
+                    .iter()
+                    .flatten()
+                    .map(|arg| consts.get(arg).copied())
+                    .collect();
+                if const_args.iter().any(Option::is_some) {
+                    call_sites.push(ConstCallSite {
+                        node: Arc::clone(node),
+                        idx,
+                        callee: callee.clone(),
+                        const_args,
+                    });
+                }
+            }
+            let Some(dest) = dest else {
+                continue;
+            };
+            let const_lit = match op.as_str() {
+                "const" => *value,
+                "id" => consts.get(&args.as_ref().unwrap()[0]).copied(),
+                _ => None,
+            };
+            if let Some(const_lit) = const_lit {
+                consts.insert(dest.clone(), const_lit);
+            } else {
+                consts.remove(dest);
+            }
+        }
+    }
+    call_sites
+}
+
+/// copy of the callee with const params bound at a fresh entry block
+fn specialize(callee: &Cfg, name: &str, const_args: &ConstArgs) -> Cfg {
+    let params = callee.func_ctx.args.clone().unwrap_or_default();
+    let mut entry = BasicBlock {
+        label: None,
+        instrs: vec![],
+    };
+    let mut kept = vec![];
+    for (param, const_arg) in params.into_iter().zip(const_args) {
+        if let Some(const_lit) = const_arg {
+            entry.instrs.push(LabelOrInst::Inst {
+                op: "const".to_string(),
+                dest: Some(param.name),
+                ty: Some(param.ty),
+                args: None,
+                funcs: None,
+                labels: None,
+                value: Some(*const_lit),
+            });
+        } else {
+            kept.push(param);
+        }
+    }
+
+    let mut nodes = Cfg::clone_nodes(&callee.nodes, &HashMap::new());
+    let root_labeled = nodes[0].lock().unwrap().label.is_some();
+    if !root_labeled {
+        // entry has no predecessor, consts are simply bound in front of it
+        let mut root_lock = nodes[0].lock().unwrap();
+        entry.instrs.append(&mut root_lock.blk.instrs);
+        root_lock.blk.instrs = entry.instrs;
+    } else {
+        nodes.insert(
+            0,
+            Arc::new(Mutex::new(CfgNode {
+                label: None,
+                blk: entry,
+                successors: vec![],
+                predecessors: vec![],
+            })),
+        );
+    }
+    let mut cfg = Cfg {
+        root: Default::default(),
+        nodes,
+        func_ctx: FuncCtx {
+            name: name.to_string(),
+            args: (!kept.is_empty()).then_some(kept),
+            ty: callee.func_ctx.ty.clone(),
+        },
+    };
+    cfg.relink();
+    optim::dce(cfg, true)
+}
+
+fn retarget_call_site(call_site: ConstCallSite, clone_name: String) {
+    let mut node_lock = call_site.node.lock().unwrap();
+    let LabelOrInst::Inst { args, funcs, .. } = &mut node_lock.blk.instrs[call_site.idx] else {
+        unreachable!()
+    };
+    *funcs = Some(vec![clone_name]);
+    let kept: Vec<String> = args
+        .take()
+        .unwrap_or_default()
+        .into_iter()
+        .zip(&call_site.const_args)
+        .filter_map(|(arg, const_arg)| const_arg.is_none().then_some(arg))
+        .collect();
+    *args = (!kept.is_empty()).then_some(kept);
+}
@@ -0,0 +1,125 @@
+//! tail-recursion elimination
+//!
+//! A self call in tail position
+//!     r: int = call @f y a;
+//!     ret r;
+//! is replaced by re-binding params `x`, `y` of `@f` and jumping back to the function entry,
+//! which is labeled as the loop header
+//!     f.tailrec.x: int = id y;          <- args referring to params are copied first
+//!     y: int = id a;
+//!     x: int = id f.tailrec.x;
+//!     jmp .f.tailrec;
+//! where `call @f y a` is eliminated, temporaries get fresh names in case the program
+//! already uses `f.tailrec.x`. The result only depends on `FuncCtx::args` and plain
+//! jumps, which stays valid across the ssa round-trip.
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::cfg::ProgCfgs;
+use crate::optim::fuel;
+
+pub fn tail_recursion_elimination(prog: ProgCfgs) -> ProgCfgs {
+    ProgCfgs(prog.0.into_iter().map(eliminate_tail_calls).collect())
+}
+
+pub fn eliminate_tail_calls(mut cfg: Cfg) -> Cfg {
+    let tail_calls: Vec<(NodeRef, usize)> = cfg
+        .nodes
+        .iter()
+        .filter_map(|node| {
+            let node_lock = node.lock().unwrap();
+            let idx = find_tail_call(&node_lock.blk, &cfg.func_ctx.name)?;
+            let describe = || format!("tailrec: `{}`", node_lock.blk.instrs[idx]);
+            fuel::consume(describe).then(|| (NodeRef::clone(node), idx))
+        })
+        .collect();
+    if tail_calls.is_empty() {
+        return cfg;
+    }
+
+    let root = cfg.root.upgrade().unwrap();
+    let root_label = root.lock().unwrap().label.clone();
+    let header = root_label.unwrap_or_else(|| {
+        let header = cfg.fresh_label(&format!("{}.tailrec", cfg.func_ctx.name));
+        let mut root_lock = root.lock().unwrap();
+        root_lock.blk.set_label(header.clone());
+        root_lock.label = Some(header.clone());
+        header
+    });
+
+    let params = cfg.func_ctx.args.clone().unwrap_or_default();
+    let hints: Vec<String> = params
+        .iter()
+        .map(|param| format!("{header}.{}", param.name))
+        .collect();
+    let tmps = cfg.fresh_vars(&hints);
+    for (node, idx) in tail_calls {
+        let mut node_lock = node.lock().unwrap();
+        let Some(LabelOrInst::Inst {
+            args: Some(call_args),
+            ..
+        }) = node_lock.blk.instrs.drain(idx..).next()
+        else {
+            unreachable!()
+        };
+        let (mut copy_in, mut copy_out) = (vec![], vec![]);
+        for ((param, arg), tmp) in params.iter().zip(call_args).zip(&tmps) {
+            if param.name == arg {
+                continue;
+            }
+            if params.iter().any(|other| other.name == arg) {
+                // might have been overwritten by the time param is assigned
+                copy_in.push(id(tmp, &param.ty, &arg));
+                copy_out.push(id(&param.name, &param.ty, tmp));
+            } else {
+                copy_out.push(id(&param.name, &param.ty, &arg));
+            }
+        }
+        node_lock.blk.instrs.extend(copy_in);
+        node_lock.blk.instrs.extend(copy_out);
+        node_lock.blk.instrs.push(LabelOrInst::Inst {
+            op: "jmp".to_string(),
+            dest: None,
+            ty: None,
+            args: None,
+            funcs: None,
+            labels: Some(vec![header.clone()]),
+            value: None,
+        });
+        eprintln!("tail call eliminated in @{}", cfg.func_ctx.name);
+    }
+    cfg.relink();
+    cfg
+}
+
+/// index of a self call immediately followed by a `ret` of its result
+fn find_tail_call(blk: &BasicBlock, func_name: &str) -> Option<usize> {
+    blk.instrs.windows(2).position(|window| {
+        let [LabelOrInst::Inst {
+            op: call_op,
+            dest,
+            funcs: Some(funcs),
+            ..
+        }, LabelOrInst::Inst {
+            op: ret_op,
+            args: ret_args,
+            ..
+        }] = window
+        else {
+            return false;
+        };
+        let ret_value = ret_args.as_ref().and_then(|args| args.first());
+        call_op == "call" && ret_op == "ret" && funcs[0] == func_name && dest.as_ref() == ret_value
+    })
+}
+
+fn id(dest: &str, ty: &str, arg: &str) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "id".to_string(),
+        dest: Some(dest.to_string()),
+        ty: Some(ty.to_string()),
+        args: Some(vec![arg.to_string()]),
+        funcs: None,
+        labels: None,
+        value: None,
+    }
+}
@@ -0,0 +1,596 @@
+//! loop unrolling on non-ssa cfg
+//!
+//! Only counted loops are handled, that is natural loops of the shape
+//!     .header:
+//!         ...
+//!         cond: bool = lt iv bound;
+//!         br cond .body .exit;
+//!     .body:
+//!         ...
+//!     .latch:
+//!         ...
+//!         iv: int = add iv step;
+//!         jmp .header;
+//! where `iv` is defined exactly once within the loop, `bound` is loop invariant
+//! and `step` is a compile time constant
+//!
+//! - if the trip count is a known small constant, the loop is fully unrolled
+//! - otherwise the loop body is replicated `factor` times behind a guard checking that
+//!   at least `factor` iterations remain, the original loop is kept as the remainder loop
+use crate::analyzer::{self, scc::find_sccs};
+use crate::bril::{LabelOrInst, ValueLit};
+use crate::cfg::prelude::*;
+use crate::optim::fuel;
+use crate::optim::loops::{find_natural_loops, NaturalLoop};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+/// loops iterating more than this will not be fully unrolled
+pub const FULL_UNROLL_MAX_TRIP_COUNT: usize = 16;
+/// max number of instrs allowed after full unrolling or partial unrolling
+pub const UNROLL_SIZE_BUDGET: usize = 256;
+
+pub fn loop_unrolling(mut cfg: Cfg, factor: usize) -> Cfg {
+    cfg.make_jumps_explicit();
+    let mut visited = HashSet::new();
+    loop {
+        let comps = find_sccs(&cfg);
+        let natural_loops = find_natural_loops(&cfg, &comps);
+        let Some(counted_loop) = natural_loops.iter().find_map(|natural_loop| {
+            let header = natural_loop.entry.lock().unwrap().label.clone()?;
+            if visited.contains(&header) {
+                None
+            } else {
+                visited.insert(header);
+                CountedLoop::from_natural_loop(&cfg, natural_loop)
+            }
+        }) else {
+            break;
+        };
+
+        let size = counted_loop.size();
+        match counted_loop.trip_count {
+            Some(trip_count)
+                if trip_count <= FULL_UNROLL_MAX_TRIP_COUNT
+                    && size.saturating_mul(trip_count + 1) <= UNROLL_SIZE_BUDGET
+                    && fuel::consume(|| {
+                        format!("unroll: fully unroll .{}", counted_loop.header)
+                    }) =>
+            {
+                eprintln!(
+                    "loop .{} fully unrolled, trip count: {trip_count}",
+                    counted_loop.header
+                );
+                counted_loop.fully_unroll(&mut cfg, trip_count);
+            }
+            _ if factor > 1
+                && size.saturating_mul(factor.saturating_add(1)) <= UNROLL_SIZE_BUDGET
+                && counted_loop.guard_offset(factor).is_some()
+                && fuel::consume(|| {
+                    format!("unroll: unroll .{} by {factor}", counted_loop.header)
+                }) =>
+            {
+                eprintln!(
+                    "loop .{} partially unrolled by {factor}",
+                    counted_loop.header
+                );
+                counted_loop.partially_unroll(&mut cfg, factor);
+            }
+            _ => {}
+        }
+    }
+    cfg.elide_fallthrough_jumps();
+    cfg
+}
+
+/// natural loop with a single exit test at the header against a linear induction variable
+pub struct CountedLoop {
+    pub header: String,
+    pub exit: String,
+    /// labels of all blocks in the loop, in layout order
+    pub blocks: Vec<String>,
+    /// label of the block the header branches to when staying in the loop
+    pub body: String,
+    pub iv: String,
+    pub bound: String,
+    pub step: i32,
+    /// comparison op under which the loop keeps iterating, i.e. `iv <cmp> bound`
+    pub cmp: String,
+    pub trip_count: Option<usize>,
+    size: usize,
+}
+
+impl CountedLoop {
+    /// returns None if `natural_loop` is not a counted loop
+    pub fn from_natural_loop(cfg: &Cfg, natural_loop: &NaturalLoop<'_>) -> Option<Self> {
+        let entry_ptr = Arc::as_ptr(&natural_loop.entry);
+        if entry_ptr == Weak::as_ptr(&cfg.root)
+            || natural_loop.exits.len() != 1
+            || Arc::as_ptr(&natural_loop.exits[0]) != entry_ptr
+        {
+            return None;
+        }
+        let comp = natural_loop.comp.lock().unwrap();
+        let loop_nodes: Vec<_> = cfg
+            .nodes
+            .iter()
+            .filter(|node| comp.contains(&Arc::as_ptr(node)))
+            .cloned()
+            .collect();
+        let blocks: Vec<String> = loop_nodes
+            .iter()
+            .map(|node| node.lock().unwrap().label.clone())
+            .collect::<Option<_>>()?;
+        if loop_nodes.iter().any(|node| {
+            node.lock()
+                .unwrap()
+                .blk
+                .instrs
+                .iter()
+                .any(|inst| matches!(inst, LabelOrInst::Inst { op, .. } if op == "ret"))
+        }) {
+            return None;
+        }
+
+        let size = loop_nodes
+            .iter()
+            .map(|node| {
+                node.lock()
+                    .unwrap()
+                    .blk
+                    .instrs
+                    .iter()
+                    .filter(|inst| matches!(inst, LabelOrInst::Inst { .. }))
+                    .count()
+            })
+            .sum();
+
+        let header = natural_loop.entry.lock().unwrap();
+        let header_label = header.label.clone()?;
+        let Some(LabelOrInst::Inst {
+            op,
+            args: Some(br_args),
+            labels: Some(br_labels),
+            ..
+        }) = header.blk.instrs.last()
+        else {
+            return None;
+        };
+        if op != "br" {
+            return None;
+        }
+        let stays_on_true = blocks.contains(&br_labels[0]);
+        if stays_on_true == blocks.contains(&br_labels[1]) {
+            return None;
+        }
+        let (body, exit) = if stays_on_true {
+            (br_labels[0].clone(), br_labels[1].clone())
+        } else {
+            (br_labels[1].clone(), br_labels[0].clone())
+        };
+
+        // single latch ending with an unconditional jump, executed exactly once per iteration
+        let latches: Vec<_> = header
+            .predecessors
+            .iter()
+            .filter(|pred| comp.contains(&Weak::as_ptr(pred)))
+            .collect();
+        if latches.len() != 1 {
+            return None;
+        }
+        let latch = latches[0].upgrade().unwrap();
+        drop(comp);
+        if Arc::ptr_eq(&latch, &natural_loop.entry) {
+            return None;
+        }
+
+        let (cmp, lhs, rhs) = header.blk.instrs.iter().rev().find_map(|inst| match inst {
+            LabelOrInst::Inst {
+                op,
+                dest: Some(dest),
+                args: Some(args),
+                ..
+            } if *dest == br_args[0] => {
+                Some((op.clone(), args.first()?.clone(), args.get(1)?.clone()))
+            }
+            _ => None,
+        })?;
+        drop(header);
+
+        let mut defs_in_loop: HashMap<String, Vec<(NodePtr, LabelOrInst)>> = HashMap::new();
+        for node in &loop_nodes {
+            for inst in &node.lock().unwrap().blk.instrs {
+                if let LabelOrInst::Inst {
+                    dest: Some(dest), ..
+                } = inst
+                {
+                    defs_in_loop
+                        .entry(dest.clone())
+                        .or_default()
+                        .push((Arc::as_ptr(node), inst.clone()));
+                }
+            }
+        }
+        let num_defs = |var: &String| defs_in_loop.get(var).map_or(0, Vec::len);
+        let (iv, bound, cmp) = if num_defs(&lhs) == 1 && num_defs(&rhs) == 0 {
+            (lhs, rhs, cmp)
+        } else if num_defs(&rhs) == 1 && num_defs(&lhs) == 0 {
+            (rhs, lhs, swap_cmp(&cmp)?.to_string())
+        } else {
+            return None;
+        };
+        let cmp = if stays_on_true {
+            cmp
+        } else {
+            negate_cmp(&cmp)?.to_string()
+        };
+
+        let (def_ptr, iv_def) = &defs_in_loop.get(&iv).unwrap()[0];
+        if *def_ptr != Arc::as_ptr(&latch) {
+            return None;
+        }
+        let LabelOrInst::Inst {
+            op,
+            args: Some(args),
+            ..
+        } = iv_def
+        else {
+            return None;
+        };
+        let const_ctx = ConstCtx::new(cfg, natural_loop, &defs_in_loop);
+        let step = match (op.as_str(), args.as_slice()) {
+            ("add", [a, b]) if *a == iv => const_ctx.int(b)?,
+            ("add", [a, b]) if *b == iv => const_ctx.int(a)?,
+            ("sub", [a, b]) if *a == iv => const_ctx.int(b)?.checked_neg()?,
+            _ => return None,
+        };
+        let increasing = match cmp.as_str() {
+            "lt" | "le" => true,
+            "gt" | "ge" => false,
+            _ => return None,
+        };
+        if step == 0 || (step > 0) != increasing {
+            return None;
+        }
+
+        let trip_count = match (const_ctx.int_at_entry(&iv), const_ctx.int(&bound)) {
+            (Some(init), Some(bound)) => eval_trip_count(&cmp, init, bound, step),
+            _ => None,
+        };
+        Some(Self {
+            header: header_label,
+            exit,
+            blocks,
+            body,
+            iv,
+            bound,
+            step,
+            cmp,
+            trip_count,
+            size,
+        })
+    }
+
+    /// number of instrs within the loop
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    fn loop_nodes(&self, cfg: &Cfg) -> Vec<NodeRef> {
+        self.blocks
+            .iter()
+            .map(|label| cfg.node_by_label(label).unwrap())
+            .collect()
+    }
+
+    /// produce `num` consecutive copies of the loop, in each copy the exit test is dropped and
+    /// the backedge is routed to the header of the next copy. The last copy jumps to `tail`.
+    /// Returns the copies along with the label of the first header
+    fn unrolled_copies(&self, cfg: &Cfg, num: usize, tail: &str) -> (Vec<NodeRef>, String) {
+        let loop_nodes = self.loop_nodes(cfg);
+        let schemes: Vec<HashMap<String, String>> = (0..num)
+            .map(|k| {
+                self.blocks
+                    .iter()
+                    .map(|label| {
+                        (
+                            label.clone(),
+                            cfg.fresh_label(&format!("{label}.unroll.{k}")),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut copies = vec![];
+        for (k, scheme) in schemes.iter().enumerate() {
+            let next_header = schemes
+                .get(k + 1)
+                .map_or(tail, |next| next.get(&self.header).unwrap());
+            let cloned = Cfg::clone_nodes(&loop_nodes, scheme);
+            for node in &cloned {
+                let mut node_lock = node.lock().unwrap();
+                node_lock
+                    .blk
+                    .redirect_jumps(scheme.get(&self.header).unwrap(), next_header);
+                if node_lock.label.as_ref() == scheme.get(&self.header) {
+                    let body = scheme.get(&self.body).unwrap();
+                    *node_lock.blk.instrs.last_mut().unwrap() = jmp(body);
+                }
+            }
+            copies.extend(cloned);
+        }
+        let first_header = schemes.first().map_or(tail.to_string(), |scheme| {
+            scheme.get(&self.header).unwrap().clone()
+        });
+        (copies, first_header)
+    }
+
+    /// replace the loop with `trip_count` copies of its body followed by a final exit test
+    pub fn fully_unroll(&self, cfg: &mut Cfg, trip_count: usize) {
+        let header_node = cfg.node_by_label(&self.header).unwrap();
+        let tail_label = cfg.fresh_label(&format!("{}.unroll.exit", self.header));
+        let tail = Cfg::clone_nodes(
+            &[header_node],
+            &HashMap::from([(self.header.clone(), tail_label.clone())]),
+        )
+        .pop()
+        .unwrap();
+        *tail.lock().unwrap().blk.instrs.last_mut().unwrap() = jmp(&self.exit);
+
+        let (mut copies, first_header) = self.unrolled_copies(cfg, trip_count, &tail_label);
+        copies.push(tail);
+        self.replace_loop(cfg, copies, &first_header, false);
+    }
+
+    /// distance between `iv` at the guard and at the last of `factor` unrolled exit tests,
+    /// `None` if it does not fit into a const
+    pub fn guard_offset(&self, factor: usize) -> Option<i32> {
+        self.step
+            .checked_mul(i32::try_from(factor.checked_sub(1)?).ok()?)
+    }
+
+    /// run the loop `factor` iterations at a time as long as the next `factor` exit tests
+    /// are known to succeed, finishing the remaining iterations in the original loop
+    ///
+    /// The guard tests `iv + offset` in place of those exit tests, which only holds if the
+    /// addition does not wrap around, otherwise the remainder loop takes over.
+    /// Leaves `cfg` untouched if the offset overflows, see [`Self::guard_offset`].
+    pub fn partially_unroll(&self, cfg: &mut Cfg, factor: usize) {
+        let Some(offset) = self.guard_offset(factor) else {
+            return;
+        };
+        let guard_label = cfg.fresh_label(&format!("{}.unroll.guard", self.header));
+        let (copies, first_header) = self.unrolled_copies(cfg, factor, &guard_label);
+
+        let (offset_var, last_iv, cond, no_wrap, enter) = (
+            format!("{guard_label}.offset"),
+            format!("{guard_label}.iv"),
+            format!("{guard_label}.cond"),
+            format!("{guard_label}.nowrap"),
+            format!("{guard_label}.enter"),
+        );
+        // `iv + offset` moves away from `iv` in the direction of `step` unless it wraps
+        let away = if self.step > 0 { "gt" } else { "lt" };
+        let mut guard_blk = BasicBlock {
+            label: None,
+            instrs: vec![
+                serde_json::from_str(&format!(
+                    r#"{{"dest": "{offset_var}", "op": "const", "type": "int", "value": {offset}}}"#
+                ))
+                .unwrap(),
+                serde_json::from_str(&format!(
+                    r#"{{"dest": "{last_iv}", "op": "add", "type": "int", "args": ["{}", "{offset_var}"]}}"#,
+                    self.iv
+                ))
+                .unwrap(),
+                serde_json::from_str(&format!(
+                    r#"{{"dest": "{cond}", "op": "{}", "type": "bool", "args": ["{last_iv}", "{}"]}}"#,
+                    self.cmp, self.bound
+                ))
+                .unwrap(),
+                serde_json::from_str(&format!(
+                    r#"{{"dest": "{no_wrap}", "op": "{away}", "type": "bool", "args": ["{last_iv}", "{}"]}}"#,
+                    self.iv
+                ))
+                .unwrap(),
+                serde_json::from_str(&format!(
+                    r#"{{"dest": "{enter}", "op": "and", "type": "bool", "args": ["{cond}", "{no_wrap}"]}}"#
+                ))
+                .unwrap(),
+                serde_json::from_str(&format!(
+                    r#"{{"op": "br", "args": ["{enter}"], "labels": ["{first_header}", "{}"]}}"#,
+                    self.header
+                ))
+                .unwrap(),
+            ],
+        };
+        guard_blk.set_label(guard_label.clone());
+        let guard = Arc::new(std::sync::Mutex::new(CfgNode {
+            label: Some(guard_label.clone()),
+            blk: guard_blk,
+            successors: vec![],
+            predecessors: vec![],
+        }));
+
+        let mut nodes = vec![guard];
+        nodes.extend(copies);
+        self.replace_loop(cfg, nodes, &guard_label, true);
+    }
+
+    /// insert `nodes` in front of the loop header and redirect entering jumps to `new_entry`
+    fn replace_loop(&self, cfg: &mut Cfg, nodes: Vec<NodeRef>, new_entry: &str, keep_loop: bool) {
+        let header_node = cfg.node_by_label(&self.header).unwrap();
+        let outside_preds: Vec<_> = header_node
+            .lock()
+            .unwrap()
+            .predecessors
+            .iter()
+            .filter(|pred| {
+                let label = pred.upgrade().unwrap().lock().unwrap().label.clone();
+                !label.is_some_and(|label| self.blocks.contains(&label))
+            })
+            .map(|pred| pred.upgrade().unwrap())
+            .collect();
+        for pred in outside_preds {
+            pred.lock()
+                .unwrap()
+                .blk
+                .redirect_jumps(&self.header, new_entry);
+        }
+
+        let header_idx = cfg
+            .nodes
+            .iter()
+            .position(|node| Arc::ptr_eq(node, &header_node))
+            .unwrap();
+        cfg.nodes.splice(header_idx..header_idx, nodes);
+        if !keep_loop {
+            cfg.nodes.retain(|node| {
+                let label = node.lock().unwrap().label.clone();
+                !label.is_some_and(|label| self.blocks.contains(&label))
+            });
+        }
+        cfg.relink();
+    }
+}
+
+/// constants known at loop entry, a var is considered to be const if its reaching defs from outside
+/// of the loop agree on the same const, or it is only ever assigned with the same const
+struct ConstCtx {
+    at_entry: HashMap<String, ValueLit>,
+    uniform: HashMap<String, Option<ValueLit>>,
+    defined_in_loop: HashSet<String>,
+}
+
+impl ConstCtx {
+    fn new(
+        cfg: &Cfg,
+        natural_loop: &NaturalLoop<'_>,
+        defs_in_loop: &HashMap<String, Vec<(NodePtr, LabelOrInst)>>,
+    ) -> Self {
+        let global_ctx = analyzer::find_global_const_folding_ctx(cfg);
+        let outside_preds: Vec<_> = natural_loop
+            .entry
+            .lock()
+            .unwrap()
+            .predecessors
+            .iter()
+            .map(Weak::as_ptr)
+            .filter(|pred| !natural_loop.comp.lock().unwrap().contains(pred))
+            .collect();
+        let mut at_entry: Option<HashMap<String, ValueLit>> = None;
+        for pred in &outside_preds {
+            let consts = global_ctx.get(pred).unwrap();
+            at_entry = Some(match at_entry {
+                None => consts.clone(),
+                Some(mut acc) => {
+                    acc.retain(|var, lit| consts.get(var) == Some(lit));
+                    acc
+                }
+            });
+        }
+        let at_entry = at_entry.unwrap_or_default();
+
+        let mut uniform: HashMap<String, Option<ValueLit>> = HashMap::new();
+        let func_args = cfg.func_ctx.args_name().unwrap_or_default();
+        for node in &cfg.nodes {
+            for inst in &node.lock().unwrap().blk.instrs {
+                if let LabelOrInst::Inst {
+                    op,
+                    dest: Some(dest),
+                    value,
+                    ..
+                } = inst
+                {
+                    let lit = if op == "const" { *value } else { None };
+                    uniform
+                        .entry(dest.clone())
+                        .and_modify(|prev| {
+                            if *prev != lit {
+                                *prev = None
+                            }
+                        })
+                        .or_insert(lit);
+                }
+            }
+        }
+        uniform.retain(|var, _| !func_args.contains(var));
+        Self {
+            at_entry,
+            uniform,
+            defined_in_loop: defs_in_loop.keys().cloned().collect(),
+        }
+    }
+
+    /// value of `var` when control first enters the loop
+    fn int_at_entry(&self, var: &str) -> Option<i32> {
+        match self.at_entry.get(var) {
+            Some(ValueLit::Int(val)) => Some(*val),
+            _ => self.int(var),
+        }
+    }
+
+    /// value of `var` throughout the loop
+    fn int(&self, var: &str) -> Option<i32> {
+        let lit = if self.defined_in_loop.contains(var) {
+            self.uniform.get(var).copied().flatten()
+        } else {
+            self.at_entry
+                .get(var)
+                .copied()
+                .or_else(|| self.uniform.get(var).copied().flatten())
+        };
+        match lit {
+            Some(ValueLit::Int(val)) => Some(val),
+            _ => None,
+        }
+    }
+}
+
+/// simulate the exit test, gives up once the loop iterates more than the full-unroll threshold
+fn eval_trip_count(cmp: &str, init: i32, bound: i32, step: i32) -> Option<usize> {
+    let (mut iv, bound, step) = (init as i64, bound as i64, step as i64);
+    let mut trip_count = 0;
+    while match cmp {
+        "lt" => iv < bound,
+        "le" => iv <= bound,
+        "gt" => iv > bound,
+        "ge" => iv >= bound,
+        _ => unreachable!(),
+    } {
+        trip_count += 1;
+        iv += step;
+        if trip_count > FULL_UNROLL_MAX_TRIP_COUNT {
+            return None;
+        }
+    }
+    Some(trip_count)
+}
+
+/// `a <cmp> b` iff `b <swapped> a`
+fn swap_cmp(cmp: &str) -> Option<&'static str> {
+    match cmp {
+        "lt" => Some("gt"),
+        "le" => Some("ge"),
+        "gt" => Some("lt"),
+        "ge" => Some("le"),
+        _ => None,
+    }
+}
+
+/// `a <cmp> b` iff not `a <negated> b`
+fn negate_cmp(cmp: &str) -> Option<&'static str> {
+    match cmp {
+        "lt" => Some("ge"),
+        "le" => Some("gt"),
+        "gt" => Some("le"),
+        "ge" => Some("lt"),
+        _ => None,
+    }
+}
+
+fn jmp(label: &str) -> LabelOrInst {
+    serde_json::from_str(&format!(r#"{{"op": "jmp", "labels": ["{label}"]}}"#)).unwrap()
+}
@@ -0,0 +1,209 @@
+//! loop unswitching on non-ssa cfg
+//!
+//! A `br` within a natural loop whose condition is not defined in the loop always goes
+//! the same way during one execution of the loop. The branch is hoisted into the preheader,
+//! which then selects between two copies of the loop, each specialized to one branch direction
+//!     .preheader:
+//!         br cond .header.unswitch.t .header.unswitch.f;
+//!     .header.unswitch.t:   <- loop with `br cond .a .b` replaced by `jmp .a`
+//!     .header.unswitch.f:   <- loop with `br cond .a .b` replaced by `jmp .b`
+use crate::analyzer::{dom::DomTree, scc::find_sccs};
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::optim::fuel;
+use crate::optim::loops::{find_natural_loops, NaturalLoop};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+/// max number of instrs the pass is allowed to add to a function
+pub const UNSWITCH_SIZE_BUDGET: usize = 128;
+
+pub fn loop_unswitching(mut cfg: Cfg) -> Cfg {
+    cfg.make_jumps_explicit();
+    let mut budget = UNSWITCH_SIZE_BUDGET;
+    loop {
+        let comps = find_sccs(&cfg);
+        let mut natural_loops = find_natural_loops(&cfg, &comps);
+        let dom_tree = DomTree::from_cfg(&cfg);
+        let Some((idx, candidate)) =
+            natural_loops
+                .iter()
+                .enumerate()
+                .find_map(|(idx, natural_loop)| {
+                    let candidate = find_invariant_branch(&cfg, natural_loop, &dom_tree)?;
+                    (candidate.loop_size < budget).then_some((idx, candidate))
+                })
+        else {
+            break;
+        };
+        // fuel never comes back once refused, the same branch would be found again
+        let describe = || {
+            format!(
+                "unswitch: on {} at .{}",
+                candidate.cond, candidate.branch_blk
+            )
+        };
+        if !fuel::consume(describe) {
+            break;
+        }
+        budget -= candidate.loop_size + 1;
+        eprintln!(
+            "loop unswitched on {} at .{}",
+            candidate.cond, candidate.branch_blk
+        );
+        unswitch(&mut cfg, &mut natural_loops[idx], &candidate);
+    }
+    cfg.elide_fallthrough_jumps();
+    cfg
+}
+
+struct InvariantBranch {
+    /// label of the block ending with the invariant branch
+    branch_blk: String,
+    cond: String,
+    loop_size: usize,
+}
+
+fn find_invariant_branch(
+    cfg: &Cfg,
+    natural_loop: &NaturalLoop<'_>,
+    dom_tree: &DomTree,
+) -> Option<InvariantBranch> {
+    let entry_ptr = Arc::as_ptr(&natural_loop.entry);
+    if entry_ptr == Weak::as_ptr(&cfg.root) {
+        return None;
+    }
+    let comp = natural_loop.comp.lock().unwrap();
+    let loop_nodes: Vec<_> = cfg
+        .nodes
+        .iter()
+        .filter(|node| comp.contains(&Arc::as_ptr(node)))
+        .cloned()
+        .collect();
+    drop(comp);
+
+    let defined_in_loop: HashSet<String> = loop_nodes
+        .iter()
+        .flat_map(|node| node.lock().unwrap().blk.defs())
+        .collect();
+    let func_args = cfg.func_ctx.args_name().unwrap_or_default();
+    // cond should be initialized on every path reaching the loop, otherwise hoisting
+    // the branch may introduce use of undefined variable
+    let defined_before_loop = |var: &String| {
+        func_args.contains(var)
+            || cfg.nodes.iter().any(|node| {
+                node.lock().unwrap().blk.defs().contains(var)
+                    && dom_tree.is_dominator_of(Arc::as_ptr(node), entry_ptr)
+            })
+    };
+
+    let loop_size = loop_nodes
+        .iter()
+        .map(|node| {
+            node.lock()
+                .unwrap()
+                .blk
+                .instrs
+                .iter()
+                .filter(|inst| matches!(inst, LabelOrInst::Inst { .. }))
+                .count()
+        })
+        .sum();
+    loop_nodes.iter().find_map(|node| {
+        let node_lock = node.lock().unwrap();
+        let Some(LabelOrInst::Inst {
+            op,
+            args: Some(args),
+            ..
+        }) = node_lock.blk.instrs.last()
+        else {
+            return None;
+        };
+        let cond = &args[0];
+        if op != "br" || defined_in_loop.contains(cond) || !defined_before_loop(cond) {
+            return None;
+        }
+        Some(InvariantBranch {
+            branch_blk: node_lock.label.clone()?,
+            cond: cond.clone(),
+            loop_size,
+        })
+    })
+}
+
+fn unswitch(cfg: &mut Cfg, natural_loop: &mut NaturalLoop<'_>, candidate: &InvariantBranch) {
+    let loop_nodes: Vec<_> = {
+        let comp = natural_loop.comp.lock().unwrap();
+        cfg.nodes
+            .iter()
+            .filter(|node| comp.contains(&Arc::as_ptr(node)))
+            .cloned()
+            .collect()
+    };
+    let loop_ptrs: HashSet<_> = loop_nodes.iter().map(Arc::as_ptr).collect();
+    let header = natural_loop.entry.lock().unwrap().label.clone().unwrap();
+    let header_idx = cfg
+        .nodes
+        .iter()
+        .position(|node| Arc::ptr_eq(node, &natural_loop.entry))
+        .unwrap();
+
+    let mut versions = vec![];
+    let mut version_headers = vec![];
+    for (target_idx, suffix) in [(0, "t"), (1, "f")] {
+        let scheme: HashMap<String, String> = loop_nodes
+            .iter()
+            .map(|node| {
+                let label = node.lock().unwrap().label.clone().unwrap();
+                let renamed = cfg.fresh_label(&format!("{label}.unswitch.{suffix}"));
+                (label, renamed)
+            })
+            .collect();
+        let cloned = Cfg::clone_nodes(&loop_nodes, &scheme);
+        let branch_blk = scheme.get(&candidate.branch_blk).unwrap();
+        for node in &cloned {
+            let mut node_lock = node.lock().unwrap();
+            if node_lock.label.as_ref() != Some(branch_blk) {
+                continue;
+            }
+            let inst = node_lock.blk.instrs.last_mut().unwrap();
+            if let LabelOrInst::Inst {
+                op,
+                args,
+                labels: Some(labels),
+                ..
+            } = inst
+            {
+                *op = "jmp".to_string();
+                *args = None;
+                *labels = vec![labels[target_idx].clone()];
+            }
+        }
+        version_headers.push(scheme.get(&header).unwrap().clone());
+        versions.extend(cloned);
+    }
+
+    let preheader = natural_loop.inject_preheader_node(cfg);
+    preheader
+        .lock()
+        .unwrap()
+        .blk
+        .instrs
+        .push(LabelOrInst::Inst {
+            op: "br".to_string(),
+            dest: None,
+            ty: None,
+            args: Some(vec![candidate.cond.clone()]),
+            funcs: None,
+            labels: Some(version_headers),
+            value: None,
+        });
+    versions.insert(0, preheader);
+    cfg.nodes.splice(header_idx..header_idx, versions);
+    cfg.nodes
+        .retain(|node| !loop_ptrs.contains(&Arc::as_ptr(node)));
+    cfg.relink();
+    // the untaken side of the branch in each version becomes dead
+    cfg.prune_unreachable();
+}
@@ -0,0 +1,258 @@
+//! pass pipelines written as text, e.g. `ssa,licm,from-ssa,dce(global)`
+//!
+//! Passes are separated by commas and applied from left to right, options of a pass go into
+//! parentheses after its name. Interprocedural passes see the whole program, every other pass
+//! runs on one function at a time.
+use crate::analyzer::effect::EffectSummary;
+use crate::cfg::{Cfg, ProgCfgs};
+use crate::optim;
+use crate::transform::cytron::{self, PhiPlacement};
+use crate::transform::phi::{self, SsaForm};
+use crate::transform::ssa;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// name, options and a one-line summary of every pass, in the order `bril-opt` lists them
+pub const PASSES: &[(&str, &str, &str)] = &[
+    (
+        "dce",
+        "global",
+        "local value numbering and dead code elimination, `global` folds consts across blocks",
+    ),
+    (
+        "adce",
+        "",
+        "aggressive dce, also removes branches and loops without observable effect",
+    ),
+    ("gvn", "", "global value numbering along the dominator tree"),
+    ("licm", "", "loop invariant code motion into preheaders"),
+    (
+        "ssa",
+        "minimal|semi-pruned|pruned",
+        "into ssa in `set`/`get` form, options pick cytron's phi placement",
+    ),
+    ("phi", "", "encode ssa with `phi` instead of `set`/`get`"),
+    ("from-ssa", "", "out of ssa, from either encoding"),
+    (
+        "unroll",
+        "<factor>",
+        "full unrolling of counted loops, partial by `factor` otherwise, 4 by default",
+    ),
+    ("unswitch", "", "hoist loop invariant branches out of loops"),
+    (
+        "rotate",
+        "",
+        "turn top-tested loops into bottom-tested ones",
+    ),
+    ("tailrec", "", "turn self calls in tail position into jumps"),
+    ("inline", "", "inline small non-recursive functions"),
+    (
+        "prune",
+        "",
+        "drop unreachable functions, unused params and unused return values",
+    ),
+    (
+        "specialize",
+        "",
+        "clone functions for call sites passing consts",
+    ),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    Dce {
+        global: bool,
+    },
+    Adce,
+    Gvn,
+    Licm,
+    /// `None` for the dominance-free construction
+    Ssa(Option<PhiPlacement>),
+    Phi,
+    FromSsa,
+    Unroll(usize),
+    Unswitch,
+    Rotate,
+    Tailrec,
+    Inline,
+    Prune,
+    Specialize,
+}
+
+impl Pass {
+    pub fn apply(self, prog: ProgCfgs) -> ProgCfgs {
+        match self {
+            Pass::Tailrec => optim::tailrec::tail_recursion_elimination(prog),
+            Pass::Inline => optim::inline::inline_functions(prog),
+            Pass::Prune => optim::prune::prune_program(prog),
+            Pass::Specialize => optim::specialize::specialize_functions(prog),
+            Pass::Dce { global } => {
+                let effects = EffectSummary::from_prog(&prog);
+                per_func(prog, |cfg| {
+                    optim::dce::effect_aware_dce(cfg, global, &effects)
+                })
+            }
+            Pass::Gvn => {
+                let effects = EffectSummary::from_prog(&prog);
+                per_func(prog, |cfg| optim::gvn::effect_aware_gvn(cfg, &effects))
+            }
+            _ => per_func(prog, |cfg| self.apply_on_cfg(cfg)),
+        }
+    }
+
+    fn apply_on_cfg(self, cfg: Cfg) -> Cfg {
+        match self {
+            Pass::Adce => optim::dce::adce::aggressive_dce(cfg),
+            Pass::Licm => optim::loops::loop_invariant_code_motion(cfg),
+            Pass::Ssa(None) => ssa::cfg_into_ssa(cfg),
+            Pass::Ssa(Some(placement)) => cytron::cfg_into_ssa_cytron(cfg, placement),
+            Pass::Phi => phi::cfg_into_form(cfg, SsaForm::Phi),
+            Pass::FromSsa => {
+                let cfg = if phi::ssa_form_of(&cfg) == Some(SsaForm::Phi) {
+                    phi::phi_into_set_get(cfg)
+                } else {
+                    cfg
+                };
+                ssa::cfg_from_ssa(cfg)
+            }
+            Pass::Unroll(factor) => optim::unroll::loop_unrolling(cfg, factor),
+            Pass::Unswitch => optim::unswitch::loop_unswitching(cfg),
+            Pass::Rotate => optim::rotate::loop_rotation(cfg),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn per_func(prog: ProgCfgs, f: impl Fn(Cfg) -> Cfg) -> ProgCfgs {
+    ProgCfgs(prog.0.into_iter().map(f).collect())
+}
+
+/// e.g. `dce(global)` or `unroll(8)`
+impl FromStr for Pass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, option) = match s.trim().split_once('(') {
+            Some((name, rest)) => {
+                let option = rest
+                    .strip_suffix(')')
+                    .ok_or_else(|| format!("missing `)` in `{s}`"))?;
+                (name.trim(), Some(option.trim()))
+            }
+            None => (s.trim(), None),
+        };
+        let pass = match (name, option) {
+            ("dce", None) => Pass::Dce { global: false },
+            ("dce", Some("global")) => Pass::Dce { global: true },
+            ("adce", None) => Pass::Adce,
+            ("gvn", None) => Pass::Gvn,
+            ("licm", None) => Pass::Licm,
+            ("ssa", None) => Pass::Ssa(None),
+            ("ssa", Some("minimal")) => Pass::Ssa(Some(PhiPlacement::Minimal)),
+            ("ssa", Some("semi-pruned")) => Pass::Ssa(Some(PhiPlacement::SemiPruned)),
+            ("ssa", Some("pruned")) => Pass::Ssa(Some(PhiPlacement::Pruned)),
+            ("phi", None) => Pass::Phi,
+            ("from-ssa", None) => Pass::FromSsa,
+            ("unroll", None) => Pass::Unroll(4),
+            ("unroll", Some(factor)) => match factor.parse() {
+                Ok(factor) if factor > 0 => Pass::Unroll(factor),
+                _ => {
+                    return Err(format!(
+                        "unroll factor should be a positive int, got `{factor}`"
+                    ))
+                }
+            },
+            ("unswitch", None) => Pass::Unswitch,
+            ("rotate", None) => Pass::Rotate,
+            ("tailrec", None) => Pass::Tailrec,
+            ("inline", None) => Pass::Inline,
+            ("prune", None) => Pass::Prune,
+            ("specialize", None) => Pass::Specialize,
+            (name, option) => {
+                let Some((_, options, _)) = PASSES.iter().find(|(known, ..)| *known == name) else {
+                    return Err(format!(
+                        "unknown pass `{name}`, see `bril-opt --list-passes`"
+                    ));
+                };
+                return Err(match (option, *options) {
+                    (Some(option), "") => format!("`{name}` takes no option, got `{option}`"),
+                    (Some(option), options) => {
+                        format!("`{name}` accepts `{options}`, got `{option}`")
+                    }
+                    (None, _) => unreachable!(),
+                });
+            }
+        };
+        Ok(pass)
+    }
+}
+
+/// prints the form `from_str` accepts
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pass::Dce { global: false } => write!(f, "dce"),
+            Pass::Dce { global: true } => write!(f, "dce(global)"),
+            Pass::Adce => write!(f, "adce"),
+            Pass::Gvn => write!(f, "gvn"),
+            Pass::Licm => write!(f, "licm"),
+            Pass::Ssa(None) => write!(f, "ssa"),
+            Pass::Ssa(Some(PhiPlacement::Minimal)) => write!(f, "ssa(minimal)"),
+            Pass::Ssa(Some(PhiPlacement::SemiPruned)) => write!(f, "ssa(semi-pruned)"),
+            Pass::Ssa(Some(PhiPlacement::Pruned)) => write!(f, "ssa(pruned)"),
+            Pass::Phi => write!(f, "phi"),
+            Pass::FromSsa => write!(f, "from-ssa"),
+            Pass::Unroll(factor) => write!(f, "unroll({factor})"),
+            Pass::Unswitch => write!(f, "unswitch"),
+            Pass::Rotate => write!(f, "rotate"),
+            Pass::Tailrec => write!(f, "tailrec"),
+            Pass::Inline => write!(f, "inline"),
+            Pass::Prune => write!(f, "prune"),
+            Pass::Specialize => write!(f, "specialize"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pipeline(pub Vec<Pass>);
+
+impl Pipeline {
+    pub fn run(&self, prog: ProgCfgs) -> ProgCfgs {
+        self.0.iter().fold(prog, |prog, pass| pass.apply(prog))
+    }
+}
+
+impl FromStr for Pipeline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut passes = vec![];
+        let (mut depth, mut start) = (0, 0);
+        // commas inside parentheses belong to the options of a pass
+        for (i, c) in s.char_indices().chain([(s.len(), ',')]) {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return Err(format!("unmatched `)` in `{s}`")),
+                ')' => depth -= 1,
+                ',' if i == s.len() && depth > 0 => return Err(format!("missing `)` in `{s}`")),
+                ',' if depth == 0 => {
+                    let pass = s[start..i].trim();
+                    if !pass.is_empty() {
+                        passes.push(pass.parse()?);
+                    }
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(Pipeline(passes))
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let passes: Vec<String> = self.0.iter().map(Pass::to_string).collect();
+        write!(f, "{}", passes.join(","))
+    }
+}
@@ -0,0 +1,446 @@
+//! delta-debugging test-case reduction
+//!
+//! Starting from an interesting program, e.g. one a pass miscompiles, candidates get smaller
+//! step by step, a candidate replaces the program if it is still interesting. Strategies run
+//! round after round until none of them makes progress:
+//!   - delete functions, calls to them become constants
+//!   - delete blocks, jumps to a deleted block go to the block right after it
+//!   - delete labels nothing refers to, merging the block into the one before
+//!   - delete instructions, chunks halving in size as in ddmin
+//!   - turn `br` into `jmp` to either target, drop args of `print`
+//!   - replace an instruction by a constant of its type, or one of its operands by a constant
+//!
+//! Candidates whose jumps or calls go nowhere are never tested, the result can always be
+//! loaded as [`ProgCfgs`](crate::cfg::ProgCfgs). Reading an undefined variable may still
+//! happen, whether that is acceptable is up to the interestingness test.
+use crate::bril::{Function, LabelOrInst, Prog, ValueLit};
+
+use std::collections::{HashMap, HashSet};
+
+pub struct Reducer<F: FnMut(&Prog) -> bool> {
+    interesting: F,
+    max_tests: usize,
+    num_tests: usize,
+    fresh_counter: usize,
+}
+
+impl<F: FnMut(&Prog) -> bool> Reducer<F> {
+    pub fn new(interesting: F) -> Self {
+        Self {
+            interesting,
+            max_tests: usize::MAX,
+            num_tests: 0,
+            fresh_counter: 0,
+        }
+    }
+
+    /// give up after testing this many candidates
+    pub fn max_tests(mut self, max_tests: usize) -> Self {
+        self.max_tests = max_tests;
+        self
+    }
+
+    #[inline]
+    pub fn num_tests(&self) -> usize {
+        self.num_tests
+    }
+
+    /// `prog` is expected to be interesting, it is returned as is otherwise
+    pub fn reduce(&mut self, mut prog: Prog) -> Prog {
+        loop {
+            let mut progress = false;
+            progress |= self.delete_functions(&mut prog);
+            progress |= self.delete_blocks(&mut prog);
+            progress |= self.delete_labels(&mut prog);
+            progress |= self.delete_instrs(&mut prog);
+            progress |= self.simplify_branches(&mut prog);
+            progress |= self.drop_print_args(&mut prog);
+            progress |= self.constify_instrs(&mut prog);
+            progress |= self.constify_operands(&mut prog);
+            if !progress || self.num_tests >= self.max_tests {
+                break prog;
+            }
+        }
+    }
+
+    /// replaces `prog` with `candidate` if it is loadable and still interesting
+    fn try_candidate(&mut self, prog: &mut Prog, candidate: Prog) -> bool {
+        if self.num_tests >= self.max_tests || !is_loadable(&candidate) {
+            return false;
+        }
+        self.num_tests += 1;
+        if (self.interesting)(&candidate) {
+            *prog = candidate;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn delete_functions(&mut self, prog: &mut Prog) -> bool {
+        let mut progress = false;
+        let mut i = 0;
+        while i < prog.functions.len() {
+            if prog.functions[i].name == "main" {
+                i += 1;
+                continue;
+            }
+            let mut candidate = prog.clone();
+            let deleted = candidate.functions.remove(i).name;
+            for func in &mut candidate.functions {
+                func.instrs = std::mem::take(&mut func.instrs)
+                    .into_iter()
+                    .filter_map(|inst| match &inst {
+                        LabelOrInst::Inst {
+                            op,
+                            funcs: Some(funcs),
+                            dest,
+                            ty,
+                            ..
+                        } if op == "call" && funcs[0] == deleted => {
+                            constant_of(dest.as_deref()?, ty.as_deref()?)
+                        }
+                        _ => Some(inst),
+                    })
+                    .collect();
+            }
+            if self.try_candidate(prog, candidate) {
+                progress = true;
+            } else {
+                i += 1;
+            }
+        }
+        progress
+    }
+
+    fn delete_blocks(&mut self, prog: &mut Prog) -> bool {
+        let mut progress = false;
+        for f in 0..prog.functions.len() {
+            let mut nth_block = 0;
+            loop {
+                let instrs = &prog.functions[f].instrs;
+                let label_positions: Vec<usize> = instrs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, inst)| matches!(inst, LabelOrInst::Label { .. }))
+                    .map(|(i, _)| i)
+                    .collect();
+                // the last block has nothing to fall into
+                if nth_block + 1 >= label_positions.len() {
+                    break;
+                }
+                let (start, end) = (label_positions[nth_block], label_positions[nth_block + 1]);
+                let (LabelOrInst::Label { label: deleted }, LabelOrInst::Label { label: next }) =
+                    (&instrs[start], &instrs[end])
+                else {
+                    unreachable!()
+                };
+                let (deleted, next) = (deleted.clone(), next.clone());
+                let mut candidate = prog.clone();
+                let func = &mut candidate.functions[f];
+                func.instrs.drain(start..end);
+                retarget(func, &deleted, &next);
+                if self.try_candidate(prog, candidate) {
+                    progress = true;
+                } else {
+                    nth_block += 1;
+                }
+            }
+        }
+        progress
+    }
+
+    fn delete_labels(&mut self, prog: &mut Prog) -> bool {
+        self.rewrite_each_inst(prog, |inst| match inst {
+            LabelOrInst::Label { .. } => vec![vec![]],
+            _ => vec![],
+        })
+    }
+
+    fn delete_instrs(&mut self, prog: &mut Prog) -> bool {
+        let mut progress = false;
+        for f in 0..prog.functions.len() {
+            let num_instrs = |prog: &Prog| inst_positions(&prog.functions[f]).len();
+            let mut chunk = num_instrs(prog).div_ceil(2);
+            while chunk > 0 {
+                let mut start = 0;
+                while start < num_instrs(prog) {
+                    let positions = inst_positions(&prog.functions[f]);
+                    let deleted: HashSet<usize> =
+                        positions.into_iter().skip(start).take(chunk).collect();
+                    let mut candidate = prog.clone();
+                    candidate.functions[f].instrs =
+                        std::mem::take(&mut candidate.functions[f].instrs)
+                            .into_iter()
+                            .enumerate()
+                            .filter(|(i, _)| !deleted.contains(i))
+                            .map(|(_, inst)| inst)
+                            .collect();
+                    if self.try_candidate(prog, candidate) {
+                        progress = true;
+                    } else {
+                        start += chunk;
+                    }
+                }
+                chunk /= 2;
+            }
+        }
+        progress
+    }
+
+    fn simplify_branches(&mut self, prog: &mut Prog) -> bool {
+        self.rewrite_each_inst(prog, |inst| match inst {
+            LabelOrInst::Inst {
+                op,
+                labels: Some(labels),
+                ..
+            } if op == "br" && labels.len() == 2 => labels
+                .iter()
+                .map(|label| LabelOrInst::Inst {
+                    op: "jmp".to_string(),
+                    dest: None,
+                    ty: None,
+                    args: None,
+                    funcs: None,
+                    labels: Some(vec![label.clone()]),
+                    value: None,
+                })
+                .map(|jmp| vec![jmp])
+                .collect(),
+            _ => vec![],
+        })
+    }
+
+    fn drop_print_args(&mut self, prog: &mut Prog) -> bool {
+        self.rewrite_each_inst(prog, |inst| match inst {
+            LabelOrInst::Inst {
+                op,
+                args: Some(args),
+                ..
+            } if op == "print" && args.len() > 1 => (0..args.len())
+                .map(|dropped| {
+                    let mut print = inst.clone();
+                    if let LabelOrInst::Inst {
+                        args: Some(args), ..
+                    } = &mut print
+                    {
+                        args.remove(dropped);
+                    }
+                    vec![print]
+                })
+                .collect(),
+            _ => vec![],
+        })
+    }
+
+    fn constify_instrs(&mut self, prog: &mut Prog) -> bool {
+        self.rewrite_each_inst(prog, |inst| match inst {
+            LabelOrInst::Inst {
+                op,
+                dest: Some(dest),
+                ty: Some(ty),
+                ..
+            } if op != "const" => constant_of(dest, ty).into_iter().map(|c| vec![c]).collect(),
+            _ => vec![],
+        })
+    }
+
+    /// an operand defined by anything but constants is read from a fresh constant instead,
+    /// exposing its definition to deletion
+    fn constify_operands(&mut self, prog: &mut Prog) -> bool {
+        let mut progress = false;
+        for f in 0..prog.functions.len() {
+            let mut i = 0;
+            while i < prog.functions[f].instrs.len() {
+                let (types, const_vars) = var_types(&prog.functions[f]);
+                let LabelOrInst::Inst {
+                    op,
+                    args: Some(args),
+                    ..
+                } = &prog.functions[f].instrs[i]
+                else {
+                    i += 1;
+                    continue;
+                };
+                let replaceable: Vec<usize> = (0..args.len())
+                    .filter(|j| op != "phi" && !const_vars.contains(&args[*j]))
+                    .filter(|j| {
+                        types
+                            .get(&args[*j])
+                            .is_some_and(|ty| ty == "int" || ty == "bool")
+                    })
+                    .collect();
+                for j in replaceable {
+                    let mut candidate = prog.clone();
+                    let instrs = &mut candidate.functions[f].instrs;
+                    let LabelOrInst::Inst {
+                        args: Some(args), ..
+                    } = &mut instrs[i]
+                    else {
+                        unreachable!()
+                    };
+                    self.fresh_counter += 1;
+                    let fresh = format!("reduce.{}", self.fresh_counter);
+                    let ty = types.get(&args[j]).unwrap();
+                    args[j] = fresh.clone();
+                    instrs.insert(i, constant_of(&fresh, ty).unwrap());
+                    if self.try_candidate(prog, candidate) {
+                        progress = true;
+                        break;
+                    }
+                }
+                // after a replacement this is the same instruction again, shifted by the
+                // inserted constant, so its remaining operands get their turn
+                i += 1;
+            }
+        }
+        progress
+    }
+
+    /// tries every replacement `rewrite` offers for each instruction, the first interesting one
+    /// is kept
+    fn rewrite_each_inst(
+        &mut self,
+        prog: &mut Prog,
+        rewrite: impl Fn(&LabelOrInst) -> Vec<Vec<LabelOrInst>>,
+    ) -> bool {
+        let mut progress = false;
+        for f in 0..prog.functions.len() {
+            let mut i = 0;
+            while i < prog.functions[f].instrs.len() {
+                let mut step = 1;
+                for replacement in rewrite(&prog.functions[f].instrs[i]) {
+                    let replaced_len = replacement.len();
+                    let mut candidate = prog.clone();
+                    candidate.functions[f].instrs.splice(i..=i, replacement);
+                    if self.try_candidate(prog, candidate) {
+                        progress = true;
+                        step = replaced_len;
+                        break;
+                    }
+                }
+                i += step;
+            }
+        }
+        progress
+    }
+}
+
+/// a program `ProgCfgs` can be built from: `main` exists, no function is empty, labels are
+/// unique and every jump or call has a target of the right shape
+pub fn is_loadable(prog: &Prog) -> bool {
+    let arity: HashMap<&str, usize> = prog
+        .functions
+        .iter()
+        .map(|func| (func.name.as_str(), func.args.as_ref().map_or(0, Vec::len)))
+        .collect();
+    if !arity.contains_key("main") {
+        return false;
+    }
+    prog.functions.iter().all(|func| {
+        let mut labels = HashSet::new();
+        for inst in &func.instrs {
+            if let LabelOrInst::Label { label } = inst {
+                if !labels.insert(label.as_str()) {
+                    return false;
+                }
+            }
+        }
+        !func.instrs.is_empty()
+            && func.instrs.iter().all(|inst| match inst {
+                LabelOrInst::Inst {
+                    op,
+                    labels: targets,
+                    args,
+                    funcs,
+                    ..
+                } => {
+                    let targets = targets.as_deref().unwrap_or_default();
+                    let args = args.as_deref().unwrap_or_default();
+                    let targets_exist = targets
+                        .iter()
+                        .all(|target| labels.contains(target.as_str()));
+                    match op.as_str() {
+                        "jmp" => targets.len() == 1 && targets_exist,
+                        "br" => targets.len() == 2 && args.len() == 1 && targets_exist,
+                        "call" => funcs.as_ref().is_some_and(|funcs| {
+                            funcs.len() == 1 && arity.get(funcs[0].as_str()) == Some(&args.len())
+                        }),
+                        _ => targets_exist,
+                    }
+                }
+                LabelOrInst::Label { .. } => true,
+            })
+    })
+}
+
+fn inst_positions(func: &Function) -> Vec<usize> {
+    func.instrs
+        .iter()
+        .enumerate()
+        .filter(|(_, inst)| matches!(inst, LabelOrInst::Inst { .. }))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+fn retarget(func: &mut Function, from: &str, to: &str) {
+    for inst in &mut func.instrs {
+        if let LabelOrInst::Inst {
+            labels: Some(labels),
+            ..
+        } = inst
+        {
+            for label in labels.iter_mut().filter(|label| *label == from) {
+                *label = to.to_string();
+            }
+        }
+    }
+}
+
+/// `dest: ty = const` of the simplest value, `None` for types without literals
+fn constant_of(dest: &str, ty: &str) -> Option<LabelOrInst> {
+    let value = match ty {
+        "int" => ValueLit::Int(0),
+        "bool" => ValueLit::Bool(false),
+        _ => return None,
+    };
+    Some(LabelOrInst::Inst {
+        op: "const".to_string(),
+        dest: Some(dest.to_string()),
+        ty: Some(ty.to_string()),
+        args: None,
+        funcs: None,
+        labels: None,
+        value: Some(value),
+    })
+}
+
+/// type of every variable of `func`, and those only ever assigned constants
+fn var_types(func: &Function) -> (HashMap<String, String>, HashSet<String>) {
+    let mut types: HashMap<String, String> = func
+        .args
+        .iter()
+        .flatten()
+        .map(|arg| (arg.name.clone(), arg.ty.clone()))
+        .collect();
+    let mut const_vars = HashSet::new();
+    let mut non_const_vars: HashSet<String> = types.keys().cloned().collect();
+    for inst in &func.instrs {
+        if let LabelOrInst::Inst {
+            op,
+            dest: Some(dest),
+            ty: Some(ty),
+            ..
+        } = inst
+        {
+            types.insert(dest.clone(), ty.clone());
+            if op == "const" {
+                const_vars.insert(dest.clone());
+            } else {
+                non_const_vars.insert(dest.clone());
+            }
+        }
+    }
+    const_vars.retain(|var| !non_const_vars.contains(var));
+    (types, const_vars)
+}
@@ -0,0 +1,346 @@
+//! textbook ssa construction (Cytron et al.), as opposed to the dominance-free one in `ssa`
+//!
+//! 1. phis of each var are placed at the iterated domination frontier of its def sites
+//! 2. vars are renamed walking down the dominator tree, with a stack of versions per var
+//!
+//! Output uses the same set/get encoding as `ssa::cfg_into_ssa`, a phi at block B
+//!     x.2 = phi x.0 .A x.1 .C
+//! becomes a `get` at the top of B, and a `set` at the end of each predecessor
+//! .B:                         .A:                         .C:
+//!     x.2: int = get;             ...                         ...
+//!                                 set x.2 x.0;                set x.2 x.1;
+//!                                 jmp .B;                     jmp .B;
+//! so that `ssa::cfg_from_ssa` converts it back. If the var is never defined along an
+//! incoming edge, the `set` takes an `undef` placed at the entry block, which leaves the phi
+//! dest undefined along that edge, just like the var was before.
+use crate::analyzer::dom::DomTree;
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::optim::dce::global::LivenessAnalysis;
+use crate::optim::dflow::WorkListAlgo;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, Weak};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PhiPlacement {
+    /// a phi at every iterated domination frontier of the def sites
+    Minimal,
+    /// only for vars live across some block boundary
+    SemiPruned,
+    /// only where the var is live on entry of the block
+    Pruned,
+}
+
+pub fn cfg_into_ssa_cytron(mut cfg: Cfg, placement: PhiPlacement) -> Cfg {
+    cfg.prune_unreachable();
+    insert_entry_blk_if_targeted(&mut cfg);
+
+    let var_tys = var_tys(&cfg);
+    let dom_tree = DomTree::from_cfg(&cfg);
+    let phis = place_phis(&cfg, &dom_tree, placement, &var_tys);
+
+    let mut ctx = RenamingCtx {
+        names: var_tys.keys().cloned().collect(),
+        versions: HashMap::new(),
+        stacks: HashMap::new(),
+        phi_dests: HashMap::new(),
+        sets: HashMap::new(),
+        undefs: BTreeMap::new(),
+    };
+    for arg in cfg.func_ctx.args_name().unwrap_or_default() {
+        ctx.stacks.insert(arg.clone(), vec![arg]);
+    }
+    // phi dests are named upfront, so that predecessors can refer to them before being visited
+    for (node_ptr, vars) in &phis {
+        let dests = vars
+            .iter()
+            .map(|var| (var.clone(), ctx.fresh_name(var)))
+            .collect();
+        ctx.phi_dests.insert(*node_ptr, dests);
+    }
+    ctx.rename(&dom_tree, &Weak::upgrade(&cfg.root).unwrap());
+
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        let mut node_lock = node.lock().unwrap();
+        let instrs = &mut node_lock.blk.instrs;
+        if let Some(sets) = ctx.sets.remove(&node_ptr) {
+            let terminator = instrs.iter().position(
+                |inst| matches!(inst, LabelOrInst::Inst {op, ..} if op == "br" || op == "jmp"),
+            );
+            let idx = terminator.unwrap_or(instrs.len());
+            instrs.splice(idx..idx, sets.into_iter().map(set));
+        }
+        if let Some(dests) = ctx.phi_dests.get(&node_ptr) {
+            let first_non_label_idx = instrs
+                .iter()
+                .position(|inst| !matches!(inst, LabelOrInst::Label { .. }))
+                .unwrap_or(instrs.len());
+            let gets = dests
+                .iter()
+                .map(|(var, dest)| get(dest, var_tys.get(var).unwrap()));
+            instrs.splice(first_non_label_idx..first_non_label_idx, gets);
+        }
+    }
+    let root = cfg.root.upgrade().unwrap();
+    let mut root_lock = root.lock().unwrap();
+    let first_non_label_idx = root_lock
+        .blk
+        .instrs
+        .iter()
+        .position(|inst| !matches!(inst, LabelOrInst::Label { .. }))
+        .unwrap_or(root_lock.blk.instrs.len());
+    let undefs = ctx
+        .undefs
+        .iter()
+        .map(|(var, undef)| undef_inst(undef, var_tys.get(var).unwrap()));
+    root_lock
+        .blk
+        .instrs
+        .splice(first_non_label_idx..first_non_label_idx, undefs);
+    drop(root_lock);
+    cfg
+}
+
+/// phis must never be placed at the entry, an extra block is needed if it can be jumped to
+fn insert_entry_blk_if_targeted(cfg: &mut Cfg) {
+    let root = cfg.root.upgrade().unwrap();
+    let root_lock = root.lock().unwrap();
+    if root_lock.predecessors.is_empty() {
+        return;
+    }
+    let label = root_lock.label.clone().unwrap();
+    drop(root_lock);
+    let entry = BasicBlock {
+        label: None,
+        instrs: vec![LabelOrInst::Inst {
+            op: "jmp".to_string(),
+            dest: None,
+            ty: None,
+            args: None,
+            funcs: None,
+            labels: Some(vec![label]),
+            value: None,
+        }],
+    };
+    cfg.nodes.insert(
+        0,
+        Arc::new(Mutex::new(CfgNode {
+            label: None,
+            blk: entry,
+            successors: vec![],
+            predecessors: vec![],
+        })),
+    );
+    cfg.relink();
+}
+
+fn var_tys(cfg: &Cfg) -> HashMap<String, String> {
+    let mut var_tys: HashMap<String, String> = cfg
+        .func_ctx
+        .args
+        .iter()
+        .flatten()
+        .map(|arg| (arg.name.clone(), arg.ty.clone()))
+        .collect();
+    for node in &cfg.nodes {
+        for inst in &node.lock().unwrap().blk.instrs {
+            if let LabelOrInst::Inst {
+                dest: Some(dest),
+                ty: Some(ty),
+                ..
+            } = inst
+            {
+                var_tys.insert(dest.clone(), ty.clone());
+            }
+        }
+    }
+    var_tys
+}
+
+/// vars to be merged by a phi at each block
+fn place_phis(
+    cfg: &Cfg,
+    dom_tree: &DomTree,
+    placement: PhiPlacement,
+    var_tys: &HashMap<String, String>,
+) -> HashMap<NodePtr, Vec<String>> {
+    let root_ptr = Weak::as_ptr(&cfg.root);
+    let mut def_sites: HashMap<&String, Vec<NodePtr>> = HashMap::new();
+    for arg in cfg.func_ctx.args.iter().flatten() {
+        def_sites.entry(&arg.name).or_default().push(root_ptr);
+    }
+    let defs_per_node: Vec<(NodePtr, HashSet<String>)> = cfg
+        .nodes
+        .iter()
+        .map(|node| (Arc::as_ptr(node), node.lock().unwrap().blk.defs()))
+        .collect();
+    for (node_ptr, defs) in &defs_per_node {
+        for var in defs {
+            def_sites
+                .entry(var_tys.get_key_value(var).unwrap().0)
+                .or_default()
+                .push(*node_ptr);
+        }
+    }
+
+    let non_locals: HashSet<String> = if placement == PhiPlacement::SemiPruned {
+        cfg.nodes
+            .iter()
+            .flat_map(|node| node.lock().unwrap().blk.used_but_not_defed())
+            .collect()
+    } else {
+        HashSet::new()
+    };
+    let live_in = if placement == PhiPlacement::Pruned {
+        LivenessAnalysis.execute(cfg)
+    } else {
+        HashMap::new()
+    };
+    let should_place = |var: &String, node_ptr: NodePtr| match placement {
+        PhiPlacement::Minimal => true,
+        PhiPlacement::SemiPruned => non_locals.contains(var),
+        PhiPlacement::Pruned => live_in.get(&node_ptr).unwrap().contains(var),
+    };
+
+    let mut phis: HashMap<NodePtr, Vec<String>> = HashMap::new();
+    let mut sorted_vars: Vec<_> = def_sites.into_iter().collect();
+    sorted_vars.sort_by_key(|(var, _)| *var);
+    for (var, sites) in sorted_vars {
+        for frontier in dom_tree.iterated_dominance_frontier(sites) {
+            if should_place(var, frontier) {
+                phis.entry(frontier).or_default().push(var.clone());
+            }
+        }
+    }
+    phis
+}
+
+struct RenamingCtx {
+    /// every name ever used in the function, fresh names never clash with them
+    names: HashSet<String>,
+    versions: HashMap<String, usize>,
+    stacks: HashMap<String, Vec<String>>,
+    /// var -> renamed dest of the phi, per block
+    phi_dests: HashMap<NodePtr, BTreeMap<String, String>>,
+    /// phi dest -> incoming value, per predecessor
+    sets: HashMap<NodePtr, BTreeMap<String, String>>,
+    /// var -> undefined value at entry
+    undefs: BTreeMap<String, String>,
+}
+
+impl RenamingCtx {
+    fn fresh_name(&mut self, var: &str) -> String {
+        let version = self.versions.entry(var.to_string()).or_default();
+        loop {
+            let name = format!("{var}.{version}");
+            *version += 1;
+            if self.names.insert(name.clone()) {
+                break name;
+            }
+        }
+    }
+
+    fn rename(&mut self, dom_tree: &DomTree, cfg_node: &NodeRef) {
+        let node_ptr = Arc::as_ptr(cfg_node);
+        let mut pushed: Vec<String> = vec![];
+        if let Some(dests) = self.phi_dests.get(&node_ptr) {
+            for (var, dest) in dests {
+                self.stacks
+                    .entry(var.clone())
+                    .or_default()
+                    .push(dest.clone());
+                pushed.push(var.clone());
+            }
+        }
+
+        let successors: Vec<NodePtr> = {
+            let mut node_lock = cfg_node.lock().unwrap();
+            for inst in node_lock.blk.instrs.iter_mut() {
+                let LabelOrInst::Inst { dest, args, .. } = inst else {
+                    continue;
+                };
+                for arg in args.iter_mut().flatten() {
+                    if let Some(renamed) = self.stacks.get(arg.as_str()).and_then(|s| s.last()) {
+                        *arg = renamed.clone();
+                    }
+                }
+                if let Some(dest) = dest {
+                    let renamed = self.fresh_name(dest);
+                    self.stacks
+                        .entry(dest.clone())
+                        .or_default()
+                        .push(renamed.clone());
+                    pushed.push(dest.clone());
+                    *dest = renamed;
+                }
+            }
+            node_lock.successors.iter().map(Weak::as_ptr).collect()
+        };
+
+        for succ_ptr in successors {
+            let Some(dests) = self.phi_dests.get(&succ_ptr) else {
+                continue;
+            };
+            for (var, dest) in dests.clone() {
+                let value = match self.stacks.get(&var).and_then(|stack| stack.last()) {
+                    Some(value) => value.clone(),
+                    // var is never defined along this edge
+                    None => match self.undefs.get(&var) {
+                        Some(undef) => undef.clone(),
+                        None => {
+                            let undef = self.fresh_name(&var);
+                            self.undefs.insert(var, undef.clone());
+                            undef
+                        }
+                    },
+                };
+                self.sets.entry(node_ptr).or_default().insert(dest, value);
+            }
+        }
+
+        for child in dom_tree.children(node_ptr) {
+            self.rename(dom_tree, child);
+        }
+        for var in pushed {
+            self.stacks.get_mut(&var).unwrap().pop();
+        }
+    }
+}
+
+fn get(dest: &str, ty: &str) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "get".to_string(),
+        dest: Some(dest.to_string()),
+        ty: Some(ty.to_string()),
+        args: None,
+        funcs: None,
+        labels: None,
+        value: None,
+    }
+}
+
+fn undef_inst(dest: &str, ty: &str) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "undef".to_string(),
+        dest: Some(dest.to_string()),
+        ty: Some(ty.to_string()),
+        args: None,
+        funcs: None,
+        labels: None,
+        value: None,
+    }
+}
+
+fn set((dest, value): (String, String)) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "set".to_string(),
+        dest: None,
+        ty: None,
+        args: Some(vec![dest, value]),
+        funcs: None,
+        labels: None,
+        value: None,
+    }
+}
@@ -1 +1,4 @@
+pub mod cytron;
+pub mod out_of_ssa;
+pub mod phi;
 pub mod ssa;
@@ -0,0 +1,443 @@
+//! out-of-ssa translation with parallel copies
+//!
+//! Rewriting each `set` into an `id` in place is wrong when copies at a block end interfere
+//!     set a.1 b.1;        a.1: int = id b.1;      <- swap, b.1 reads the new a.1
+//!     set b.1 a.1;   ->   b.1: int = id a.1;
+//!     br c .h .end;       br c .h .end;           <- lost copy, .end expects the old a.1
+//!
+//! Instead, every phi dest `d` gets a shadow var, which isolates it from the rest of the program
+//!     - a run of `set d v` is a parallel copy `(d.in, ...) <- (v, ...)`
+//!     - a run of `d = get` is a parallel copy `(d, ...) <- (d.in, ...)`
+//! then copies whose vars do not interfere are coalesced, and the remaining ones are
+//! sequentialized per parallel copy, with a temporary to break each cycle.
+//!
+//! An `undef` may hold any value, it becomes a const of its type, so that copies of it are
+//! ordinary copies and never read an undefined var. The const is dropped when every copy of
+//! the `undef` got coalesced away, nothing reads it then. Pointers have no literal, their
+//! `undef`s are dropped along with the `set`s of them.
+use crate::bril::{LabelOrInst, ValueLit};
+use crate::cfg::prelude::*;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+/// (dest, src) pairs to be performed simultaneously
+type ParallelCopy = Vec<(String, String)>;
+
+enum Item {
+    Inst(LabelOrInst),
+    Copies(ParallelCopy),
+}
+
+pub fn cfg_from_ssa(cfg: Cfg) -> Cfg {
+    let mut var_tys: HashMap<String, String> = cfg
+        .func_ctx
+        .args
+        .iter()
+        .flatten()
+        .map(|arg| (arg.name.clone(), arg.ty.clone()))
+        .collect();
+    let (mut undefs, mut phi_dests) = (HashSet::new(), vec![]);
+    let mut names: HashSet<String> = var_tys.keys().cloned().collect();
+    for node in &cfg.nodes {
+        for inst in &node.lock().unwrap().blk.instrs {
+            let LabelOrInst::Inst {
+                op, dest, ty, args, ..
+            } = inst
+            else {
+                continue;
+            };
+            names.extend(dest.iter().chain(args.iter().flatten()).cloned());
+            if let (Some(dest), Some(ty)) = (dest, ty) {
+                var_tys.insert(dest.clone(), ty.clone());
+                if op == "undef" && zero_of(ty).is_none() {
+                    undefs.insert(dest.clone());
+                } else if op == "get" {
+                    phi_dests.push(dest.clone());
+                }
+            }
+        }
+    }
+    let mut fresh_name = |hint: &str| {
+        let mut name = hint.to_string();
+        let mut cnt = 0;
+        while !names.insert(name.clone()) {
+            name = format!("{hint}.{cnt}");
+            cnt += 1;
+        }
+        name
+    };
+
+    // phi dest -> shadow var
+    let mut shadows: HashMap<String, String> = HashMap::new();
+    for var in phi_dests {
+        let shadow = fresh_name(&format!("{var}.in"));
+        var_tys.insert(shadow.clone(), var_tys.get(&var).unwrap().clone());
+        shadows.insert(var, shadow);
+    }
+
+    let mut items: HashMap<NodePtr, Vec<Item>> = HashMap::new();
+    for node in &cfg.nodes {
+        let node_lock = node.lock().unwrap();
+        let mut blk_items = vec![];
+        let mut last_op = None;
+        for inst in &node_lock.blk.instrs {
+            let copy = match inst {
+                LabelOrInst::Inst {
+                    op,
+                    dest: Some(dest),
+                    ..
+                } if op == "get" => Some((dest.clone(), shadows.get(dest).unwrap().clone())),
+                LabelOrInst::Inst {
+                    op,
+                    args: Some(args),
+                    ..
+                } if op == "set" => {
+                    if undefs.contains(&args[1]) {
+                        // the shadow stays undefined, which also keeps the surrounding
+                        // sets in one parallel copy
+                        continue;
+                    }
+                    Some((shadows.get(&args[0]).unwrap().clone(), args[1].clone()))
+                }
+                LabelOrInst::Inst {
+                    op,
+                    dest: Some(dest),
+                    ty: Some(ty),
+                    ..
+                } if op == "undef" && zero_of(ty).is_none() => continue,
+                _ => None,
+            };
+            let op = match inst {
+                LabelOrInst::Inst { op, .. } => Some(op.clone()),
+                _ => None,
+            };
+            match copy {
+                Some(copy) => match blk_items.last_mut() {
+                    Some(Item::Copies(copies)) if last_op == op => copies.push(copy),
+                    _ => blk_items.push(Item::Copies(vec![copy])),
+                },
+                None => blk_items.push(Item::Inst(inst.clone())),
+            }
+            last_op = op;
+        }
+        items.insert(Arc::as_ptr(node), blk_items);
+    }
+
+    let interference = InterferenceGraph::build(&cfg, &items);
+    let args: HashSet<String> = cfg
+        .func_ctx
+        .args_name()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let classes = coalesce(
+        &cfg,
+        &items,
+        interference,
+        &args,
+        &shadows.values().collect(),
+    );
+
+    // an `undef` only needs its const if some copy left after coalescing reads it
+    let copied: HashSet<String> = items
+        .values()
+        .flatten()
+        .filter_map(|item| match item {
+            Item::Copies(copies) => Some(copies),
+            _ => None,
+        })
+        .flatten()
+        .map(|(dest, src)| (classes.find(dest), classes.find(src)))
+        .filter_map(|(dest, src)| (dest != src).then_some(src))
+        .collect();
+
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        let mut instrs = vec![];
+        for item in items.remove(&node_ptr).unwrap() {
+            match item {
+                Item::Inst(mut inst) => {
+                    if let LabelOrInst::Inst { dest, args, .. } = &mut inst {
+                        for var in dest.iter_mut().chain(args.iter_mut().flatten()) {
+                            *var = classes.find(var);
+                        }
+                    }
+                    match &inst {
+                        LabelOrInst::Inst {
+                            op,
+                            dest: Some(dest),
+                            ty: Some(ty),
+                            ..
+                        } if op == "undef" => {
+                            if copied.contains(dest) {
+                                instrs.push(const_inst(dest, ty, zero_of(ty).unwrap()));
+                            }
+                        }
+                        _ => instrs.push(inst),
+                    }
+                }
+                Item::Copies(copies) => {
+                    let copies = copies
+                        .into_iter()
+                        .map(|(dest, src)| (classes.find(&dest), classes.find(&src)))
+                        .collect();
+                    for (dest, src) in sequentialize(copies, &mut fresh_name) {
+                        // temporaries take the type of the var they save
+                        let ty = var_tys.get(&dest).or_else(|| var_tys.get(&src)).unwrap();
+                        instrs.push(id(&dest, ty, &src));
+                    }
+                }
+            }
+        }
+        node.lock().unwrap().blk.instrs = instrs;
+    }
+    cfg
+}
+
+/// order the parallel copy so that no src is overwritten before being read
+fn sequentialize<F>(copies: ParallelCopy, fresh_name: &mut F) -> ParallelCopy
+where
+    F: FnMut(&str) -> String,
+{
+    let mut pending: ParallelCopy = vec![];
+    for (dest, src) in copies {
+        if dest != src && !pending.iter().any(|(d, _)| *d == dest) {
+            pending.push((dest, src));
+        }
+    }
+    let mut sequential = vec![];
+    while !pending.is_empty() {
+        let ready = pending
+            .iter()
+            .position(|(dest, _)| !pending.iter().any(|(_, src)| src == dest));
+        if let Some(ready) = ready {
+            sequential.push(pending.remove(ready));
+        } else {
+            // every dest is still to be read, the remaining copies form cycles
+            let dest = pending[0].0.clone();
+            let tmp = fresh_name(&format!("{dest}.tmp"));
+            sequential.push((tmp.clone(), dest.clone()));
+            for (_, src) in pending.iter_mut().filter(|(_, src)| *src == dest) {
+                *src = tmp.clone();
+            }
+        }
+    }
+    sequential
+}
+
+#[derive(Default)]
+struct InterferenceGraph(HashMap<String, HashSet<String>>);
+
+impl InterferenceGraph {
+    fn build(cfg: &Cfg, items: &HashMap<NodePtr, Vec<Item>>) -> Self {
+        let live_in = live_in_per_node(cfg, items);
+        let mut graph = Self::default();
+        for node in &cfg.nodes {
+            let mut live: HashSet<String> = node
+                .lock()
+                .unwrap()
+                .successors
+                .iter()
+                .flat_map(|succ| live_in.get(&Weak::as_ptr(succ)).unwrap().clone())
+                .collect();
+            for item in items.get(&Arc::as_ptr(node)).unwrap().iter().rev() {
+                match item {
+                    Item::Inst(LabelOrInst::Inst { dest, args, .. }) => {
+                        if let Some(dest) = dest {
+                            for var in &live {
+                                graph.add_edge(dest, var);
+                            }
+                            live.remove(dest);
+                        }
+                        live.extend(args.iter().flatten().cloned());
+                    }
+                    Item::Copies(copies) => {
+                        for (i, (dest, src)) in copies.iter().enumerate() {
+                            // dest holds the same value as src right after the copy
+                            for var in live.iter().filter(|var| *var != src) {
+                                graph.add_edge(dest, var);
+                            }
+                            for (other, _) in &copies[i + 1..] {
+                                graph.add_edge(dest, other);
+                            }
+                        }
+                        for (dest, _) in copies {
+                            live.remove(dest);
+                        }
+                        live.extend(copies.iter().map(|(_, src)| src.clone()));
+                    }
+                    Item::Inst(LabelOrInst::Label { .. }) => {}
+                }
+            }
+        }
+        graph
+    }
+
+    fn add_edge(&mut self, a: &str, b: &str) {
+        if a != b {
+            self.0
+                .entry(a.to_string())
+                .or_default()
+                .insert(b.to_string());
+            self.0
+                .entry(b.to_string())
+                .or_default()
+                .insert(a.to_string());
+        }
+    }
+}
+
+fn live_in_per_node(
+    cfg: &Cfg,
+    items: &HashMap<NodePtr, Vec<Item>>,
+) -> HashMap<NodePtr, HashSet<String>> {
+    // vars read before being written, and vars written, per block
+    let use_def: HashMap<NodePtr, (HashSet<String>, HashSet<String>)> = items
+        .iter()
+        .map(|(node_ptr, blk_items)| {
+            let (mut used, mut defed) = (HashSet::new(), HashSet::new());
+            for item in blk_items.iter().rev() {
+                let (dests, srcs): (Vec<&String>, Vec<&String>) = match item {
+                    Item::Inst(LabelOrInst::Inst { dest, args, .. }) => {
+                        (dest.iter().collect(), args.iter().flatten().collect())
+                    }
+                    Item::Copies(copies) => copies.iter().map(|(d, s)| (d, s)).unzip(),
+                    Item::Inst(LabelOrInst::Label { .. }) => continue,
+                };
+                for dest in dests {
+                    used.remove(dest);
+                    defed.insert(dest.clone());
+                }
+                used.extend(srcs.into_iter().cloned());
+            }
+            (*node_ptr, (used, defed))
+        })
+        .collect();
+
+    let mut live_in: HashMap<NodePtr, HashSet<String>> = use_def
+        .iter()
+        .map(|(node_ptr, (used, _))| (*node_ptr, used.clone()))
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in cfg.nodes.iter().rev() {
+            let node_ptr = Arc::as_ptr(node);
+            let (used, defed) = use_def.get(&node_ptr).unwrap();
+            let mut new_live_in: HashSet<String> = node
+                .lock()
+                .unwrap()
+                .successors
+                .iter()
+                .flat_map(|succ| live_in.get(&Weak::as_ptr(succ)).unwrap().clone())
+                .filter(|var| !defed.contains(var))
+                .collect();
+            new_live_in.extend(used.iter().cloned());
+            if new_live_in != *live_in.get(&node_ptr).unwrap() {
+                live_in.insert(node_ptr, new_live_in);
+                changed = true;
+            }
+        }
+    }
+    live_in
+}
+
+/// congruence classes of vars, every var of a class is renamed to its representative
+#[derive(Default)]
+struct Classes {
+    repr: HashMap<String, String>,
+    members: HashMap<String, Vec<String>>,
+}
+
+impl Classes {
+    fn find(&self, var: &str) -> String {
+        self.repr
+            .get(var)
+            .cloned()
+            .unwrap_or_else(|| var.to_string())
+    }
+}
+
+fn coalesce(
+    cfg: &Cfg,
+    items: &HashMap<NodePtr, Vec<Item>>,
+    mut interference: InterferenceGraph,
+    args: &HashSet<String>,
+    shadows: &HashSet<&String>,
+) -> Classes {
+    let mut classes = Classes::default();
+    let copies = cfg
+        .nodes
+        .iter()
+        .flat_map(|node| items.get(&Arc::as_ptr(node)).unwrap())
+        .filter_map(|item| match item {
+            Item::Copies(copies) => Some(copies),
+            _ => None,
+        });
+    for (dest, src) in copies.flatten() {
+        let (a, b) = (classes.find(dest), classes.find(src));
+        if a == b || interference.0.get(&a).is_some_and(|adj| adj.contains(&b)) {
+            continue;
+        }
+        // params keep their name, two of them are never merged
+        let (repr, merged) = match (args.contains(&a), args.contains(&b)) {
+            (true, true) => continue,
+            (false, true) => (b, a),
+            (false, false) if shadows.contains(&a) && !shadows.contains(&b) => (b, a),
+            _ => (a, b),
+        };
+        let mut merged_members = classes
+            .members
+            .remove(&merged)
+            .unwrap_or_else(|| vec![merged.clone()]);
+        for member in &merged_members {
+            classes.repr.insert(member.clone(), repr.clone());
+        }
+        classes
+            .members
+            .entry(repr.clone())
+            .or_insert_with(|| vec![repr.clone()])
+            .append(&mut merged_members);
+        // the merged class interferes with whatever either class interferes with
+        let adj = interference.0.remove(&merged).unwrap_or_default();
+        for var in adj {
+            interference.0.get_mut(&var).unwrap().remove(&merged);
+            interference.add_edge(&repr, &var);
+        }
+    }
+    classes
+}
+
+/// stands in for an undefined value of type `ty`
+fn zero_of(ty: &str) -> Option<ValueLit> {
+    match ty {
+        "int" => Some(ValueLit::Int(0)),
+        "bool" => Some(ValueLit::Bool(false)),
+        _ => None,
+    }
+}
+
+fn const_inst(dest: &str, ty: &str, value: ValueLit) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "const".to_string(),
+        dest: Some(dest.to_string()),
+        ty: Some(ty.to_string()),
+        args: None,
+        funcs: None,
+        labels: None,
+        value: Some(value),
+    }
+}
+
+fn id(dest: &str, ty: &str, arg: &str) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: "id".to_string(),
+        dest: Some(dest.to_string()),
+        ty: Some(ty.to_string()),
+        args: Some(vec![arg.to_string()]),
+        funcs: None,
+        labels: None,
+        value: None,
+    }
+}
@@ -0,0 +1,468 @@
+//! conversion between the two encodings of ssa in bril
+//!
+//! `phi` form, as produced by `to_ssa.py` in bril examples, lists the incoming value of
+//! every predecessor at the merge point
+//! .body:
+//!     x.1: int = phi x.0 x.2 .entry .body;
+//! while `set`/`get` form writes a shadow of the dest at the end of the predecessors
+//! .entry:
+//!     set x.1 x.0;
+//!     jmp .body;
+//! .body:
+//!     x.1: int = get;
+//!     ...
+//!     set x.1 x.2;
+//!     jmp .body;
+//!
+//! Passes of this crate work on `set`/`get` form, `phi` form is only converted at the boundary.
+//! Vars never defined along an edge are spelled `__undefined` in `phi` form and bound by
+//! `undef` at the entry in `set`/`get` form.
+use crate::bril::LabelOrInst;
+use crate::cfg::prelude::*;
+use crate::optim::dflow::WorkListAlgo;
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+/// name of the phi arg along edges where the var is never defined
+pub const UNDEFINED: &str = "__undefined";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SsaForm {
+    SetGet,
+    Phi,
+}
+
+/// encoding used by the cfg, `None` if there is neither `phi` nor `set`/`get`
+pub fn ssa_form_of(cfg: &Cfg) -> Option<SsaForm> {
+    let mut form = None;
+    for node in &cfg.nodes {
+        for inst in &node.lock().unwrap().blk.instrs {
+            match inst {
+                LabelOrInst::Inst { op, .. } if op == "phi" => return Some(SsaForm::Phi),
+                LabelOrInst::Inst { op, .. } if op == "set" || op == "get" => {
+                    form = Some(SsaForm::SetGet)
+                }
+                _ => {}
+            }
+        }
+    }
+    form
+}
+
+/// convert cfg in `set`/`get` form into `form`
+pub fn cfg_into_form(cfg: Cfg, form: SsaForm) -> Cfg {
+    match form {
+        SsaForm::SetGet => cfg,
+        SsaForm::Phi => set_get_into_phi(cfg),
+    }
+}
+
+/// every phi becomes a `get` of its dest, every incoming value a `set` at the end of the
+/// predecessor it is labeled with
+pub fn phi_into_set_get(cfg: Cfg) -> Cfg {
+    let mut names: HashSet<String> = cfg
+        .func_ctx
+        .args_name()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    for node in &cfg.nodes {
+        names.extend(node.lock().unwrap().blk.defs());
+    }
+    let defined = names.clone();
+    let labels: HashMap<String, NodePtr> = cfg
+        .nodes
+        .iter()
+        .filter_map(|node| {
+            let label = node.lock().unwrap().label.clone()?;
+            Some((label, Arc::as_ptr(node)))
+        })
+        .collect();
+
+    let mut sets: HashMap<NodePtr, Vec<LabelOrInst>> = HashMap::new();
+    let mut undefs = vec![];
+    for node in &cfg.nodes {
+        let mut node_lock = node.lock().unwrap();
+        let preds: HashSet<NodePtr> = node_lock.predecessors.iter().map(Weak::as_ptr).collect();
+        for inst in node_lock.blk.instrs.iter_mut() {
+            let LabelOrInst::Inst {
+                op,
+                dest: Some(dest),
+                ty: Some(ty),
+                args,
+                labels: phi_labels,
+                ..
+            } = inst
+            else {
+                continue;
+            };
+            if op != "phi" {
+                continue;
+            }
+            let (dest, ty) = (dest.clone(), ty.clone());
+            let mut undef = None;
+            let incoming = args.iter().flatten().zip(phi_labels.iter().flatten());
+            for (arg, label) in incoming {
+                // labels of blocks that are not predecessors can never be taken
+                let Some(&pred_ptr) = labels.get(label).filter(|ptr| preds.contains(ptr)) else {
+                    continue;
+                };
+                let value = if defined.contains(arg) {
+                    arg.clone()
+                } else {
+                    undef
+                        .get_or_insert_with(|| {
+                            let undef = fresh_name(&mut names, &format!("{dest}.undef"));
+                            undefs.push(inst_of("undef", Some(&undef), Some(&ty), vec![]));
+                            undef
+                        })
+                        .clone()
+                };
+                sets.entry(pred_ptr).or_default().push(inst_of(
+                    "set",
+                    None,
+                    None,
+                    vec![dest.clone(), value],
+                ));
+            }
+            *inst = inst_of("get", Some(&dest), Some(&ty), vec![]);
+        }
+    }
+
+    for node in &cfg.nodes {
+        if let Some(sets) = sets.remove(&Arc::as_ptr(node)) {
+            insert_before_terminator(&mut node.lock().unwrap().blk.instrs, sets);
+        }
+    }
+    let root = cfg.root.upgrade().unwrap();
+    let instrs = &mut root.lock().unwrap().blk.instrs;
+    let first_non_label_idx = first_non_label_idx(instrs);
+    instrs.splice(first_non_label_idx..first_non_label_idx, undefs);
+    cfg
+}
+
+/// every `get` becomes a phi over the values its shadow is set to at the end of each
+/// predecessor, `get`s are expected to come before any `set` of their shadow in the block
+///
+/// A shadow may be set in a block further up than the predecessors, when different values
+/// reach the end of a predecessor, an extra phi merging them is placed in that predecessor.
+/// Predecessors without label are given one, since phis name their incoming edges by label.
+pub fn set_get_into_phi(cfg: Cfg) -> Cfg {
+    let mut tys = HashMap::new();
+    let mut undefs = HashSet::new();
+    let mut names: HashSet<String> = cfg
+        .func_ctx
+        .args_name()
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let mut gets: HashMap<NodePtr, Vec<String>> = HashMap::new();
+    for node in &cfg.nodes {
+        let node_lock = node.lock().unwrap();
+        names.extend(node_lock.blk.defs());
+        for inst in &node_lock.blk.instrs {
+            if let LabelOrInst::Inst {
+                op,
+                dest: Some(dest),
+                ty: Some(ty),
+                ..
+            } = inst
+            {
+                match op.as_str() {
+                    "get" => {
+                        tys.insert(dest.clone(), ty.clone());
+                        gets.entry(Arc::as_ptr(node))
+                            .or_default()
+                            .push(dest.clone());
+                    }
+                    "undef" => {
+                        undefs.insert(dest.clone());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let reaching = ReachingSetAnalysis {
+        root_ptr: Weak::as_ptr(&cfg.root),
+        shadows: tys.keys().cloned().collect(),
+        undefs: &undefs,
+    }
+    .execute(&cfg);
+    let mut ctx = PhiCtx {
+        reaching,
+        preds: cfg
+            .nodes
+            .iter()
+            .map(|node| {
+                let preds = node.lock().unwrap().predecessors.clone();
+                // the fallthrough edge out of a returning block is never taken
+                let preds = preds
+                    .iter()
+                    .filter(|pred| !returns(&pred.upgrade().unwrap().lock().unwrap().blk))
+                    .map(Weak::as_ptr)
+                    .collect();
+                (Arc::as_ptr(node), preds)
+            })
+            .collect(),
+        names,
+        merged: HashMap::new(),
+        phis: HashMap::new(),
+    };
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        for dest in gets.remove(&node_ptr).unwrap_or_default() {
+            let incoming = ctx.incoming_of(&dest, node_ptr);
+            ctx.phis
+                .entry(node_ptr)
+                .or_default()
+                .push(Phi { dest, incoming });
+        }
+    }
+
+    // merging phis are typed after the shadow they stand for
+    let mut phi_tys = tys.clone();
+    for ((shadow, _), dest) in &ctx.merged {
+        phi_tys.insert(dest.clone(), tys.get(shadow).unwrap().clone());
+    }
+
+    // phis of a block are not guaranteed to be evaluated in parallel, an incoming value defined
+    // by another phi of the same block could be clobbered before being read, it is copied in
+    // the predecessor instead. A phi reading its own dest needs no copy, which matters when the
+    // dest is undefined along some path, as copying it would read an undefined var.
+    let mut copies: HashMap<NodePtr, Vec<LabelOrInst>> = HashMap::new();
+    for phis in ctx.phis.values_mut() {
+        let dests: HashSet<String> = phis.iter().map(|phi| phi.dest.clone()).collect();
+        for phi in phis.iter_mut() {
+            for (value, pred_ptr) in phi.incoming.iter_mut() {
+                if *value != phi.dest && dests.contains(value) {
+                    let ty = phi_tys.get(value).unwrap();
+                    let copy = fresh_name(&mut ctx.names, &format!("{value}.copy"));
+                    copies.entry(*pred_ptr).or_default().push(inst_of(
+                        "id",
+                        Some(&copy),
+                        Some(ty),
+                        vec![value.clone()],
+                    ));
+                    *value = copy;
+                }
+            }
+        }
+    }
+
+    let mut labels = HashMap::new();
+    for (i, node) in cfg.nodes.iter().enumerate() {
+        let label = node.lock().unwrap().label.clone();
+        let label = label.unwrap_or_else(|| {
+            let label = cfg.fresh_label(&format!("{}.blk.{i}", cfg.func_ctx.name));
+            let mut node_lock = node.lock().unwrap();
+            node_lock.blk.set_label(label.clone());
+            node_lock.label = Some(label.clone());
+            label
+        });
+        labels.insert(Arc::as_ptr(node), label);
+    }
+
+    for node in &cfg.nodes {
+        let node_ptr = Arc::as_ptr(node);
+        let mut node_lock = node.lock().unwrap();
+        let instrs = &mut node_lock.blk.instrs;
+        instrs.retain(
+            |inst| !matches!(inst, LabelOrInst::Inst { op, .. } if op == "get" || op == "set"),
+        );
+        if let Some(copies) = copies.remove(&node_ptr) {
+            insert_before_terminator(instrs, copies);
+        }
+        let Some(phis) = ctx.phis.remove(&node_ptr) else {
+            continue;
+        };
+        let phis = phis.into_iter().map(|phi| {
+            let ty = phi_tys.get(&phi.dest).unwrap();
+            let (args, phi_labels): (Vec<_>, Vec<_>) = phi
+                .incoming
+                .into_iter()
+                .map(|(value, pred_ptr)| (value, labels.get(&pred_ptr).unwrap().clone()))
+                .unzip();
+            LabelOrInst::Inst {
+                op: "phi".to_string(),
+                dest: Some(phi.dest),
+                ty: Some(ty.clone()),
+                args: Some(args),
+                funcs: None,
+                labels: Some(phi_labels),
+                value: None,
+            }
+        });
+        let first_non_label_idx = first_non_label_idx(instrs);
+        instrs.splice(first_non_label_idx..first_non_label_idx, phis);
+    }
+
+    // undefs were only there to feed `set`
+    let used: HashSet<String> = cfg
+        .nodes
+        .iter()
+        .flat_map(|node| node.lock().unwrap().blk.used_but_not_defed())
+        .collect();
+    for node in &cfg.nodes {
+        node.lock().unwrap().blk.instrs.retain(|inst| {
+            !matches!(inst, LabelOrInst::Inst { op, dest: Some(dest), .. } if op == "undef" && !used.contains(dest))
+        });
+    }
+    cfg
+}
+
+struct Phi {
+    dest: String,
+    incoming: Vec<(String, NodePtr)>,
+}
+
+struct PhiCtx {
+    reaching: HashMap<NodePtr, HashMap<String, BTreeSet<String>>>,
+    preds: HashMap<NodePtr, Vec<NodePtr>>,
+    names: HashSet<String>,
+    // (shadow, block) -> dest of the phi merging the values reaching the block
+    merged: HashMap<(String, NodePtr), String>,
+    phis: HashMap<NodePtr, Vec<Phi>>,
+}
+
+impl PhiCtx {
+    fn incoming_of(&mut self, shadow: &str, node_ptr: NodePtr) -> Vec<(String, NodePtr)> {
+        self.preds
+            .get(&node_ptr)
+            .unwrap()
+            .clone()
+            .into_iter()
+            .map(|pred_ptr| (self.value_at_end_of(shadow, pred_ptr), pred_ptr))
+            .collect()
+    }
+
+    /// var holding the shadow at the end of the block
+    fn value_at_end_of(&mut self, shadow: &str, node_ptr: NodePtr) -> String {
+        let mut values = self
+            .reaching
+            .get(&node_ptr)
+            .and_then(|reaching| reaching.get(shadow))
+            .cloned()
+            .unwrap_or_default();
+        // a phi arg that is undefined on some path is never used along it
+        values.remove(UNDEFINED);
+        match values.len() {
+            0 => return UNDEFINED.to_string(),
+            1 => return values.pop_first().unwrap(),
+            _ => {}
+        }
+        let key = (shadow.to_string(), node_ptr);
+        if let Some(merged) = self.merged.get(&key) {
+            return merged.clone();
+        }
+        // registered before visiting predecessors, so that loops refer back to it
+        let dest = fresh_name(&mut self.names, shadow);
+        self.merged.insert(key, dest.clone());
+        let incoming = self.incoming_of(shadow, node_ptr);
+        self.phis.entry(node_ptr).or_default().push(Phi {
+            dest: dest.clone(),
+            incoming,
+        });
+        dest
+    }
+}
+
+/// vars each shadow may be set to on paths from the entry to the end of each block,
+/// `UNDEFINED` if the shadow may not be set at all
+struct ReachingSetAnalysis<'a> {
+    root_ptr: NodePtr,
+    shadows: Vec<String>,
+    undefs: &'a HashSet<String>,
+}
+
+impl WorkListAlgo for ReachingSetAnalysis<'_> {
+    const FORWARD_PASS: bool = true;
+    type InFlowType = HashMap<String, BTreeSet<String>>;
+    type OutFlowType = HashMap<String, BTreeSet<String>>;
+
+    fn transfer(&mut self, node: &NodeRef, in_flow: Option<Self::InFlowType>) -> Self::OutFlowType {
+        let mut out_flow = in_flow.unwrap_or_default();
+        if Arc::as_ptr(node) == self.root_ptr {
+            for shadow in &self.shadows {
+                out_flow
+                    .entry(shadow.clone())
+                    .or_default()
+                    .insert(UNDEFINED.to_string());
+            }
+        }
+        let node_lock = node.lock().unwrap();
+        if returns(&node_lock.blk) {
+            return HashMap::new();
+        }
+        for inst in &node_lock.blk.instrs {
+            if let LabelOrInst::Inst {
+                op,
+                args: Some(args),
+                ..
+            } = inst
+            {
+                if op == "set" {
+                    let value = if self.undefs.contains(&args[1]) {
+                        UNDEFINED.to_string()
+                    } else {
+                        args[1].clone()
+                    };
+                    out_flow.insert(args[0].clone(), BTreeSet::from([value]));
+                }
+            }
+        }
+        out_flow
+    }
+
+    fn merge(out_flows: Vec<Self::OutFlowType>) -> Self::InFlowType {
+        let mut in_flow: Self::InFlowType = HashMap::new();
+        for out_flow in out_flows {
+            for (shadow, values) in out_flow {
+                in_flow.entry(shadow).or_default().extend(values);
+            }
+        }
+        in_flow
+    }
+}
+
+fn fresh_name(names: &mut HashSet<String>, hint: &str) -> String {
+    let name = (0..)
+        .map(|i| format!("{hint}.{i}"))
+        .find(|name| !names.contains(name))
+        .unwrap();
+    names.insert(name.clone());
+    name
+}
+
+fn returns(blk: &BasicBlock) -> bool {
+    matches!(blk.instrs.last(), Some(LabelOrInst::Inst { op, .. }) if op == "ret")
+}
+
+fn first_non_label_idx(instrs: &[LabelOrInst]) -> usize {
+    instrs
+        .iter()
+        .position(|inst| !matches!(inst, LabelOrInst::Label { .. }))
+        .unwrap_or(instrs.len())
+}
+
+fn insert_before_terminator(instrs: &mut Vec<LabelOrInst>, new_instrs: Vec<LabelOrInst>) {
+    let terminator = instrs
+        .iter()
+        .position(|inst| matches!(inst, LabelOrInst::Inst {op, ..} if op == "br" || op == "jmp"));
+    let idx = terminator.unwrap_or(instrs.len());
+    instrs.splice(idx..idx, new_instrs);
+}
+
+fn inst_of(op: &str, dest: Option<&str>, ty: Option<&str>, args: Vec<String>) -> LabelOrInst {
+    LabelOrInst::Inst {
+        op: op.to_string(),
+        dest: dest.map(str::to_string),
+        ty: ty.map(str::to_string),
+        args: (!args.is_empty()).then_some(args),
+        funcs: None,
+        labels: None,
+        value: None,
+    }
+}
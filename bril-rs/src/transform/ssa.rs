@@ -5,6 +5,8 @@ use std::collections::{BTreeSet, HashMap, HashSet};
 use std::default::Default;
 use std::sync::{Arc, Mutex, Weak};
 
+pub use super::out_of_ssa::cfg_from_ssa;
+
 pub fn cfg_into_ssa(mut cfg: Cfg) -> Cfg {
     if let Some(dummy_entry_blk) = require_dummy_entry_blk(&cfg) {
         let old_root_cfg_node = Weak::clone(&cfg.root);
@@ -30,57 +32,12 @@ pub fn cfg_into_ssa(mut cfg: Cfg) -> Cfg {
     cfg
 }
 
-pub fn cfg_from_ssa(cfg: Cfg) -> Cfg {
-    let mut to_get_tys = HashMap::new();
-    for node in &cfg.nodes {
-        for inst in &node.lock().unwrap().blk.instrs {
-            if let LabelOrInst::Inst {
-                op,
-                dest: Some(dest),
-                ty: Some(ty),
-                ..
-            } = inst
-            {
-                if op == "get" {
-                    assert!(to_get_tys.insert(dest.clone(), ty.clone()).is_none());
-                }
-            }
-        }
-    }
-
-    for node in &cfg.nodes {
-        let mut node_lock = node.lock().unwrap();
-        let instrs = &mut node_lock.blk.instrs;
-        // delete all get instr
-        instrs.retain(|inst| !matches!(inst, LabelOrInst::Inst {op, ..} if op == "get"));
-        instrs.iter_mut().for_each(|inst| {
-            if let LabelOrInst::Inst {
-                op,
-                args: Some(args),
-                dest,
-                ty,
-                ..
-            } = inst
-            {
-                if op == "set" {
-                    *op = "id".to_string();
-                    let (to_set, canonical_repr) = (args[0].clone(), args[1].clone());
-                    *ty = Some(to_get_tys.get(&to_set).unwrap().clone());
-                    *dest = Some(to_set);
-                    *args = vec![canonical_repr];
-                }
-            }
-        })
-    }
-    cfg
-}
-
 fn require_dummy_entry_blk(cfg: &Cfg) -> Option<BasicBlock> {
     let root_node = cfg.root.upgrade().unwrap();
     let root_node_lock = root_node.lock().unwrap();
-    if root_node_lock.label.is_some() && cfg.func_ctx.args.is_some() {
+    if let (Some(_), Some(args)) = (&root_node_lock.label, &cfg.func_ctx.args) {
         let mut instrs = vec![];
-        for arg in cfg.func_ctx.args.as_ref().unwrap() {
+        for arg in args {
             instrs.push(
                 serde_json::from_str(&format!(
                     r#"{{
@@ -306,7 +263,8 @@ impl<'a> SSATransContext<'a> {
                 .instrs
                 .iter()
                 .position(|inst| !matches!(inst, LabelOrInst::Label { .. }))
-                .unwrap_or(1); // otherwise, basic block only contains a single label
+                // otherwise, basic block is empty or only contains a single label
+                .unwrap_or(node_lock.blk.instrs.len());
 
             node_lock
                 .blk
@@ -0,0 +1,81 @@
+//! shared by the integration tests, programs are kept in bril text under `tests/progs`,
+//! with one `# ARGS: ...` line per arg set as in turnt
+#![allow(dead_code)]
+use bril_rs::bril::Prog;
+use bril_rs::cfg::ProgCfgs;
+use bril_rs::fuzz::ProgGenerator;
+use bril_rs::interp::diff::{Case, CaseReport, DiffTester};
+use bril_rs::pipeline::Pipeline;
+
+use std::path::PathBuf;
+
+pub fn prog_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/progs")
+        .join(name)
+        .with_extension("bril")
+}
+
+pub fn load(name: &str) -> Prog {
+    let src = std::fs::read_to_string(prog_path(name)).unwrap();
+    Prog::from_text(&src).unwrap_or_else(|e| panic!("{name}.bril: {e}"))
+}
+
+pub fn case(name: &str) -> Case {
+    let src = std::fs::read_to_string(prog_path(name)).unwrap();
+    let mut arg_sets: Vec<Vec<String>> = src
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("# ARGS:"))
+        .map(|args| args.split_whitespace().map(str::to_string).collect())
+        .collect();
+    if arg_sets.is_empty() {
+        arg_sets.push(vec![]);
+    }
+    Case {
+        name: name.to_string(),
+        prog: load(name),
+        arg_sets,
+    }
+}
+
+/// same as `difftest --fuzz n`
+pub fn fuzz_cases(n: u64) -> Vec<Case> {
+    (0..n)
+        .map(|seed| {
+            let mut generator = ProgGenerator::new(seed);
+            Case {
+                name: format!("fuzz-{seed}"),
+                prog: generator.generate(),
+                arg_sets: generator.arg_sets(3),
+            }
+        })
+        .collect()
+}
+
+pub fn optimize(prog: &Prog, pipeline: &str) -> Prog {
+    let pipeline: Pipeline = pipeline.parse().unwrap();
+    pipeline
+        .run(ProgCfgs::from_bril_prog(prog))
+        .into_bril_prog()
+}
+
+/// runs `case` before and after `pipeline`, panics on the first disagreement
+pub fn assert_agrees(pipeline: &str, case: &Case) -> CaseReport {
+    let parsed: Pipeline = pipeline.parse().unwrap();
+    let report = DiffTester::new(|prog| parsed.run(prog))
+        .step_limit(1_000_000)
+        .check(case);
+    if let Some(msg) = &report.pass_panic {
+        panic!("`{pipeline}` panicked on {}: {msg}", case.name);
+    }
+    if let Some(mismatch) = &report.minimal_mismatch {
+        panic!(
+            "`{pipeline}` changes {} with args [{}]\nbaseline: {:?}\noptimized: {:?}",
+            case.name,
+            mismatch.args.join(" "),
+            mismatch.baseline,
+            mismatch.optimized
+        );
+    }
+    report
+}
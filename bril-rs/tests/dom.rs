@@ -0,0 +1,322 @@
+mod common;
+
+use bril_rs::analyzer::dom::DomTree;
+use bril_rs::bril::{LabelOrInst, Prog};
+use bril_rs::cfg::{BasicBlock, Cfg, CfgNode, NodePtr, NodeRef, ProgCfgs};
+use common::fuzz_cases;
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+fn assert_chk_matches_dom_sets(cfg: &Cfg, name: &str) {
+    assert_eq!(
+        DomTree::from_cfg(cfg).immediate_dominators(),
+        DomTree::from_cfg_by_dom_sets(cfg).immediate_dominators(),
+        "dominators of `{name}` differ"
+    );
+}
+
+/// block `i` is labeled `.b{i}`, targets past the last block lead to `.exit`
+fn label(blk: usize, size: usize) -> String {
+    if blk < size {
+        format!("b{blk}")
+    } else {
+        "exit".to_string()
+    }
+}
+
+/// block `i` branches to `targets[i]`
+fn graph(targets: &[Vec<usize>]) -> Cfg {
+    let mut src = "@main(c: bool) {\n".to_string();
+    for (blk, succs) in targets.iter().enumerate() {
+        src += &format!(
+            ".{}:\n  {}\n",
+            label(blk, targets.len()),
+            terminator(succs, targets.len())
+        );
+    }
+    src += ".exit:\n  ret;\n}\n";
+    let prog = Prog::from_text(&src).unwrap();
+    ProgCfgs::from_bril_prog(&prog).0.remove(0)
+}
+
+fn terminator(succs: &[usize], size: usize) -> LabelOrInst {
+    let labels: Vec<String> = succs.iter().map(|&succ| label(succ, size)).collect();
+    branch(labels)
+}
+
+fn branch(labels: Vec<String>) -> LabelOrInst {
+    let (op, args) = match labels.len() {
+        1 => ("jmp", None),
+        _ => ("br", Some(vec!["c".to_string()])),
+    };
+    LabelOrInst::Inst {
+        op: op.to_string(),
+        dest: None,
+        ty: None,
+        args,
+        funcs: None,
+        labels: Some(labels),
+        value: None,
+    }
+}
+
+/// one or two distinct targets per block, loops, irreducible ones and unreachable blocks included
+fn random_graph(rng: &mut StdRng, size: usize) -> Vec<Vec<usize>> {
+    (0..size)
+        .map(|_| {
+            let first = rng.random_range(0..=size);
+            let second = rng.random_range(0..=size);
+            if rng.random_bool(0.3) || first == second {
+                vec![first]
+            } else {
+                vec![first, second]
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn chk_matches_dom_sets_on_in_tree_progs() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let dirs = [
+        root.join("tests/progs"),
+        root.join("../l2/count-path"),
+        root.join("../l2/hanoi"),
+        root.join("../l3/examples"),
+        root.join("../l4/examples"),
+    ];
+    let mut num_progs = 0;
+    for dir in dirs {
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "bril") {
+                continue;
+            }
+            let prog = Prog::from_text(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            for cfg in &ProgCfgs::from_bril_prog(&prog).0 {
+                assert_chk_matches_dom_sets(cfg, &path.display().to_string());
+            }
+            num_progs += 1;
+        }
+    }
+    assert!(num_progs > 10);
+}
+
+#[test]
+fn chk_matches_dom_sets_on_fuzz_corpus() {
+    for case in fuzz_cases(100) {
+        for cfg in &ProgCfgs::from_bril_prog(&case.prog).0 {
+            assert_chk_matches_dom_sets(cfg, &case.name);
+        }
+    }
+}
+
+#[test]
+fn chk_matches_dom_sets_on_synthetic_cfgs() {
+    // if-else diamonds in a row
+    let diamonds: Vec<Vec<usize>> = (0..60)
+        .map(|blk| match blk % 3 {
+            0 => vec![blk + 1, blk + 2],
+            _ => vec![blk + 1],
+        })
+        .collect();
+    assert_chk_matches_dom_sets(&graph(&diamonds), "diamonds");
+    // loops nested ten deep, a header enters the inner loop or leaves to the enclosing latch
+    let nested_loops: Vec<Vec<usize>> = (0..20)
+        .map(|blk| match blk {
+            header if header < 10 => vec![header + 1, 20 - header],
+            latch => vec![19 - latch, latch + 1],
+        })
+        .collect();
+    assert_chk_matches_dom_sets(&graph(&nested_loops), "nested loops");
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for seed in 0..200 {
+        let size = rng.random_range(1..40);
+        let targets = random_graph(&mut rng, size);
+        assert_chk_matches_dom_sets(&graph(&targets), &format!("random graph {seed}"));
+    }
+}
+
+fn ptr(cfg: &Cfg, label: &str) -> NodePtr {
+    Arc::as_ptr(&cfg.node_by_label(label).unwrap())
+}
+
+/// rewrites the terminator of block `from`, then relinks the cfg
+fn retarget(cfg: &mut Cfg, from: &str, labels: Vec<String>) {
+    let node = cfg.node_by_label(from).unwrap();
+    *node.lock().unwrap().blk.instrs.last_mut().unwrap() = branch(labels);
+    cfg.relink();
+}
+
+/// appends a block jumping to `to`, preds are up to the caller
+fn push_block(cfg: &mut Cfg, label: &str, to: &str) -> NodeRef {
+    let blk = BasicBlock {
+        label: Some(label.to_string()),
+        instrs: vec![
+            LabelOrInst::Label {
+                label: label.to_string(),
+            },
+            branch(vec![to.to_string()]),
+        ],
+    };
+    let node = Arc::new(Mutex::new(CfgNode {
+        label: Some(label.to_string()),
+        blk,
+        successors: vec![],
+        predecessors: vec![],
+    }));
+    cfg.nodes.push(Arc::clone(&node));
+    node
+}
+
+fn assert_matches_recompute(dom_tree: &DomTree, cfg: &Cfg, edit: &str) {
+    assert_eq!(
+        dom_tree.immediate_dominators(),
+        DomTree::from_cfg(cfg).immediate_dominators(),
+        "dom tree diverges from recompute after {edit}"
+    );
+}
+
+#[test]
+fn edge_edits_match_recompute() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..50 {
+        let size = rng.random_range(1..25);
+        let mut targets = random_graph(&mut rng, size);
+        let mut cfg = graph(&targets);
+        let mut dom_tree = DomTree::from_cfg(&cfg);
+        for _ in 0..40 {
+            let blk = rng.random_range(0..size);
+            let (from, to, inserted) = if targets[blk].len() == 2 {
+                let removed = targets[blk].remove(rng.random_range(0..2));
+                (label(blk, size), label(removed, size), false)
+            } else {
+                let added = rng.random_range(0..=size);
+                if targets[blk].contains(&added) {
+                    continue;
+                }
+                targets[blk].push(added);
+                (label(blk, size), label(added, size), true)
+            };
+            let labels = targets[blk].iter().map(|&succ| label(succ, size)).collect();
+            retarget(&mut cfg, &from, labels);
+            let (from_ptr, to_ptr) = (ptr(&cfg, &from), ptr(&cfg, &to));
+            let edit = if inserted {
+                dom_tree.insert_edge(&cfg, from_ptr, to_ptr);
+                format!("inserting {from} -> {to} into {targets:?}")
+            } else {
+                dom_tree.delete_edge(&cfg, from_ptr, to_ptr);
+                format!("deleting {from} -> {to} from {targets:?}")
+            };
+            assert_matches_recompute(&dom_tree, &cfg, &edit);
+        }
+    }
+}
+
+#[test]
+fn deletes_cutting_off_blocks_match_recompute() {
+    // b1 and b2 form a loop only entered from b0, b3 follows the loop
+    let mut cfg = graph(&[vec![1, 4], vec![2], vec![1, 3], vec![4]]);
+    let mut dom_tree = DomTree::from_cfg(&cfg);
+    let b = |cfg: &Cfg, blk: usize| ptr(cfg, &label(blk, 4));
+
+    retarget(&mut cfg, "b0", vec!["exit".to_string()]);
+    dom_tree.delete_edge(&cfg, b(&cfg, 0), b(&cfg, 1));
+    assert_matches_recompute(&dom_tree, &cfg, "cutting off the loop");
+    for blk in 1..4 {
+        assert!(dom_tree.idom(b(&cfg, blk)).is_none());
+        assert!(!dom_tree.is_dominator_of(b(&cfg, 0), b(&cfg, blk)));
+    }
+
+    // an edge out of a block left out of the tree is never taken
+    retarget(&mut cfg, "b3", vec!["b1".to_string(), "exit".to_string()]);
+    dom_tree.insert_edge(&cfg, b(&cfg, 3), b(&cfg, 1));
+    assert_matches_recompute(
+        &dom_tree,
+        &cfg,
+        "inserting an edge between unreachable blocks",
+    );
+    assert!(dom_tree.idom(b(&cfg, 1)).is_none());
+
+    // entering through b2 makes the whole loop reachable again, b2 now dominates b1
+    retarget(&mut cfg, "b0", vec!["b2".to_string(), "exit".to_string()]);
+    dom_tree.insert_edge(&cfg, b(&cfg, 0), b(&cfg, 2));
+    assert_matches_recompute(&dom_tree, &cfg, "entering the loop again");
+    assert_eq!(
+        dom_tree.idom(b(&cfg, 1)).map(|idom| Arc::as_ptr(&idom)),
+        Some(b(&cfg, 2))
+    );
+
+    // leaving the loop no more cuts off b3, b1 is still entered through the back edge
+    retarget(&mut cfg, "b2", vec!["b1".to_string()]);
+    dom_tree.delete_edge(&cfg, b(&cfg, 2), b(&cfg, 3));
+    assert_matches_recompute(&dom_tree, &cfg, "deleting the loop exit");
+    assert!(dom_tree.idom(b(&cfg, 3)).is_none());
+    assert!(dom_tree.is_dominator_of(b(&cfg, 2), b(&cfg, 1)));
+}
+
+#[test]
+fn block_insertions_match_recompute() {
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..50 {
+        let size = rng.random_range(1..25);
+        let targets = random_graph(&mut rng, size);
+        let mut cfg = graph(&targets);
+        let mut dom_tree = DomTree::from_cfg(&cfg);
+        for k in 0..20 {
+            let new = format!("n{k}");
+            let from = cfg.nodes[rng.random_range(0..cfg.nodes.len())]
+                .lock()
+                .unwrap()
+                .label
+                .clone()
+                .unwrap();
+            let mut labels = match cfg
+                .node_by_label(&from)
+                .unwrap()
+                .lock()
+                .unwrap()
+                .blk
+                .instrs
+                .last()
+            {
+                Some(LabelOrInst::Inst {
+                    labels: Some(labels),
+                    ..
+                }) => labels.clone(),
+                _ => continue,
+            };
+            let to = label(rng.random_range(0..=size), size);
+            let edit = match rng.random_range(0..3) {
+                0 => {
+                    // split an edge of `from`, like a preheader does
+                    let split = rng.random_range(0..labels.len());
+                    let node = push_block(&mut cfg, &new, &labels[split]);
+                    let edit = format!("splitting {from} -> {} by {new}", labels[split]);
+                    labels[split] = new;
+                    retarget(&mut cfg, &from, labels);
+                    dom_tree.insert_block(&cfg, &node);
+                    edit
+                }
+                1 if labels.len() == 1 => {
+                    let node = push_block(&mut cfg, &new, &to);
+                    labels.push(new.clone());
+                    retarget(&mut cfg, &from, labels);
+                    dom_tree.insert_block(&cfg, &node);
+                    format!("adding {from} -> {new} -> {to}")
+                }
+                _ => {
+                    let node = push_block(&mut cfg, &new, &to);
+                    cfg.relink();
+                    dom_tree.insert_block(&cfg, &node);
+                    format!("adding unreachable {new} -> {to}")
+                }
+            };
+            assert_matches_recompute(&dom_tree, &cfg, &edit);
+        }
+    }
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use bril_rs::bril::Prog;
+use bril_rs::interp::{run_prog, InterpError};
+use common::load;
+
+fn run(prog: &Prog, args: &[&str]) -> (String, Result<(), InterpError>) {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    let mut out = vec![];
+    let ret = run_prog(prog, &args, &mut out).map(|_| ());
+    (String::from_utf8(out).unwrap(), ret)
+}
+
+#[test]
+fn set_of_undefined_value_leaves_shadow_undefined() {
+    let prog = load("set-undef");
+    assert_eq!(run(&prog, &["true"]), ("1\ntrue\n".to_string(), Ok(())));
+    assert_eq!(run(&prog, &["false"]), ("false\n".to_string(), Ok(())));
+}
+
+#[test]
+fn reading_undefined_value_after_get_fails() {
+    let prog = Prog::from_text(
+        "@main {
+          x.undef: int = undef;
+          set x.1 x.undef;
+          x.1: int = get;
+          print x.1;
+        }",
+    )
+    .unwrap();
+    let undefined = Err(InterpError::UndefinedVar("x.1".to_string()));
+    assert_eq!(run(&prog, &[]), (String::new(), undefined));
+}
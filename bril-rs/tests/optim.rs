@@ -0,0 +1,282 @@
+mod common;
+
+use bril_rs::bril::{LabelOrInst, Prog, ValueLit};
+use bril_rs::optim::fuel;
+use bril_rs::optim::specialize::SPECIALIZE_MAX_CLONES;
+use common::{assert_agrees, case, fuzz_cases, load, optimize};
+
+#[test]
+fn unroll_guard_does_not_wrap() {
+    for name in ["unroll-wrap", "unroll-down"] {
+        for pipeline in ["unroll(2)", "unroll(4)", "unroll(7)"] {
+            assert_agrees(pipeline, &case(name));
+        }
+    }
+}
+
+#[test]
+fn unroll_skips_loops_whose_offset_overflows() {
+    let case = case("unroll-wrap");
+    for pipeline in ["unroll(3000000000)", &format!("unroll({})", usize::MAX)] {
+        assert_agrees(pipeline, &case);
+    }
+}
+
+#[test]
+fn tailrec_turns_self_calls_into_loops() {
+    for name in ["gcd", "fact"] {
+        let case = case(name);
+        for pipeline in [
+            "tailrec",
+            "tailrec,ssa,from-ssa",
+            "tailrec,ssa(pruned),from-ssa",
+        ] {
+            assert_agrees(pipeline, &case);
+        }
+        let optimized = optimize(&case.prog, "tailrec");
+        let recursive = &optimized.functions[1];
+        assert!(
+            !recursive
+                .instrs
+                .iter()
+                .any(|inst| inst.to_string().contains("call")),
+            "self call left in {recursive}"
+        );
+    }
+}
+
+#[test]
+fn adce_never_slows_programs_down() {
+    let cases = ["gcd", "fact", "unroll-wrap", "partial-def"]
+        .map(case)
+        .into_iter()
+        .chain(fuzz_cases(50));
+    for case in cases {
+        let report = assert_agrees("adce", &case);
+        assert!(
+            report.dyn_inst_delta() >= 0,
+            "adce slowed `{}` down by {} dyn insts",
+            report.name,
+            -report.dyn_inst_delta()
+        );
+    }
+}
+
+#[test]
+fn const_folding_keeps_results_out_of_i32_range() {
+    let case = case("fold-overflow");
+    for pipeline in ["dce", "dce(global)", "gvn", "ssa,gvn,from-ssa"] {
+        assert_agrees(pipeline, &case);
+    }
+}
+
+#[test]
+fn unswitch_keeps_preheaders_of_licm() {
+    let case = case("licm-unswitch");
+    assert_agrees("licm,unswitch", &case);
+    let prog = optimize(&case.prog, "licm,unswitch");
+    let mut labels: Vec<String> = prog.functions[0]
+        .instrs
+        .iter()
+        .filter_map(|inst| match inst {
+            LabelOrInst::Label { label } => Some(label.clone()),
+            _ => None,
+        })
+        .collect();
+    let num_labels = labels.len();
+    labels.sort();
+    labels.dedup();
+    assert_eq!(labels.len(), num_labels, "duplicate labels in {labels:?}");
+}
+
+fn num_calls(prog: &Prog) -> usize {
+    prog.functions
+        .iter()
+        .flat_map(|func| &func.instrs)
+        .filter(|inst| matches!(inst, LabelOrInst::Inst { op, .. } if op == "call"))
+        .count()
+}
+
+#[test]
+fn inline_replaces_calls_by_callee_bodies() {
+    for name in ["inline-ret-label", "inline-multi-ret"] {
+        let case = case(name);
+        assert_agrees("inline", &case);
+        assert_eq!(num_calls(&optimize(&case.prog, "inline")), 0, "{name}");
+    }
+}
+
+#[test]
+fn dead_calls_that_trap_or_hang_are_kept() {
+    for name in ["dead-call-traps", "dead-call-spins"] {
+        let case = case(name);
+        for pipeline in ["dce", "dce(global)", "gvn"] {
+            assert_agrees(pipeline, &case);
+        }
+    }
+}
+
+#[test]
+fn gvn_speeds_up_fuzz_corpus() {
+    let delta: i64 = fuzz_cases(50)
+        .iter()
+        .map(|case| assert_agrees("gvn", case).dyn_inst_delta())
+        .sum();
+    assert!(
+        delta > 0,
+        "gvn slowed the fuzz corpus down by {} dyn insts",
+        -delta
+    );
+}
+
+#[test]
+fn global_const_prop_forgets_redefined_consts() {
+    assert_agrees("dce(global)", &case("const-redefined"));
+}
+
+#[test]
+fn licm_keeps_back_edges_into_the_header() {
+    for name in ["licm-unswitch", "gcd", "unroll-wrap"] {
+        assert_agrees("licm,dce(global)", &case(name));
+    }
+    for case in fuzz_cases(20) {
+        assert_agrees("licm,dce(global)", &case);
+    }
+}
+
+#[test]
+fn specialize_spends_no_fuel_on_calls_past_the_clone_limit() {
+    let prog = load("specialize-consts");
+    let (specialized, tally) = fuel::with_budget(u64::MAX, || optimize(&prog, "specialize"));
+    // four clones for k = 1, 2, 3, 4, the second call passing 2 shares its clone
+    assert_eq!(specialized.functions.len(), 1 + 1 + SPECIALIZE_MAX_CLONES);
+    assert_eq!(tally.requested, 5);
+}
+
+/// labels of every jmp and br of `func`, in program order
+fn branch_targets(prog: &Prog, func: &str) -> Vec<Vec<String>> {
+    prog.functions
+        .iter()
+        .find(|f| f.name == func)
+        .unwrap()
+        .instrs
+        .iter()
+        .filter_map(|inst| match inst {
+            LabelOrInst::Inst {
+                labels: Some(labels),
+                ..
+            } => Some(labels.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn rotate_turns_loops_bottom_tested() {
+    let case = case("rotate-count");
+    let report = assert_agrees("rotate", &case);
+    assert!(report.dyn_inst_delta() > 0, "no jump saved");
+    // the guard and the copy at the bottom test the exit, nothing jumps back to the header
+    let targets = branch_targets(&optimize(&case.prog, "rotate"), "main");
+    let exit_test = vec!["body".to_string(), "done".to_string()];
+    assert_eq!(targets, vec![exit_test.clone(), exit_test]);
+    for case in fuzz_cases(20) {
+        assert_agrees("rotate", &case);
+    }
+}
+
+#[test]
+fn prune_drops_dead_functions_args_and_return_values() {
+    let case = case("prune-dead");
+    assert_agrees("prune", &case);
+    let prog = optimize(&case.prog, "prune");
+    let names: Vec<&str> = prog.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["main", "scale", "log"]);
+    let scale = &prog.functions[1];
+    let params: Vec<&str> = scale
+        .args
+        .iter()
+        .flatten()
+        .map(|arg| arg.name.as_str())
+        .collect();
+    assert_eq!(params, ["x"]);
+    assert_eq!(prog.functions[2].ty, None);
+    for case in fuzz_cases(20) {
+        assert_agrees("prune", &case);
+    }
+}
+
+#[test]
+fn specialize_clones_callees_for_const_args() {
+    let case = case("specialize-consts");
+    assert_agrees("specialize", &case);
+    let prog = optimize(&case.prog, "specialize");
+    // the clone for k = 2 binds `k` at its entry instead of taking it
+    let clone = prog
+        .functions
+        .iter()
+        .find(|f| f.name == "scale.spec.1")
+        .unwrap();
+    let params: Vec<&str> = clone
+        .args
+        .iter()
+        .flatten()
+        .map(|arg| arg.name.as_str())
+        .collect();
+    assert_eq!(params, ["x"]);
+    assert!(matches!(
+        &clone.instrs[0],
+        LabelOrInst::Inst { op, dest: Some(dest), value: Some(ValueLit::Int(2)), .. }
+            if op == "const" && dest == "k"
+    ));
+    let callees: Vec<&str> = prog.functions[0]
+        .instrs
+        .iter()
+        .filter_map(|inst| match inst {
+            LabelOrInst::Inst {
+                funcs: Some(funcs), ..
+            } => Some(funcs[0].as_str()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        callees,
+        [
+            "scale.spec.0",
+            "scale.spec.1",
+            "scale.spec.1",
+            "scale.spec.2",
+            "scale.spec.3",
+            "scale",
+            "scale"
+        ]
+    );
+    for case in fuzz_cases(20) {
+        assert_agrees("specialize", &case);
+    }
+}
+
+#[test]
+fn unswitch_hoists_invariant_branches_out_of_loops() {
+    let case = case("unswitch-flag");
+    let report = assert_agrees("unswitch", &case);
+    assert!(report.dyn_inst_delta() > 0, "no branch saved");
+    let prog = optimize(&case.prog, "unswitch");
+    let ops: Vec<String> = prog.functions[0]
+        .instrs
+        .iter()
+        .filter_map(|inst| match inst {
+            LabelOrInst::Inst { op, args, .. } => Some(format!(
+                "{op} {}",
+                args.iter().flatten().cloned().collect::<Vec<_>>().join(" ")
+            )),
+            _ => None,
+        })
+        .filter(|inst| inst == "br c" || inst.starts_with("ge "))
+        .collect();
+    // one copy of the loop for each direction of `c`, selected before entering either
+    assert_eq!(ops, ["br c", "ge i n", "ge i n"]);
+    for case in fuzz_cases(20) {
+        assert_agrees("unswitch", &case);
+    }
+}
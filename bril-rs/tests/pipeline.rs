@@ -0,0 +1,65 @@
+use bril_rs::pipeline::{Pass, Pipeline};
+use bril_rs::transform::cytron::PhiPlacement;
+
+fn parse(s: &str) -> Result<Vec<Pass>, String> {
+    s.parse::<Pipeline>().map(|pipeline| pipeline.0)
+}
+
+#[test]
+fn parses_passes_and_options() {
+    assert_eq!(
+        parse("ssa(pruned), licm ,from-ssa,dce(global)").unwrap(),
+        [
+            Pass::Ssa(Some(PhiPlacement::Pruned)),
+            Pass::Licm,
+            Pass::FromSsa,
+            Pass::Dce { global: true },
+        ]
+    );
+    assert_eq!(
+        parse("unroll,unroll(8)").unwrap(),
+        [Pass::Unroll(4), Pass::Unroll(8)]
+    );
+    assert_eq!(parse("").unwrap(), []);
+    assert_eq!(parse(" , gvn,").unwrap(), [Pass::Gvn]);
+}
+
+#[test]
+fn rejects_unknown_passes_and_options() {
+    for s in [
+        "gvn,licn",
+        "dce(local)",
+        "licm(2)",
+        "ssa(maximal)",
+        "unroll(0)",
+        "unroll(-1)",
+        "unroll(x)",
+    ] {
+        assert!(parse(s).is_err(), "`{s}` parsed");
+    }
+}
+
+#[test]
+fn rejects_unbalanced_parens() {
+    for s in [
+        "ssa,licm(,gvn",
+        "licm(",
+        "unroll(4",
+        "licm)",
+        "gvn,dce(global))",
+        "dce)(global",
+    ] {
+        assert!(parse(s).is_err(), "`{s}` parsed");
+    }
+}
+
+#[test]
+fn display_round_trips() {
+    for s in [
+        "dce,dce(global),adce,gvn,licm",
+        "ssa,ssa(minimal),ssa(semi-pruned),ssa(pruned),phi,from-ssa",
+        "unroll(4),unroll(16),unswitch,rotate,tailrec,inline,prune,specialize",
+    ] {
+        assert_eq!(s.parse::<Pipeline>().unwrap().to_string(), s);
+    }
+}
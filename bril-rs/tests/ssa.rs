@@ -0,0 +1,67 @@
+mod common;
+
+use common::{assert_agrees, case, fuzz_cases, optimize};
+
+const PLACEMENTS: [&str; 3] = ["ssa(minimal)", "ssa(semi-pruned)", "ssa(pruned)"];
+
+fn num_gets(pipeline: &str, name: &str) -> usize {
+    optimize(&case(name).prog, pipeline)
+        .functions
+        .iter()
+        .flat_map(|func| &func.instrs)
+        .filter(|inst| inst.to_string().contains(" = get;"))
+        .count()
+}
+
+#[test]
+fn cytron_placements_keep_behavior() {
+    for placement in PLACEMENTS {
+        for name in ["partial-def", "gcd", "fact", "unroll-wrap"] {
+            assert_agrees(placement, &case(name));
+        }
+    }
+}
+
+#[test]
+fn cytron_placements_keep_behavior_on_fuzz_corpus() {
+    for case in fuzz_cases(25) {
+        for placement in PLACEMENTS {
+            assert_agrees(placement, &case);
+        }
+    }
+}
+
+#[test]
+fn pruning_places_fewer_phis() {
+    let [minimal, semi_pruned, pruned] =
+        PLACEMENTS.map(|placement| num_gets(placement, "partial-def"));
+    assert!(minimal > semi_pruned, "{minimal} vs {semi_pruned}");
+    assert!(semi_pruned > pruned, "{semi_pruned} vs {pruned}");
+}
+
+#[test]
+fn phi_encoding_keeps_behavior() {
+    for placement in PLACEMENTS {
+        for name in ["partial-def", "gcd", "fact"] {
+            assert_agrees(&format!("{placement},phi"), &case(name));
+            assert_agrees(&format!("{placement},phi,from-ssa"), &case(name));
+        }
+    }
+}
+
+#[test]
+fn out_of_ssa_copies_undefined_values() {
+    assert_agrees("from-ssa", &case("undef-copy-ssa"));
+    for placement in PLACEMENTS {
+        assert_agrees(&format!("{placement},from-ssa"), &case("undef-copy"));
+    }
+}
+
+#[test]
+fn round_trip_keeps_behavior_on_fuzz_corpus() {
+    for case in fuzz_cases(25) {
+        for placement in PLACEMENTS {
+            assert_agrees(&format!("{placement},from-ssa"), &case);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use bril_rs::analyzer::verify::{verify_ssa, InstLoc, SsaViolation};
+use bril_rs::bril::Prog;
+use bril_rs::cfg::ProgCfgs;
+use common::{case, fuzz_cases, optimize};
+
+fn violations(prog: &Prog) -> Vec<SsaViolation> {
+    ProgCfgs::from_bril_prog(prog)
+        .0
+        .iter()
+        .filter_map(|cfg| verify_ssa(cfg).err())
+        .flatten()
+        .collect()
+}
+
+fn violations_of(src: &str) -> Vec<SsaViolation> {
+    violations(&Prog::from_text(src).unwrap())
+}
+
+fn at(blk: &str, idx: usize) -> InstLoc {
+    InstLoc {
+        blk: blk.to_string(),
+        idx,
+    }
+}
+
+#[test]
+fn accepts_output_of_every_construction() {
+    let mut corpus: Vec<_> = ["partial-def", "gcd", "fact", "undef-copy", "unroll-wrap"]
+        .into_iter()
+        .map(case)
+        .collect();
+    corpus.extend(fuzz_cases(25));
+    for case in &corpus {
+        for pipeline in ["ssa(minimal)", "ssa(semi-pruned)", "ssa(pruned)", "ssa"] {
+            // the dominance-free construction keeps reads of vars undefined on some path as is
+            if pipeline == "ssa" && case.name == "partial-def" {
+                continue;
+            }
+            let found = violations(&optimize(&case.prog, pipeline));
+            assert!(found.is_empty(), "`{pipeline}` on {}: {found:?}", case.name);
+        }
+    }
+    assert_eq!(violations(&case("undef-copy-ssa").prog), vec![]);
+}
+
+#[test]
+fn reports_partially_defined_vars_after_dominance_free_ssa() {
+    let found = violations(&optimize(&case("partial-def").prog, "ssa"));
+    assert!(
+        matches!(found.as_slice(), [SsaViolation::UseNotDominated { at, .. }] if at.blk == ".use"),
+        "{found:?}"
+    );
+}
+
+#[test]
+fn rejects_multiple_defs() {
+    let found = violations_of(
+        "@main(a: int) {
+          x.0: int = const 1;
+          a: int = add x.0 x.0;
+          x.0: int = const 2;
+          print a x.0;
+        }",
+    );
+    assert_eq!(
+        found,
+        vec![
+            SsaViolation::MultipleDefs {
+                var: "a".to_string(),
+                defs: vec![at("params", 0), at("blk.0", 1)],
+            },
+            SsaViolation::MultipleDefs {
+                var: "x.0".to_string(),
+                defs: vec![at("blk.0", 0), at("blk.0", 2)],
+            },
+        ]
+    );
+}
+
+#[test]
+fn rejects_uses_not_dominated_by_their_def() {
+    let found = violations_of(
+        "@main(c: bool) {
+          print y.0;
+          br c .then .join;
+        .then:
+          x.0: int = const 1;
+        .join:
+          print x.0;
+        }",
+    );
+    assert_eq!(
+        found,
+        vec![
+            SsaViolation::UseWithoutDef {
+                var: "y.0".to_string(),
+                at: at("blk.0", 0),
+            },
+            SsaViolation::UseNotDominated {
+                var: "x.0".to_string(),
+                def: at(".then", 1),
+                at: at(".join", 1),
+            },
+        ]
+    );
+}
+
+#[test]
+fn rejects_gets_not_set_on_every_path() {
+    let found = violations_of(
+        "@main(c: bool) {
+          one: int = const 1;
+          set z.0 one;
+          br c .then .join;
+        .then:
+          set x.0 one;
+        .join:
+          x.0: int = get;
+          print x.0;
+        }",
+    );
+    assert_eq!(
+        found,
+        vec![
+            SsaViolation::SetWithoutGet {
+                var: "z.0".to_string(),
+                at: at("blk.0", 1),
+            },
+            SsaViolation::GetWithoutSet {
+                var: "x.0".to_string(),
+                at: at(".join", 1),
+            },
+        ]
+    );
+}
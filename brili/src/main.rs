@@ -0,0 +1,52 @@
+use bril_rs::{bril, interp};
+use clap::Parser;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+/// runs a bril program in json, a drop-in replacement for `brili`
+#[derive(Parser)]
+struct Args {
+    #[arg(short)]
+    f: Option<String>,
+    /// report dynamic instruction count to stderr
+    #[arg(short)]
+    p: bool,
+    /// report allocations of the memory extension per function to stderr
+    #[arg(long)]
+    heap_stats: bool,
+    /// args passed to `main`
+    #[arg(allow_negative_numbers = true)]
+    args: Vec<String>,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let mut reader: Box<dyn Read> = if let Some(ref f) = args.f {
+        Box::new(BufReader::new(std::fs::File::open(f)?))
+    } else {
+        Box::new(BufReader::new(std::io::stdin()))
+    };
+    let mut buf = String::new();
+    assert!(reader.read_to_string(&mut buf)? > 0);
+
+    let bril_prog = bril::Prog::from_json(&buf).unwrap();
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    let ret = interp::run_prog(&bril_prog, &args.args, &mut out);
+    out.flush()?;
+    match ret {
+        Ok(profile) => {
+            if args.p {
+                eprintln!("total_dyn_inst: {}", profile.total_dyn_inst);
+            }
+            if args.heap_stats {
+                for (func_name, stat) in &profile.alloc_per_func {
+                    eprintln!("{func_name}: {stat:#?}");
+                }
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            std::process::exit(2)
+        }
+    }
+}
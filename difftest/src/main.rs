@@ -0,0 +1,206 @@
+use bril_rs::bril;
+use bril_rs::cfg::ProgCfgs;
+use bril_rs::fuzz::{ProgGenerator, OPS};
+use bril_rs::interp::diff::{Bisection, Case, CaseReport, DiffTester, Run};
+use bril_rs::pipeline::Pipeline;
+
+use clap::Parser;
+use std::path::{Path, PathBuf};
+
+/// runs every program of the corpus before and after the pipeline and compares the results,
+/// exits with 1 if any program misbehaves once optimized
+#[derive(Parser)]
+struct Args {
+    /// passes in the order they are applied, e.g. `--pass 'ssa,licm,from-ssa,dce(global)'`,
+    /// see `bril-opt --list-passes`
+    #[arg(long = "pass")]
+    pipeline: Pipeline,
+    /// json programs, directories are searched for `*.json`; arg sets of `prog.json` are read
+    /// from `prog.args`, one set per line, `main` runs without args if there is none
+    #[arg(required_unless_present = "fuzz")]
+    corpus: Vec<PathBuf>,
+    /// dynamic instructions a single run may take
+    #[arg(long, default_value_t = 100_000_000)]
+    step_limit: u64,
+    /// additionally test on this many randomly generated programs
+    #[arg(long)]
+    fuzz: Option<u64>,
+    /// the i-th generated program uses seed `seed + i`, `--fuzz 1 --seed s` reproduces `fuzz-s`
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// relative frequency of ops in generated programs, e.g. `--op-mix mul=0,div=4`
+    #[arg(long, value_delimiter = ',', value_parser = parse_op_weight)]
+    op_mix: Vec<(String, u32)>,
+    /// failing programs are written into this directory together with their `.args`
+    #[arg(long)]
+    dump: Option<PathBuf>,
+    /// binary search the optimization fuel of failing programs for the first bad rewrite
+    #[arg(long)]
+    bisect: bool,
+}
+
+fn parse_op_weight(s: &str) -> Result<(String, u32), String> {
+    let (op, weight) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected op=weight, got {s}"))?;
+    if !OPS.contains(&op) {
+        return Err(format!("{op} is not one of {OPS:?}"));
+    }
+    let weight = weight.parse().map_err(|e| format!("{e}"))?;
+    Ok((op.to_string(), weight))
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let mut corpus = load_corpus(&args.corpus)?;
+    for i in 0..args.fuzz.unwrap_or_default() {
+        corpus.push(generate_case(args.seed + i, &args.op_mix));
+    }
+    let pipeline = args.pipeline;
+    let tester = DiffTester::new(|prog| pipeline.run(prog)).step_limit(args.step_limit);
+
+    let (mut num_passed, mut baseline_dyn_inst, mut optimized_dyn_inst) = (0, 0, 0);
+    for case in &corpus {
+        let report = tester.check(case);
+        print_report(&report);
+        if report.passed() {
+            num_passed += 1;
+        } else {
+            if args.bisect {
+                print_bisection(&tester, case, &report);
+            }
+            if let Some(dir) = &args.dump {
+                dump_case(dir, case)?;
+            }
+        }
+        for execution in &report.executions {
+            if execution.baseline.error.is_none() && execution.optimized.error.is_none() {
+                baseline_dyn_inst += execution.baseline.total_dyn_inst;
+                optimized_dyn_inst += execution.optimized.total_dyn_inst;
+            }
+        }
+    }
+    println!(
+        "{num_passed}/{} programs agree, total_dyn_inst: {baseline_dyn_inst} -> {optimized_dyn_inst}",
+        corpus.len()
+    );
+    if num_passed != corpus.len() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn print_report(report: &CaseReport) {
+    if let Some(msg) = &report.pass_panic {
+        println!("PANIC     {}: {msg}", report.name);
+    } else if let Some(mismatch) = &report.minimal_mismatch {
+        println!(
+            "MISMATCH  {} with args [{}]",
+            report.name,
+            mismatch.args.join(" ")
+        );
+        print_run("baseline", &mismatch.baseline);
+        print_run("optimized", &mismatch.optimized);
+    } else {
+        println!(
+            "ok        {} ({} runs, dyn inst delta {})",
+            report.name,
+            report.executions.len(),
+            report.dyn_inst_delta()
+        );
+    }
+}
+
+/// bisects on the minimal mismatching args only, a crashing pipeline on the program as is
+fn print_bisection<F: Fn(ProgCfgs) -> ProgCfgs>(
+    tester: &DiffTester<F>,
+    case: &Case,
+    report: &CaseReport,
+) {
+    let arg_sets = match &report.minimal_mismatch {
+        Some(mismatch) => vec![mismatch.args.clone()],
+        None => case.arg_sets.clone(),
+    };
+    let case = Case {
+        name: case.name.clone(),
+        prog: case.prog.clone(),
+        arg_sets,
+    };
+    match tester.bisect(&case) {
+        Some(Bisection {
+            num_rewrites,
+            first_bad: Some((idx, rewrite)),
+        }) => println!("  first bad rewrite #{idx} of {num_rewrites}: {rewrite}"),
+        Some(Bisection { num_rewrites, .. }) => {
+            println!("  fails without any rewrite, {num_rewrites} rewrites in total")
+        }
+        None => println!("  does not fail again when bisecting"),
+    }
+}
+
+fn print_run(tag: &str, run: &Run) {
+    let status = run
+        .error
+        .as_ref()
+        .map_or("ok".to_string(), |e| format!("error: {e}"));
+    println!("  {tag}: {status}");
+    for line in run.output.lines() {
+        println!("    {line}");
+    }
+}
+
+fn generate_case(seed: u64, op_mix: &[(String, u32)]) -> Case {
+    let mut generator = op_mix
+        .iter()
+        .fold(ProgGenerator::new(seed), |generator, (op, weight)| {
+            generator.op_weight(op, *weight)
+        });
+    Case {
+        name: format!("fuzz-{seed}"),
+        prog: generator.generate(),
+        arg_sets: generator.arg_sets(3),
+    }
+}
+
+fn dump_case(dir: &Path, case: &Case) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let stem = Path::new(&case.name).file_stem().unwrap_or_default();
+    let file = dir.join(stem).with_extension("json");
+    std::fs::write(&file, serde_json::to_string_pretty(&case.prog)?)?;
+    let arg_sets: Vec<String> = case.arg_sets.iter().map(|args| args.join(" ")).collect();
+    std::fs::write(file.with_extension("args"), arg_sets.join("\n") + "\n")
+}
+
+fn load_corpus(paths: &[PathBuf]) -> std::io::Result<Vec<Case>> {
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let mut in_dir: Vec<_> = std::fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<_, _>>()?;
+            in_dir.retain(|file| file.extension().is_some_and(|ext| ext == "json"));
+            in_dir.sort();
+            files.extend(in_dir);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.iter().map(|file| load_case(file)).collect()
+}
+
+fn load_case(file: &Path) -> std::io::Result<Case> {
+    let prog = bril::Prog::from_json(&std::fs::read_to_string(file)?)?;
+    let arg_sets = match std::fs::read_to_string(file.with_extension("args")) {
+        Ok(content) => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split_whitespace().map(str::to_string).collect())
+            .collect(),
+        Err(_) => vec![vec![]],
+    };
+    Ok(Case {
+        name: file.display().to_string(),
+        prog,
+        arg_sets,
+    })
+}
@@ -1,3 +1,4 @@
+use bril_rs::analyzer::effect::EffectSummary;
 use bril_rs::bril::*;
 use bril_rs::{bril, cfg, optim};
 
@@ -10,6 +11,12 @@ struct Args {
     f: Option<String>,
     #[arg(short = 'g', default_value_t = false)]
     with_global_ctx: bool,
+    /// also run mark-and-sweep dce, which removes useless branches and loops
+    #[arg(long, default_value_t = false)]
+    adce: bool,
+    /// number values across blocks along the dominator tree before the local passes
+    #[arg(long, default_value_t = false)]
+    gvn: bool,
 }
 
 fn main() -> std::io::Result<()> {
@@ -23,16 +30,28 @@ fn main() -> std::io::Result<()> {
     assert!(reader.read_to_string(&mut buf)? > 0);
 
     let bril_prog = bril::Prog::from_json(&buf).unwrap();
-    let prog = apply_cfg_optim(bril_prog, args.with_global_ctx);
+    let prog = apply_cfg_optim(bril_prog, args.with_global_ctx, args.adce, args.gvn);
     println!("{:#}", serde_json::to_string(&prog).unwrap());
     Ok(())
 }
 
-fn apply_cfg_optim(bril_prog: Prog, with_global_ctx: bool) -> Prog {
+fn apply_cfg_optim(bril_prog: Prog, with_global_ctx: bool, adce: bool, gvn: bool) -> Prog {
     let cfgs = cfg::ProgCfgs::from_bril_prog(&bril_prog);
+    let effects = EffectSummary::from_prog(&cfgs);
     let mut functions = vec![];
     for cfg in cfgs.0 {
-        functions.push(optim::dce(cfg, with_global_ctx).into_bril_func());
+        let cfg = if gvn {
+            optim::gvn::effect_aware_gvn(cfg, &effects)
+        } else {
+            cfg
+        };
+        let cfg = if adce {
+            optim::dce::adce::aggressive_dce(cfg)
+        } else {
+            cfg
+        };
+        functions
+            .push(optim::dce::effect_aware_dce(cfg, with_global_ctx, &effects).into_bril_func());
     }
     Prog { functions }
 }
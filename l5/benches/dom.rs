@@ -0,0 +1,133 @@
+//! dominator tree construction on synthetic large cfgs
+//!
+//! `from_cfg` (Cooper, Harvey, Kennedy) is timed against `from_cfg_by_dom_sets`
+//! (dataflow over dominator sets), both must agree on every immediate dominator.
+//!     $ cargo bench -p l5
+use bril_rs::analyzer::dom::DomTree;
+use bril_rs::bril::{Function, LabelOrInst};
+use bril_rs::cfg::Cfg;
+use std::time::{Duration, Instant};
+
+const SIZES: [usize; 3] = [100, 500, 2000];
+const ROUNDS: u32 = 3;
+
+fn main() {
+    println!(
+        "{:<10}{:>8}{:>16}{:>16}",
+        "shape", "blocks", "chk", "dom sets"
+    );
+    for (shape, gen) in [
+        ("diamonds", diamonds as fn(usize) -> Function),
+        ("loops", nested_loops),
+        ("random", random_branches),
+    ] {
+        for size in SIZES {
+            let cfg = Cfg::from_bril_func(&gen(size));
+            let fast = DomTree::from_cfg(&cfg).immediate_dominators();
+            let slow = DomTree::from_cfg_by_dom_sets(&cfg).immediate_dominators();
+            assert_eq!(fast, slow, "{shape} with {size} blocks");
+            let chk = time(|| drop(DomTree::from_cfg(&cfg)));
+            let dom_sets = time(|| drop(DomTree::from_cfg_by_dom_sets(&cfg)));
+            println!("{shape:<10}{size:>8}{chk:>16.2?}{dom_sets:>16.2?}");
+        }
+    }
+}
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        f();
+    }
+    start.elapsed() / ROUNDS
+}
+
+/// if-else diamonds chained one after another
+fn diamonds(size: usize) -> Function {
+    let mut blocks = vec![];
+    for i in 0..size / 3 {
+        let (head, then, join) = (3 * i, 3 * i + 1, 3 * i + 2);
+        blocks.push((head, vec![then, join]));
+        blocks.push((then, vec![join]));
+        blocks.push((join, vec![join + 1]));
+    }
+    into_func(blocks)
+}
+
+/// loops nested ten deep, repeated
+fn nested_loops(size: usize) -> Function {
+    const DEPTH: usize = 10;
+    let mut blocks = vec![];
+    for nest in 0..size / (2 * DEPTH) {
+        let base = nest * 2 * DEPTH;
+        for d in 0..DEPTH {
+            let (header, latch) = (base + d, base + 2 * DEPTH - 1 - d);
+            // header enters the inner loop or leaves to the enclosing latch
+            blocks.push((header, vec![header + 1, latch + 1]));
+        }
+        for d in (0..DEPTH).rev() {
+            let (header, latch) = (base + d, base + 2 * DEPTH - 1 - d);
+            blocks.push((latch, vec![header, latch + 1]));
+        }
+    }
+    blocks.sort_by_key(|(blk, _)| *blk);
+    into_func(blocks)
+}
+
+/// every block branches to its successor and to some pseudo random block
+fn random_branches(size: usize) -> Function {
+    let mut seed: u64 = 0x2545f4914f6cdd1d;
+    let blocks = (0..size)
+        .map(|blk| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (blk, vec![blk + 1, seed as usize % size])
+        })
+        .collect();
+    into_func(blocks)
+}
+
+/// block `i` is labeled `.b{i}`, targets past the last block lead to `.exit`
+fn into_func(blocks: Vec<(usize, Vec<usize>)>) -> Function {
+    let num_blocks = blocks.len();
+    let label = |blk: usize| {
+        if blk < num_blocks {
+            format!("b{blk}")
+        } else {
+            "exit".to_string()
+        }
+    };
+    let inst =
+        |op: &str, dest: Option<&str>, args: Vec<String>, labels: Vec<String>| LabelOrInst::Inst {
+            op: op.to_string(),
+            dest: dest.map(str::to_string),
+            ty: dest.map(|_| "bool".to_string()),
+            args: (!args.is_empty()).then_some(args),
+            funcs: None,
+            labels: (!labels.is_empty()).then_some(labels),
+            value: None,
+        };
+    let mut instrs = vec![];
+    for (blk, targets) in blocks {
+        instrs.push(LabelOrInst::Label { label: label(blk) });
+        let targets: Vec<String> = targets.into_iter().map(label).collect();
+        if targets.len() == 1 {
+            instrs.push(inst("jmp", None, vec![], targets));
+        } else {
+            instrs.push(inst("br", None, vec!["c".to_string()], targets));
+        }
+    }
+    instrs.push(LabelOrInst::Label {
+        label: "exit".to_string(),
+    });
+    instrs.push(inst("ret", None, vec![], vec![]));
+    Function {
+        name: "main".to_string(),
+        args: Some(vec![bril_rs::bril::Arg {
+            name: "c".to_string(),
+            ty: "bool".to_string(),
+        }]),
+        ty: None,
+        instrs,
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(unused_imports)]
 use bril_rs::analyzer::dom::*;
+use bril_rs::analyzer::postdom::*;
 use bril_rs::bril::*;
 use bril_rs::optim::dce::global;
 use bril_rs::{
@@ -7,7 +8,7 @@ use bril_rs::{
     cfg::{self, BasicBlock, Cfg, NodePtr, NodeRef},
     optim,
 };
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::collections::{HashMap, HashSet};
 use std::io::{BufReader, Read};
 use std::sync::{Arc, Weak};
@@ -16,6 +17,16 @@ use std::sync::{Arc, Weak};
 struct Args {
     #[arg(short)]
     f: Option<String>,
+    /// graph drawn alongside the cfg
+    #[arg(long, value_enum, default_value_t = Graph::Dom)]
+    graph: Graph,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Graph {
+    Dom,
+    PostDom,
+    Cdg,
 }
 
 fn main() -> std::io::Result<()> {
@@ -32,8 +43,13 @@ fn main() -> std::io::Result<()> {
     let prog_cfgs = cfg::ProgCfgs::from_bril_prog(&bril_prog);
     for cfg in &prog_cfgs.0 {
         check_dom_tree_impl(cfg);
+        check_against_dom_sets(cfg);
     }
-    let dot_string = draw_prog_with_dom_as_dot_string(&prog_cfgs);
+    let dot_string = match args.graph {
+        Graph::Dom => draw_prog_with_dom_as_dot_string(&prog_cfgs),
+        Graph::PostDom => draw_prog_with_post_dom_as_dot_string(&prog_cfgs),
+        Graph::Cdg => draw_prog_with_cdg_as_dot_string(&prog_cfgs),
+    };
     println!("{}", dot_string);
     Ok(())
 }
@@ -85,6 +101,14 @@ fn check_dom_tree_impl(cfg: &Cfg) {
     assert_eq!(visitor.dom_set_per_cfg, visitor.exact_dom_per_cfg);
 }
 
+/// the fast construction must agree with dominator sets solved as a dataflow problem
+fn check_against_dom_sets(cfg: &Cfg) {
+    assert_eq!(
+        DomTree::from_cfg(cfg).immediate_dominators(),
+        DomTree::from_cfg_by_dom_sets(cfg).immediate_dominators()
+    );
+}
+
 fn collect_dom_set_per_cfg(dom_tree: &DomTree) -> HashMap<NodePtr, HashSet<NodePtr>> {
     fn recurse_on_dom_node(
         node: &DomNodeRef,
@@ -1,4 +1,5 @@
 use bril_rs::bril::*;
+use bril_rs::transform::phi::{self, SsaForm};
 use bril_rs::transform::ssa;
 use bril_rs::{bril, cfg};
 
@@ -23,7 +24,7 @@ fn main() -> std::io::Result<()> {
 
     let bril_prog = bril::Prog::from_json(&buf).unwrap();
     let prog = apply_cfg_optim(bril_prog);
-    
+
     println!("{:#}", serde_json::to_string(&prog).unwrap());
     Ok(())
 }
@@ -32,6 +33,11 @@ fn apply_cfg_optim(bril_prog: Prog) -> Prog {
     let cfgs = cfg::ProgCfgs::from_bril_prog(&bril_prog);
     let mut functions = vec![];
     for cfg in cfgs.0.into_iter() {
+        let cfg = if phi::ssa_form_of(&cfg) == Some(SsaForm::Phi) {
+            phi::phi_into_set_get(cfg)
+        } else {
+            cfg
+        };
         let cfg = ssa::cfg_from_ssa(cfg);
         functions.push(cfg.into_bril_func());
     }
@@ -1,14 +1,58 @@
+use bril_rs::analyzer::verify::verify_ssa;
 use bril_rs::bril::*;
+use bril_rs::transform::cytron::{self, PhiPlacement};
+use bril_rs::transform::phi::{self, SsaForm};
 use bril_rs::transform::ssa;
 use bril_rs::{bril, cfg};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use std::io::{BufReader, Read};
 
 #[derive(Parser)]
 struct Args {
     #[arg(short)]
     f: Option<String>,
+    /// use phi placement at iterated dominance frontier instead of the dominance-free algo
+    #[arg(long, value_enum)]
+    cytron: Option<Placement>,
+    /// check that the output is in ssa form, violations are reported to stderr
+    #[arg(long, default_value_t = false)]
+    verify: bool,
+    /// encoding of the output ssa
+    #[arg(long, value_enum, default_value_t = Format::SetGet)]
+    format: Format,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    SetGet,
+    Phi,
+}
+
+impl From<Format> for SsaForm {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::SetGet => SsaForm::SetGet,
+            Format::Phi => SsaForm::Phi,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Placement {
+    Minimal,
+    SemiPruned,
+    Pruned,
+}
+
+impl From<Placement> for PhiPlacement {
+    fn from(placement: Placement) -> Self {
+        match placement {
+            Placement::Minimal => PhiPlacement::Minimal,
+            Placement::SemiPruned => PhiPlacement::SemiPruned,
+            Placement::Pruned => PhiPlacement::Pruned,
+        }
+    }
 }
 
 fn main() -> std::io::Result<()> {
@@ -22,18 +66,37 @@ fn main() -> std::io::Result<()> {
     assert!(reader.read_to_string(&mut buf)? > 0);
 
     let bril_prog = bril::Prog::from_json(&buf).unwrap();
-    let prog = apply_cfg_optim(bril_prog);
-    
+    let prog = apply_cfg_optim(
+        bril_prog,
+        args.cytron.map(PhiPlacement::from),
+        args.verify,
+        args.format.into(),
+    );
+
     println!("{:#}", serde_json::to_string(&prog).unwrap());
     Ok(())
 }
 
-fn apply_cfg_optim(bril_prog: Prog) -> Prog {
+fn apply_cfg_optim(
+    bril_prog: Prog,
+    cytron: Option<PhiPlacement>,
+    verify: bool,
+    form: SsaForm,
+) -> Prog {
     let cfgs = cfg::ProgCfgs::from_bril_prog(&bril_prog);
     let mut functions = vec![];
     for cfg in cfgs.0.into_iter() {
-        let cfg = ssa::cfg_into_ssa(cfg);
-        functions.push(cfg.into_bril_func());
+        let cfg = if let Some(placement) = cytron {
+            cytron::cfg_into_ssa_cytron(cfg, placement)
+        } else {
+            ssa::cfg_into_ssa(cfg)
+        };
+        if verify && let Err(violations) = verify_ssa(&cfg) {
+            for violation in violations {
+                eprintln!("@{}: {violation}", cfg.func_ctx.name);
+            }
+        }
+        functions.push(phi::cfg_into_form(cfg, form).into_bril_func());
     }
     Prog { functions }
 }
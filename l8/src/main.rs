@@ -30,7 +30,7 @@ fn main() -> std::io::Result<()> {
     assert!(reader.read_to_string(&mut buf)? > 0);
 
     let bril_prog = bril::Prog::from_json(&buf).unwrap();
-    let mut prog_cfgs = cfg::ProgCfgs::from_bril_prog(&bril_prog);
+    let prog_cfgs = cfg::ProgCfgs::from_bril_prog(&bril_prog);
 
     let mut optim_cfgs = vec![];
     for cfg in prog_cfgs.0 {
@@ -43,4 +43,3 @@ fn main() -> std::io::Result<()> {
 
     Ok(())
 }
-